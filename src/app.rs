@@ -16,24 +16,94 @@
  * You should have received a copy of the GNU General Public License along with
  * this program.  If not, see <http://www.gnu.org/licenses/>.
  */
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::convert::From;
 use std::fmt;
 use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Mutex, Once};
+use std::time::{Duration, SystemTime};
 
-use crate::appui::{AppUi, BranchToDeleteInfo};
-use crate::batchappui::BatchAppUi;
-use crate::cliargs::CliArgs;
-use crate::git::{BranchRestorer, GitError, Repository};
+use crate::appui::{AppUi, BranchToDeleteInfo, Verbosity};
+use crate::batchappui::{self, BatchAppUi};
+use crate::cliargs::{CliArgs, EventFormat, OutputFormat, SortOrder, UpdateStrategy};
+use crate::configfile::ConfigFile;
+use crate::git::{
+    BranchRestorer, GitError, GitRepository, MergeFilter, Repository, StashGuard, MIN_GIT_VERSION,
+};
+use crate::glob;
+use crate::identical::IdenticalGroupPlan;
 use crate::interactiveappui::InteractiveAppUi;
+use crate::jsonappui::JsonAppUi;
+use crate::logfile::LogFile;
+use crate::ndjsonappui::NdjsonAppUi;
+use crate::report::{
+    DeletableBranch, ProtectedBranch, ProtectionSource, Report, RewrittenMerge, RunReport,
+    SkippedBranch,
+};
+use crate::tui;
 
 pub static DEFAULT_BRANCH_CONFIG_KEY: &str = "git-bonsai.default-branch";
+pub static PROTECTED_PATTERN_CONFIG_KEY: &str = "git-bonsai.protected-pattern";
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+const DEFAULT_REMOTE: &str = "origin";
+
+/// Process exit codes returned by run(). EXIT_DIRTY_TREE is also used for errors which do not
+/// have a specific code.
+pub const EXIT_DIRTY_TREE: i32 = 1;
+pub const EXIT_GIT_FAILED: i32 = 2;
+pub const EXIT_INTERRUPTED: i32 = 3;
+pub const EXIT_UNSAFE_DELETE: i32 = 4;
+/// Returned when git-bonsai is interrupted with Ctrl-C, like shells do for SIGINT
+pub const EXIT_SIGINT: i32 = 130;
+
+/// What to restore if git-bonsai gets interrupted
+pub struct RunRestorePoint {
+    pub path: PathBuf,
+    /// The branch to check out, or the sha1 if HEAD was detached
+    pub rev: String,
+    /// Set if the changes of the working tree have been stashed by --autostash
+    pub stashed: bool,
+}
+
+pub type InterruptRestorePoint = Mutex<Option<RunRestorePoint>>;
+
+static INTERRUPT_RESTORE_POINT: InterruptRestorePoint = Mutex::new(None);
+static INSTALL_INTERRUPT_HANDLER: Once = Once::new();
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum AppError {
     Git(GitError),
     UnsafeDelete,
     InterruptedByUser,
+    NoDeletionTarget,
+    NoSuchBranch(String),
+    /// --current-only has been used while HEAD is detached
+    NoCurrentBranch,
+}
+
+impl AppError {
+    /// Returns the process exit code matching this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AppError::Git(_) => EXIT_GIT_FAILED,
+            AppError::InterruptedByUser => EXIT_INTERRUPTED,
+            AppError::UnsafeDelete => EXIT_UNSAFE_DELETE,
+            AppError::NoDeletionTarget | AppError::NoSuchBranch(_) | AppError::NoCurrentBranch => {
+                EXIT_DIRTY_TREE
+            }
+        }
+    }
+}
+
+/// The error which stopped App::run(), with what had been done before it happened
+#[derive(Debug, PartialEq, Eq)]
+pub struct RunError {
+    pub error: AppError,
+    pub report: Box<RunReport>,
 }
 
 impl From<GitError> for AppError {
@@ -52,71 +122,420 @@ impl fmt::Display for AppError {
             AppError::InterruptedByUser => {
                 write!(f, "Interrupted")
             }
+            AppError::NoDeletionTarget => {
+                write!(
+                    f,
+                    "No default branch, use --merged-into to select the branches to delete"
+                )
+            }
+            AppError::NoSuchBranch(branch) => {
+                write!(f, "Branch {} does not exist", branch)
+            }
+            AppError::NoCurrentBranch => {
+                write!(
+                    f,
+                    "HEAD is detached, --current-only requires a current branch"
+                )
+            }
         }
     }
 }
 
 pub struct App {
-    repo: Repository,
-    protected_branches: HashSet<String>,
+    repo: Box<dyn GitRepository>,
+    protected_branches: HashMap<String, ProtectionSource>,
+    protected_patterns: Vec<(String, ProtectionSource)>,
+    include_patterns: Vec<String>,
     ui: Box<dyn AppUi>,
+    remote: String,
     fetch: bool,
+    fetch_all: bool,
+    fetch_args: Vec<String>,
+    update: bool,
+    update_strategy: UpdateStrategy,
+    check_default_branch: bool,
+    merged_into: Option<String>,
+    current_only: bool,
+    protect_current: bool,
+    keep_merged_into: Option<String>,
+    protect_tagged: bool,
+    keep_tracked: bool,
+    prune_empty: bool,
+    max_age: Option<u64>,
+    before_ref: Option<String>,
+    merged_before: Option<u64>,
+    detect_squashed: bool,
+    detect_rebased: bool,
+    maintain: bool,
+    report_only: bool,
+    list: bool,
+    show_protected: bool,
+    dry_run: bool,
+    confirm_each: bool,
+    final_confirm: bool,
+    fail_fast: bool,
+    archive_prefix: Option<String>,
+    delete_remote: bool,
+    on_delete: Option<String>,
+    explain: bool,
+    auto_keep_one: bool,
+    single_prompt: bool,
+    allow_untracked: bool,
+    show_stat: bool,
+    sort: SortOrder,
+    reset_default_branch: bool,
+    assume_default: Option<String>,
+    // The default branch found by add_default_branch_to_protected_branches(). It is not always
+    // stored in git config.
+    found_default_branch: Option<String>,
+    park_branch: Option<String>,
+    keep_if_open_worktree: bool,
+    restore: bool,
+    log_file: Option<LogFile>,
+    // Set once the backup refs of the previous run have been removed
+    backup_refs_reset: Cell<bool>,
+    // The branches which would have been deleted, with --dry-run
+    planned_deletions: RefCell<Vec<String>>,
+    // The branches checked out in a worktree, and the ones checked out in a worktree other than
+    // the current one, listed once per run by list_worktree_branches()
+    worktree_branches: RefCell<Option<(HashSet<String>, HashSet<String>)>>,
+    run_report: RefCell<RunReport>,
 }
 
 impl App {
-    pub fn new(args: &CliArgs, ui: Box<dyn AppUi>, repo_dir: &str) -> App {
-        let repo = Repository::new(&PathBuf::from(repo_dir));
+    /// Creates the app. Fails if the git config of the repository cannot be read.
+    pub fn new(
+        args: &CliArgs,
+        config: &ConfigFile,
+        ui: Box<dyn AppUi>,
+        repo_dir: &str,
+    ) -> Result<App, GitError> {
+        let mut repo = Repository::new(&PathBuf::from(repo_dir));
+        repo.set_timeout(args.timeout.map(Duration::from_secs));
+        repo.set_debug(args.verbose);
+        App::with_repository(args, config, ui, Box::new(repo))
+    }
 
-        let mut branches: HashSet<String> = HashSet::new();
-        for branch in repo
-            .get_config_keys("git-bonsai.protected-branches")
-            .unwrap()
-        {
-            branches.insert(branch.to_string());
+    /// Like new(), but works on an already created repository, which can be a test
+    /// implementation of GitRepository. The timeout and debug settings of `repo` are left as is.
+    pub fn with_repository(
+        args: &CliArgs,
+        config: &ConfigFile,
+        ui: Box<dyn AppUi>,
+        repo: Box<dyn GitRepository>,
+    ) -> Result<App, GitError> {
+        let read_config_keys = |key: &str| {
+            repo.get_config_keys(key).map_err(|x| {
+                ui.log_error(&format!("Failed to read git config: {}", x));
+                x
+            })
+        };
+        let mut branches: HashMap<String, ProtectionSource> = HashMap::new();
+        // The global git config cannot be read without $HOME, for example when running from cron
+        match repo.get_global_config_keys("git-bonsai.protected-branches") {
+            Ok(keys) => {
+                for branch in keys {
+                    branches.insert(branch.to_string(), ProtectionSource::GlobalGitConfig);
+                }
+            }
+            Err(x) => ui.log_warning(&format!("Failed to read global git config: {}", x)),
+        }
+        // Also returns the global values, which are already in `branches`
+        for branch in read_config_keys("git-bonsai.protected-branches")? {
+            branches
+                .entry(branch.to_string())
+                .or_insert(ProtectionSource::GitConfig);
+        }
+        for branch in &config.protected {
+            branches
+                .entry(branch.to_string())
+                .or_insert(ProtectionSource::ConfigFile);
         }
         for branch in &args.excluded {
-            branches.insert(branch.to_string());
+            branches
+                .entry(branch.to_string())
+                .or_insert(ProtectionSource::CommandLine);
+        }
+        let mut patterns: Vec<(String, ProtectionSource)> = Vec::new();
+        for pattern in read_config_keys(PROTECTED_PATTERN_CONFIG_KEY)? {
+            patterns.push((pattern, ProtectionSource::GitConfig));
+        }
+        for pattern in &args.exclude_pattern {
+            patterns.push((pattern.to_string(), ProtectionSource::CommandLine));
+        }
+        if let Some(prefix) = &args.archive_prefix {
+            // Do not delete the branches archived during this run
+            patterns.push((format!("{}*", prefix), ProtectionSource::CommandLine));
         }
-        App {
+        // With --current-only, the current branch is the --merged-into target. It is set by
+        // run_steps().
+        let merged_into = if args.current_only {
+            None
+        } else {
+            args.merged_into.clone()
+        };
+        if let Some(branch) = &merged_into {
+            branches
+                .entry(branch.to_string())
+                .or_insert(ProtectionSource::MergedInto);
+        }
+        if let Some(branch) = &args.park_branch {
+            branches
+                .entry(branch.to_string())
+                .or_insert(ProtectionSource::CommandLine);
+        }
+        // A log file which cannot be opened must not prevent cleaning the repository
+        let log_file = args
+            .log_file
+            .as_ref()
+            .and_then(|path| match LogFile::open(path) {
+                Ok(x) => Some(x),
+                Err(x) => {
+                    ui.log_warning(&format!("Cannot open log file {}: {}", path.display(), x));
+                    None
+                }
+            });
+        Ok(App {
             repo,
             protected_branches: branches,
+            protected_patterns: patterns,
+            include_patterns: args.include.clone(),
             ui,
-            fetch: !args.no_fetch,
-        }
+            remote: args
+                .remote
+                .clone()
+                .or_else(|| config.remote.clone())
+                .unwrap_or_else(|| DEFAULT_REMOTE.to_string()),
+            fetch: !(args.no_fetch || config.no_fetch.unwrap_or(false)),
+            fetch_all: args.fetch_all,
+            fetch_args: args.fetch_args.clone(),
+            update: !args.no_update,
+            update_strategy: args.update_strategy,
+            check_default_branch: !args.exclude_default_branch_check,
+            merged_into,
+            current_only: args.current_only,
+            protect_current: args.protect_current,
+            keep_merged_into: args.keep_merged_into.clone(),
+            protect_tagged: args.protect_tagged,
+            keep_tracked: args.keep_tracked,
+            prune_empty: args.prune_empty,
+            max_age: args.max_age,
+            before_ref: args.before_ref.clone(),
+            merged_before: args.merged_before,
+            detect_squashed: args.detect_squashed,
+            detect_rebased: args.detect_rebased,
+            maintain: args.maintain,
+            report_only: args.report_only,
+            list: args.list,
+            show_protected: args.show_protected,
+            dry_run: args.dry_run,
+            confirm_each: args.confirm_each,
+            final_confirm: !args.no_final_confirm,
+            fail_fast: args.fail_fast,
+            archive_prefix: args.archive_prefix.clone(),
+            delete_remote: args.delete_remote,
+            on_delete: args.on_delete.clone(),
+            explain: args.explain,
+            auto_keep_one: args.auto_keep_one,
+            single_prompt: args.single_prompt,
+            allow_untracked: args.allow_untracked,
+            show_stat: args.show_stat,
+            sort: args.sort,
+            reset_default_branch: args.reset_default_branch,
+            assume_default: args.assume_default.clone(),
+            found_default_branch: None,
+            park_branch: args.park_branch.clone(),
+            keep_if_open_worktree: args.keep_if_open_worktree,
+            restore: args.restore,
+            log_file,
+            backup_refs_reset: Cell::new(false),
+            planned_deletions: RefCell::new(Vec::new()),
+            worktree_branches: RefCell::new(None),
+            run_report: RefCell::new(RunReport::default()),
+        })
     }
 
     // Used by test code
     #[allow(dead_code)]
     pub fn get_protected_branches(&self) -> HashSet<String> {
-        self.protected_branches.clone()
+        self.protected_branches.keys().cloned().collect()
+    }
+
+    /// Returns the branches checked out in a worktree, and the ones checked out in a worktree
+    /// other than the current one. The worktrees are only listed once per run.
+    fn list_worktree_branches(&self) -> Result<(HashSet<String>, HashSet<String>), GitError> {
+        if let Some(worktree_branches) = &*self.worktree_branches.borrow() {
+            return Ok(worktree_branches.clone());
+        }
+        let current_branch = self.repo.current_branch()?;
+        // Failing to list the worktrees is not fatal: only the current branch is known to be
+        // checked out then
+        let all = match self.repo.branches_in_use_by_worktrees() {
+            Ok(x) => x,
+            Err(x) => {
+                self.ui
+                    .log_warning(&format!("Failed to list worktrees: {}", x));
+                current_branch.iter().cloned().collect()
+            }
+        };
+        let mut others = all.clone();
+        if let Some(branch) = &current_branch {
+            others.remove(branch);
+        }
+        *self.worktree_branches.borrow_mut() = Some((all.clone(), others.clone()));
+        Ok((all, others))
+    }
+
+    /// Returns the branches protected by --keep-if-open-worktree: the ones checked out in any
+    /// worktree
+    fn list_worktree_protected_branches(&self) -> Result<HashSet<String>, GitError> {
+        if !self.keep_if_open_worktree {
+            return Ok(HashSet::new());
+        }
+        let (all, _) = self.list_worktree_branches()?;
+        Ok(all)
+    }
+
+    /// Returns the branches which must not be deleted: the protected branches and, since git
+    /// refuses to delete them, the branches checked out in another worktree
+    fn get_undeletable_branches(&self) -> Result<HashSet<String>, AppError> {
+        let mut branches = self.list_protected_branch_names()?;
+        let (_, other_worktree_branches) = self.list_worktree_branches()?;
+        branches.extend(other_worktree_branches);
+        Ok(branches)
+    }
+
+    /// Returns the names of the branches listed by list_protected_branches()
+    fn list_protected_branch_names(&self) -> Result<HashSet<String>, AppError> {
+        let mut branches = self.get_protected_branches();
+        branches.extend(
+            self.list_pattern_protected_branches()?
+                .into_iter()
+                .map(|(name, _)| name),
+        );
+        branches.extend(self.list_worktree_protected_branches()?);
+        branches.extend(self.list_kept_merged_branches()?);
+        branches.extend(self.list_tagged_branches()?);
+        branches.extend(self.list_live_tracking_branches()?);
+        Ok(branches)
+    }
+
+    /// Returns the branches whose upstream has not been deleted from the remote, with
+    /// --keep-tracked
+    fn list_live_tracking_branches(&self) -> Result<Vec<String>, GitError> {
+        if !self.keep_tracked {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .repo
+            .list_branches_with_upstream()?
+            .into_iter()
+            .filter(|(_, upstream, gone)| upstream.is_some() && !gone)
+            .map(|(branch, _, _)| branch)
+            .collect())
+    }
+
+    /// Returns the branches whose tip is tagged, with --protect-tagged
+    fn list_tagged_branches(&self) -> Result<Vec<String>, GitError> {
+        if !self.protect_tagged {
+            return Ok(Vec::new());
+        }
+        let tag_commits = self.repo.list_tag_commits()?;
+        Ok(self
+            .repo
+            .list_branches_with_sha1s()?
+            .into_iter()
+            .filter(|(_, sha1)| tag_commits.contains(sha1))
+            .map(|(branch, _)| branch)
+            .collect())
+    }
+
+    /// Returns the branches merged into the --keep-merged-into branch, including itself
+    fn list_kept_merged_branches(&self) -> Result<Vec<String>, GitError> {
+        match &self.keep_merged_into {
+            Some(branch) => self
+                .repo
+                .list_branches_filtered(MergeFilter::MergedInto(branch)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the branches matching one of the protection patterns, from git config or
+    /// --exclude-pattern, with the source of the first matching pattern
+    fn list_pattern_protected_branches(&self) -> Result<Vec<(String, ProtectionSource)>, AppError> {
+        if self.protected_patterns.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .repo
+            .list_branches()?
+            .into_iter()
+            .filter_map(|branch| {
+                self.protected_patterns
+                    .iter()
+                    .find(|(pattern, _)| glob::matches(pattern, &branch))
+                    .map(|(_, source)| (branch, *source))
+            })
+            .collect())
+    }
+
+    /// Returns true if the run must not change the repository: with --dry-run, or when only
+    /// printing information with --report-only, --list or --show-protected
+    fn is_read_only(&self) -> bool {
+        self.dry_run || self.report_only || self.list || self.show_protected
+    }
+
+    /// Returns true if the branch matches the --include patterns, or if there are none
+    fn is_included(&self, branch: &str) -> bool {
+        self.include_patterns.is_empty() || glob::matches_any(&self.include_patterns, branch)
     }
 
     pub fn is_working_tree_clean(&self) -> bool {
-        if self.repo.get_current_branch().is_none() {
-            self.ui.log_error("No current branch");
-            return false;
+        match self.repo.is_detached_head() {
+            Ok(false) => (),
+            Ok(true) => {
+                self.ui
+                    .log_error("HEAD is detached, check out a branch first");
+                return false;
+            }
+            Err(x) => {
+                self.ui
+                    .log_error(&format!("Failed to get the current branch: {}", x));
+                return false;
+            }
         }
-        match self.repo.has_changes() {
-            Ok(has_changes) => {
-                if has_changes {
+        match self.has_blocking_changes() {
+            Ok(false) => true,
+            Ok(true) => {
+                self.ui
+                    .log_error("Can't work in a tree with uncommitted changes");
+                if !self.allow_untracked {
                     self.ui
-                        .log_error("Can't work in a tree with uncommitted changes");
-                    return false;
+                        .log_info("Use --allow-untracked to ignore untracked files");
                 }
-                true
+                false
             }
-            Err(_) => {
-                self.ui.log_error("Failed to get working tree status");
+            Err(x) => {
+                self.ui
+                    .log_error(&format!("Failed to get working tree status: {}", x));
                 false
             }
         }
     }
 
-    /// Ask git the name of the default branch, and store the result in git config. If we can't
-    /// find it using git, fallback to asking the user.
+    /// Returns true if the working tree contains changes which prevent git-bonsai from running
+    fn has_blocking_changes(&self) -> Result<bool, GitError> {
+        if self.repo.has_staged_changes()? || self.repo.has_unstaged_changes()? {
+            return Ok(true);
+        }
+        Ok(!self.allow_untracked && self.repo.has_untracked_files()?)
+    }
+
+    /// Ask git the name of the default branch. If we can't find it using git, fallback to asking
+    /// the user.
     pub fn find_default_branch_from_git(&self) -> Result<String, AppError> {
         self.ui.log_info("Determining repository default branch");
-        let branch = match self.repo.find_default_branch() {
+        let branch = match self.repo.find_default_branch(&self.remote) {
             Ok(x) => x,
             Err(err) => {
                 self.ui.log_error(&format!(
@@ -126,13 +545,12 @@ impl App {
                 return self.find_default_branch_from_user();
             }
         };
-        self.repo
-            .set_config_key(DEFAULT_BRANCH_CONFIG_KEY, &branch)?;
         self.ui.log_info(&format!("Default branch is {}", branch));
         Ok(branch)
     }
 
-    /// Ask the user the name of the default branch, and store the result in git config
+    /// Ask the user the name of the default branch. In batch mode, the UI picks it without asking,
+    /// or gives up.
     pub fn find_default_branch_from_user(&self) -> Result<String, AppError> {
         let branch = match self.ui.select_default_branch(&self.repo.list_branches()?) {
             Some(x) => x,
@@ -140,31 +558,109 @@ impl App {
                 return Err(AppError::InterruptedByUser);
             }
         };
-        self.repo
-            .set_config_key(DEFAULT_BRANCH_CONFIG_KEY, &branch)?;
+        self.ui.log_info(&format!("Default branch is {}", branch));
         Ok(branch)
     }
 
-    /// Return the default branch stored in git config, if any
+    /// Return the --assume-default branch, the default branch found during this run, or the
+    /// default branch stored in git config, if any
     pub fn get_default_branch(&self) -> Result<Option<String>, AppError> {
-        match self.repo.get_config_keys(DEFAULT_BRANCH_CONFIG_KEY) {
-            Ok(values) => Ok(if values.len() != 1 {
-                None
-            } else {
-                Some(values[0].clone())
-            }),
-            Err(x) => Err(AppError::Git(x)),
+        if let Some(branch) = self
+            .assume_default
+            .as_ref()
+            .or(self.found_default_branch.as_ref())
+        {
+            return Ok(Some(branch.clone()));
+        }
+        self.repo
+            .get_config_value(DEFAULT_BRANCH_CONFIG_KEY)
+            .map_err(AppError::Git)
+    }
+
+    /// Return the branch the deleted branches must be merged into: the one passed to
+    /// --merged-into, or the default branch
+    fn get_base_branch(&self) -> Result<String, AppError> {
+        if let Some(branch) = &self.merged_into {
+            return Ok(branch.clone());
         }
+        self.get_default_branch()?.ok_or(AppError::NoDeletionTarget)
     }
 
     pub fn fetch_changes(&self) -> Result<(), AppError> {
+        if self.fetch_all {
+            return self.fetch_all_changes();
+        }
+        if !self.repo.list_remotes()?.contains(&self.remote) {
+            self.ui.log_info(&format!(
+                "No remote called {}, not fetching changes",
+                self.remote
+            ));
+            return Ok(());
+        }
+        if self.dry_run {
+            self.ui.log_info("Checking for changes to fetch");
+            let changes = self.repo.fetch_dry_run(&self.remote)?;
+            for line in changes.lines() {
+                self.ui.log_info(line);
+            }
+            return Ok(());
+        }
         self.ui.log_info("Fetching changes");
-        self.repo.fetch()?;
+        self.repo
+            .fetch_with_args(&self.get_fetch_args(&self.remote))?;
+        Ok(())
+    }
+
+    /// Returns the arguments of `git fetch`: --prune, the --fetch-arg arguments, and `target`,
+    /// which is a remote or `--all`
+    fn get_fetch_args<'a>(&'a self, target: &'a str) -> Vec<&'a str> {
+        let mut args = vec!["--prune"];
+        args.extend(self.fetch_args.iter().map(|x| x.as_str()));
+        args.push(target);
+        args
+    }
+
+    /// Fetch changes from all remotes, with --fetch-all
+    fn fetch_all_changes(&self) -> Result<(), AppError> {
+        let remotes = self.repo.list_remotes()?;
+        if remotes.is_empty() {
+            self.ui.log_info("No remotes, not fetching changes");
+            return Ok(());
+        }
+        if self.dry_run {
+            self.ui.log_info("Checking for changes to fetch");
+            for remote in remotes {
+                let changes = self.repo.fetch_dry_run(&remote)?;
+                for line in changes.lines() {
+                    self.ui.log_info(line);
+                }
+            }
+            return Ok(());
+        }
+        self.ui.log_info("Fetching changes from all remotes");
+        self.repo.fetch_with_args(&self.get_fetch_args("--all"))?;
         Ok(())
     }
 
+    /// Returns the branches tracking a branch of the remote, or of any remote with --fetch-all.
+    /// Branches checked out in another worktree are skipped, since they cannot be checked out
+    /// here.
+    fn list_tracking_branches(&self) -> Result<Vec<String>, GitError> {
+        let mut branches: Vec<String> = Vec::new();
+        if self.fetch_all {
+            for remote in self.repo.list_remotes()? {
+                branches.extend(self.repo.list_tracking_branches(&remote)?);
+            }
+        } else {
+            branches = self.repo.list_tracking_branches(&self.remote)?;
+        }
+        let (_, other_worktree_branches) = self.list_worktree_branches()?;
+        branches.retain(|x| !other_worktree_branches.contains(x));
+        Ok(branches)
+    }
+
     pub fn update_tracking_branches(&self) -> Result<(), AppError> {
-        let branches = match self.repo.list_tracking_branches() {
+        let branches = match self.list_tracking_branches() {
             Ok(x) => x,
             Err(x) => {
                 self.ui.log_error("Failed to list tracking branches");
@@ -172,108 +668,661 @@ impl App {
             }
         };
 
-        let _restorer = BranchRestorer::new(&self.repo);
-        for branch in branches {
-            self.ui.log_info(&format!("Updating {}", branch));
+        if self.dry_run {
+            for branch in branches {
+                self.ui.log_info(&format!("Would update {}", branch));
+            }
+            return Ok(());
+        }
+
+        let _restorer = match self.get_default_branch()? {
+            Some(default_branch) => {
+                BranchRestorer::with_fallback(self.repo.as_ref(), &default_branch)?
+            }
+            None => BranchRestorer::new(self.repo.as_ref())?,
+        };
+        let total = branches.len();
+        for (index, branch) in branches.into_iter().enumerate() {
+            self.ui
+                .report_progress(index + 1, total, &format!("Updating {}", branch));
+            match self.repo.ahead_behind(&branch) {
+                Ok((_, 0)) => continue,
+                Ok((0, behind)) => self
+                    .ui
+                    .log_info(&format!("{} is {} behind, fast-forwarding", branch, behind)),
+                Ok(_) if self.update_strategy == UpdateStrategy::Rebase => self
+                    .ui
+                    .log_info(&format!("{} has diverged, rebasing", branch)),
+                Ok(_) => {
+                    self.ui
+                        .log_warning(&format!("{} has diverged, skipping", branch));
+                    self.run_report
+                        .borrow_mut()
+                        .errors
+                        .push(format!("{} has diverged from its upstream", branch));
+                    continue;
+                }
+                Err(x) => {
+                    self.ui.log_warning(&format!(
+                        "Failed to compare {} with its upstream: {}",
+                        branch, x
+                    ));
+                    self.run_report
+                        .borrow_mut()
+                        .errors
+                        .push(format!("Failed to update {}: {}", branch, x));
+                    continue;
+                }
+            }
             if let Err(x) = self.repo.checkout(&branch) {
                 self.ui.log_error("Failed to checkout branch");
                 return Err(AppError::Git(x));
             }
-            if let Err(_x) = self.repo.update_branch() {
-                self.ui.log_warning("Failed to update branch");
-                // This is not wrong, it can happen if the branches have diverged
-                // let's continue
+            let result = match self.update_strategy {
+                UpdateStrategy::FfOnly => self.repo.update_branch(),
+                UpdateStrategy::Rebase => self.repo.rebase_onto_upstream(),
+            };
+            if let Err(x) = result {
+                self.ui
+                    .log_warning(&format!("Failed to update branch: {}", x));
+                self.run_report
+                    .borrow_mut()
+                    .errors
+                    .push(format!("Failed to update {}: {}", branch, x));
+                // Can happen if a rebase fails because of conflicts, but do not stop the other
+                // updates
+            } else {
+                self.log_to_file("updated", &branch);
+                self.run_report.borrow_mut().updated.push(branch);
             }
         }
         Ok(())
     }
     pub fn remove_merged_branches(&self) -> Result<(), AppError> {
-        let to_delete = self.get_deletable_branches()?;
+        let selected_branches = self.select_merged_branches(&[])?;
+        self.delete_selected_branches(&selected_branches)
+    }
+
+    /// Asks which of the deletable branches to delete. The branches in `pending` have already
+    /// been selected for deletion: they are not offered again, and no longer count as containing
+    /// other branches.
+    fn select_merged_branches(&self, pending: &[String]) -> Result<Vec<String>, AppError> {
+        let (analyzed, mut skipped) = self.analyze_branches()?;
+        let mut to_delete = Vec::new();
+        for mut info in analyzed {
+            if pending.contains(&info.name) {
+                continue;
+            }
+            info.contained_in.retain(|x| !pending.contains(x));
+            if info.contained_in.is_empty() && info.rewritten_merge.is_none() {
+                skipped.push(SkippedBranch {
+                    name: info.name,
+                    reason: "only contained in branches selected for deletion".to_string(),
+                });
+                continue;
+            }
+            to_delete.push(info);
+        }
+        if self.explain {
+            self.explain_kept_branches(&skipped)?;
+        }
+        self.run_report.borrow_mut().skipped.extend(skipped);
 
         if to_delete.is_empty() {
             self.ui.log_info("No deletable branches");
-            return Ok(());
+            return Ok(Vec::new());
+        }
+
+        Ok(self
+            .ui
+            .select_branches_to_delete(&to_delete)
+            .into_iter()
+            .map(|x| x.name)
+            .collect())
+    }
+
+    /// Implements --single-prompt: like remove_merged_branches(), but identical branches are
+    /// annotated and selected in the same prompt. If all the branches of a group of identical
+    /// branches which are not contained in any other branch get selected, one of them is kept.
+    fn remove_branches_with_single_prompt(&self) -> Result<(), AppError> {
+        let (mut to_delete, skipped) = self.analyze_branches()?;
+        if self.explain {
+            self.explain_kept_branches(&skipped)?;
+        }
+        self.run_report.borrow_mut().skipped.extend(skipped);
+
+        let undeletable_branches = self.get_undeletable_branches()?;
+        let mut keep_one_groups: Vec<Vec<String>> = Vec::new();
+        for (sha1, branch_set) in self.find_identical_branches()? {
+            for info in to_delete
+                .iter_mut()
+                .filter(|x| branch_set.contains(&x.name))
+            {
+                info.identical_to = branch_set
+                    .iter()
+                    .filter(|x| **x != info.name)
+                    .cloned()
+                    .collect();
+                info.identical_to.sort();
+            }
+            // A protected branch of the group, or a branch outside of it, keeps the commit alive
+            if branch_set.is_disjoint(&undeletable_branches)
+                && !self.is_sha1_contained_in_another_branch(&sha1, &branch_set)?
+            {
+                keep_one_groups.push(branch_set.into_iter().collect());
+            }
         }
 
-        let selected_branches = self.ui.select_branches_to_delete(&to_delete);
-        if selected_branches.is_empty() {
+        if to_delete.is_empty() {
+            self.ui.log_info("No deletable branches");
             return Ok(());
         }
 
-        let branch_names: Vec<String> = selected_branches
-            .iter()
-            .map(|x| x.name.to_string())
+        let mut selected_branches: Vec<String> = self
+            .ui
+            .select_branches_to_delete(&to_delete)
+            .into_iter()
+            .map(|x| x.name)
+            .collect();
+        for group in keep_one_groups {
+            if group.iter().all(|x| selected_branches.contains(x)) {
+                let keep = self.get_branch_to_keep(&group)?;
+                self.ui.log_info(&format!(
+                    "Keeping {}, it points to the same commit as the other selected branches",
+                    keep
+                ));
+                selected_branches.retain(|x| *x != keep);
+            }
+        }
+        self.delete_selected_branches(&selected_branches)
+    }
+
+    /// Report why each existing branch which is not deleted is kept
+    fn explain_kept_branches(&self, skipped: &[SkippedBranch]) -> Result<(), AppError> {
+        let protected_branches = self.list_protected_branch_names()?;
+        let mut kept: Vec<(String, String)> = self
+            .repo
+            .list_branches()?
+            .into_iter()
+            .filter(|x| protected_branches.contains(x))
+            .map(|x| (x, "protected".to_string()))
             .collect();
-        self.delete_branches(&branch_names[..])?;
+        kept.extend(skipped.iter().map(|x| (x.name.clone(), x.reason.clone())));
+        kept.sort();
+        self.ui.report_kept(&kept);
+        Ok(())
+    }
+
+    /// Rename the specified branches, adding `prefix` to their names, and make sure branches with
+    /// this prefix are protected by git config, so that future runs keep them
+    fn archive_branches(&self, branches: &[String], prefix: &str) -> Result<(), AppError> {
+        if self.dry_run {
+            for branch in branches {
+                self.ui
+                    .log_info(&format!("Would archive {} as {}{}", branch, prefix, branch));
+            }
+            return Ok(());
+        }
+
+        let pattern = format!("{}*", prefix);
+        if !self
+            .repo
+            .get_config_keys(PROTECTED_PATTERN_CONFIG_KEY)?
+            .contains(&pattern)
+        {
+            self.repo
+                .add_config_key(PROTECTED_PATTERN_CONFIG_KEY, &pattern)?;
+        }
+
+        for branch in branches {
+            let archived_branch = format!("{}{}", prefix, branch);
+            self.ui
+                .log_info(&format!("Archiving {} as {}", branch, archived_branch));
+            if let Err(x) = self.repo.rename_branch(branch, &archived_branch) {
+                if self.fail_fast {
+                    return Err(AppError::Git(x));
+                }
+                self.ui
+                    .log_warning(&format!("Failed to archive branch: {}", x));
+                self.run_report
+                    .borrow_mut()
+                    .errors
+                    .push(format!("Failed to archive {}: {}", branch, x));
+                continue;
+            }
+            self.run_report.borrow_mut().archived.push(archived_branch);
+        }
         Ok(())
     }
 
+    /// Implements --prune-empty: deletes the branches pointing to the tip of the base branch,
+    /// which have no commits of their own, after confirmation
+    fn prune_empty_branches(&self) -> Result<(), AppError> {
+        let base = self.get_base_branch()?;
+        let base_sha1 = self.repo.rev_parse(&base)?;
+        let undeletable_branches = self.get_undeletable_branches()?;
+        let mut branches = Vec::new();
+        for branch in self.repo.list_branches()? {
+            if branch == base
+                || undeletable_branches.contains(&branch)
+                || !self.is_included(&branch)
+            {
+                continue;
+            }
+            if self.repo.rev_parse(&branch)? == base_sha1 {
+                self.ui
+                    .log_info(&format!("{} has no commits beyond {}", branch, base));
+                branches.push(branch);
+            }
+        }
+        if branches.is_empty() {
+            return Ok(());
+        }
+        if !self.dry_run && !self.ui.confirm_bulk_delete(&branches) {
+            self.ui.log_info("Not deleting empty branches");
+            return Ok(());
+        }
+        self.delete_branches(&branches)
+    }
+
+    /// Delete branches selected by the user, after asking for a final confirmation
+    fn delete_selected_branches(&self, branches: &[String]) -> Result<(), AppError> {
+        if branches.is_empty() {
+            return Ok(());
+        }
+        // --confirm-each already asks for each branch
+        let ask = self.final_confirm && !self.confirm_each && !self.dry_run;
+        if ask && !self.ui.confirm_bulk_delete(branches) {
+            self.ui.log_info("Not deleting any branch");
+            return Ok(());
+        }
+        self.delete_branches(branches)
+    }
+
     /// Delete the specified branches, takes care of checking out another branch if we are deleting
     /// the current one
     fn delete_branches(&self, branches: &[String]) -> Result<(), AppError> {
-        let current_branch = self.repo.get_current_branch().unwrap();
-
-        let mut current_branch_deleted = false;
-        let default_branch = self.get_default_branch().unwrap().unwrap();
-
-        match self.repo.checkout(&default_branch) {
-            Ok(()) => (),
-            Err(x) => {
-                let msg = format!("Failed to switch to default branch '{}'", default_branch);
-                self.ui.log_error(&msg);
-                return Err(AppError::Git(x));
+        if let Some(prefix) = &self.archive_prefix {
+            return self.archive_branches(branches, prefix);
+        }
+        if self.dry_run {
+            for branch in branches {
+                self.ui.log_info(&format!("Would delete {}", branch));
             }
+            self.planned_deletions
+                .borrow_mut()
+                .extend(branches.iter().cloned());
+            return Ok(());
         }
 
+        let base_branch = self.get_base_branch()?;
+        let park_branch = match &self.park_branch {
+            Some(branch) => {
+                if !self.repo.list_branches()?.contains(branch) {
+                    return Err(AppError::NoSuchBranch(branch.clone()));
+                }
+                branch.clone()
+            }
+            None => base_branch.clone(),
+        };
+        // Only switch to the park branch if the current branch is about to be deleted. This never
+        // happens with --keep-if-open-worktree, since it protects the current branch.
+        let current_branch = self.repo.current_branch()?;
+        let _restorer = match current_branch {
+            Some(current_branch) if branches.contains(&current_branch) => {
+                // If the current branch gets deleted, stay on the park branch
+                let restorer = BranchRestorer::with_fallback(self.repo.as_ref(), &park_branch)?;
+                if let Err(x) = self.repo.checkout(&park_branch) {
+                    let msg = format!("Failed to switch to branch '{}'", park_branch);
+                    self.ui.log_error(&msg);
+                    return Err(AppError::Git(x));
+                }
+                Some(restorer)
+            }
+            _ => None,
+        };
+
         for branch in branches {
+            if self.confirm_each {
+                let log = self.repo.log_range(&base_branch, branch)?;
+                if !self.ui.confirm_delete(branch, &log) {
+                    self.ui.log_info(&format!("Keeping {}", branch));
+                    continue;
+                }
+            }
             self.ui.log_info(&format!("Deleting {}", branch));
 
-            if self.safe_delete_branch(branch).is_err() {
-                self.ui.log_warning("Failed to delete branch");
-            } else if *branch == current_branch {
-                current_branch_deleted = true;
+            if let Err(x) = self.safe_delete_branch(branch) {
+                if self.fail_fast {
+                    return Err(x);
+                }
+                self.ui
+                    .log_warning(&format!("Failed to delete branch: {}", x));
+                self.run_report
+                    .borrow_mut()
+                    .errors
+                    .push(format!("Failed to delete {}: {}", branch, x));
+                continue;
+            }
+            self.log_to_file("deleted", branch);
+            self.run_report
+                .borrow_mut()
+                .deleted
+                .push(branch.to_string());
+            if let Some(command) = &self.on_delete {
+                self.run_on_delete_hook(command, branch);
+            }
+            if self.delete_remote {
+                self.delete_remote_branch(branch)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append a record to the --log-file, if any. Failures are logged but do not stop the run.
+    fn log_to_file(&self, action: &str, branch: &str) {
+        if let Some(log_file) = &self.log_file {
+            if let Err(x) = log_file.log(action, branch) {
+                self.ui
+                    .log_warning(&format!("Failed to write to log file: {}", x));
             }
         }
+    }
 
-        if !current_branch_deleted {
-            self.repo.checkout(&current_branch)?;
+    /// Run the --on-delete command for a deleted branch. The branch name is passed as the first
+    /// argument of the command and in the GB_BRANCH environment variable. Failures are logged
+    /// but do not stop the run.
+    fn run_on_delete_hook(&self, command: &str, branch: &str) {
+        let status = Command::new("sh")
+            .args(["-c", command, "sh", branch])
+            .env("GB_BRANCH", branch)
+            .current_dir(self.repo.path())
+            .status();
+        match status {
+            Ok(status) if status.success() => (),
+            Ok(status) => self.ui.log_warning(&format!(
+                "--on-delete command failed for {} ({})",
+                branch, status
+            )),
+            Err(x) => self.ui.log_warning(&format!(
+                "Failed to run --on-delete command for {}: {}",
+                branch, x
+            )),
         }
-        Ok(())
     }
 
-    fn get_deletable_branches(&self) -> Result<Vec<BranchToDeleteInfo>, AppError> {
-        let deletable_branches: Vec<BranchToDeleteInfo> = match self.repo.list_branches() {
+    /// Returns the branches which can be deleted, sorted by name, without deleting anything. The
+    /// protected branches and all the filters set on the command line are applied. Branches
+    /// sharing their commit with other branches are listed too, even though a run would handle
+    /// them with delete_identical_branches().
+    pub fn deletable_branches(&self) -> Result<Vec<BranchToDeleteInfo>, AppError> {
+        let (deletable_branches, _) = self.analyze_branches()?;
+        Ok(deletable_branches)
+    }
+
+    /// Returns the deletable branches which are merged into a protected branch, using one
+    /// `git branch --merged` call per protected branch. This is cheaper than analyze_branches(),
+    /// but misses branches which are only contained in unprotected branches, and rewritten
+    /// merges.
+    pub fn list_branches_merged_into_protected_branches(
+        &self,
+    ) -> Result<HashSet<String>, AppError> {
+        let undeletable_branches = self.get_undeletable_branches()?;
+        let mut bases = self.get_protected_branches();
+        if self.check_default_branch {
+            bases.extend(self.get_default_branch()?);
+        }
+        let existing_branches: HashSet<String> = self.repo.list_branches()?.into_iter().collect();
+
+        let mut merged_branches: HashSet<String> = HashSet::new();
+        for base in bases.intersection(&existing_branches) {
+            merged_branches.extend(self.repo.list_merged_branches(base)?);
+        }
+        Ok(merged_branches
+            .into_iter()
+            .filter(|x| !bases.contains(x) && !undeletable_branches.contains(x))
+            .filter(|x| self.is_included(x))
+            .collect())
+    }
+
+    /// Sort unprotected branches in two lists: those which can be deleted, in the --sort order,
+    /// and those which must be kept, with the reason why
+    fn analyze_branches(&self) -> Result<(Vec<BranchToDeleteInfo>, Vec<SkippedBranch>), AppError> {
+        let protected_branches = self.list_protected_branch_names()?;
+        let (_, other_worktree_branches) = self.list_worktree_branches()?;
+        let branches = match self.repo.list_branches() {
             Ok(x) => x,
             Err(x) => {
                 self.ui.log_error("Failed to list branches");
                 return Err(AppError::Git(x));
             }
-        }
-        .iter()
-        .filter(|&x| !self.protected_branches.contains(x))
-        .map(|branch| {
-            let contained_in: HashSet<String> = match self.repo.list_branches_containing(branch) {
-                Ok(x) => x,
-                Err(_x) => {
-                    self.ui
-                        .log_error(&format!("Failed to list branches containing {}", branch));
-                    [].to_vec()
+        };
+        let merged_branches: Option<HashSet<String>> = match &self.merged_into {
+            Some(base) => Some(
+                self.repo
+                    .list_branches_filtered(MergeFilter::MergedInto(base))?
+                    .into_iter()
+                    .collect(),
+            ),
+            None => None,
+        };
+        let containment_map = match self.repo.build_containment_map() {
+            Ok(x) => x,
+            Err(x) => {
+                self.ui.log_error("Failed to list branch containment");
+                return Err(AppError::Git(x));
+            }
+        };
+        // Not knowing the base branch is not a problem here, it only means unique commits are
+        // not counted
+        let base_branch = self.get_base_branch().ok();
+        let rewritten_merge_base = if self.detect_squashed || self.detect_rebased {
+            Some(self.get_base_branch()?)
+        } else {
+            None
+        };
+        let detect_rewritten_merge = |branch: &str| -> Result<Option<RewrittenMerge>, AppError> {
+            match &rewritten_merge_base {
+                Some(base) => self.detect_rewritten_merge(branch, base),
+                None => Ok(None),
+            }
+        };
+
+        let mut deletable_branches: Vec<BranchToDeleteInfo> = Vec::new();
+        let mut skipped_branches: Vec<SkippedBranch> = Vec::new();
+        for branch in branches {
+            if protected_branches.contains(&branch) {
+                continue;
+            }
+            if other_worktree_branches.contains(&branch) {
+                self.ui.log_warning(&format!(
+                    "Not deleting {}, it is checked out in another worktree",
+                    branch
+                ));
+                skipped_branches.push(SkippedBranch {
+                    name: branch,
+                    reason: "checked out in another worktree".to_string(),
+                });
+                continue;
+            }
+            if !self.is_included(&branch) {
+                skipped_branches.push(SkippedBranch {
+                    name: branch,
+                    reason: "does not match the --include patterns".to_string(),
+                });
+                continue;
+            }
+            if let Some(days) = self.max_age {
+                let date = self.repo.get_branch_commit_date(&branch)?;
+                if is_less_than_days_old(date, days) {
+                    skipped_branches.push(SkippedBranch {
+                        name: branch,
+                        reason: format!("last commit is less than {} days old", days),
+                    });
+                    continue;
                 }
             }
-            .iter()
-            .filter(|&x| x != branch)
-            .cloned()
-            .collect();
+            if let Some(before_ref) = &self.before_ref {
+                if !self.repo.is_ancestor(&branch, before_ref)? {
+                    skipped_branches.push(SkippedBranch {
+                        name: branch,
+                        reason: format!("last commit is not in {}", before_ref),
+                    });
+                    continue;
+                }
+            }
+            if let (Some(days), Some(base)) = (self.merged_before, &base_branch) {
+                let date = match self.repo.merge_commit_date(&branch, base)? {
+                    Some(x) => x,
+                    None => self.repo.get_branch_commit_date(&branch)?,
+                };
+                if is_less_than_days_old(date, days) {
+                    skipped_branches.push(SkippedBranch {
+                        name: branch,
+                        reason: format!("merged less than {} days ago", days),
+                    });
+                    continue;
+                }
+            }
+            let mut rewritten_merge = None;
+            if let (Some(base), Some(merged_branches)) = (&self.merged_into, &merged_branches) {
+                if !merged_branches.contains(&branch) {
+                    rewritten_merge = detect_rewritten_merge(&branch)?;
+                    if rewritten_merge.is_none() {
+                        skipped_branches.push(SkippedBranch {
+                            name: branch,
+                            reason: format!("not merged into {}", base),
+                        });
+                        continue;
+                    }
+                }
+            }
+
+            let contained_in: HashSet<String> = containment_map
+                .get(&branch)
+                .into_iter()
+                .flatten()
+                .filter(|x| **x != branch)
+                .cloned()
+                .collect();
 
-            BranchToDeleteInfo {
-                name: branch.to_string(),
+            if contained_in.is_empty() && rewritten_merge.is_none() {
+                rewritten_merge = detect_rewritten_merge(&branch)?;
+            }
+            if contained_in.is_empty() && rewritten_merge.is_none() {
+                skipped_branches.push(SkippedBranch {
+                    name: branch,
+                    reason: "not contained in any other branch".to_string(),
+                });
+                continue;
+            }
+            let unique_commits = match &base_branch {
+                Some(base) => self.repo.count_unique_commits(&branch, base)?,
+                None => 0,
+            };
+            let diffstat = match &base_branch {
+                Some(base) if self.show_stat => Some(self.repo.diff_stat(base, &branch)?),
+                _ => None,
+            };
+            deletable_branches.push(BranchToDeleteInfo {
+                name: branch,
                 contained_in,
+                rewritten_merge,
+                unique_commits,
+                diffstat,
+                identical_to: Vec::new(),
+            });
+        }
+
+        deletable_branches.sort();
+        if self.sort == SortOrder::Date {
+            let mut dated_branches = Vec::new();
+            for info in deletable_branches {
+                let date = self.repo.get_branch_commit_date(&info.name)?;
+                dated_branches.push((date, info));
             }
-        })
-        .filter(|x| !x.contained_in.is_empty())
-        .collect();
+            // The sort is stable, so branches with the same date remain sorted by name
+            dated_branches.sort_by_key(|(date, _)| *date);
+            deletable_branches = dated_branches.into_iter().map(|(_, info)| info).collect();
+        }
+        Ok((deletable_branches, skipped_branches))
+    }
 
-        Ok(deletable_branches)
+    /// Returns the protected branches, sorted by name, with the reason why they are protected
+    fn list_protected_branches(&self) -> Result<Vec<ProtectedBranch>, AppError> {
+        let mut protected: Vec<ProtectedBranch> = self
+            .protected_branches
+            .iter()
+            .map(|(name, reason)| ProtectedBranch {
+                name: name.clone(),
+                reason: *reason,
+            })
+            .collect();
+        for (name, reason) in self.list_pattern_protected_branches()? {
+            if !self.protected_branches.contains_key(&name) {
+                protected.push(ProtectedBranch { name, reason });
+            }
+        }
+        for name in self.list_worktree_protected_branches()? {
+            if !protected.iter().any(|x| x.name == name) {
+                protected.push(ProtectedBranch {
+                    name,
+                    reason: ProtectionSource::Worktree,
+                });
+            }
+        }
+        let kept_branches = self
+            .list_kept_merged_branches()?
+            .into_iter()
+            .map(|x| (x, ProtectionSource::KeepMergedInto))
+            .chain(
+                self.list_tagged_branches()?
+                    .into_iter()
+                    .map(|x| (x, ProtectionSource::Tagged)),
+            )
+            .chain(
+                self.list_live_tracking_branches()?
+                    .into_iter()
+                    .map(|x| (x, ProtectionSource::Tracked)),
+            );
+        for (name, reason) in kept_branches {
+            if !protected.iter().any(|x| x.name == name) {
+                protected.push(ProtectedBranch { name, reason });
+            }
+        }
+        protected.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(protected)
+    }
+
+    /// Build a read-only snapshot of the protected, deletable and skipped branches
+    pub fn build_report(&self) -> Result<Report, AppError> {
+        let default_branch = if self.check_default_branch {
+            self.get_default_branch()?
+        } else {
+            None
+        };
+        let protected = self.list_protected_branches()?;
+
+        let (deletable_branches, skipped) = self.analyze_branches()?;
+        let deletable = deletable_branches
+            .into_iter()
+            .map(|info| {
+                let mut contained_in: Vec<String> = info.contained_in.into_iter().collect();
+                contained_in.sort();
+                DeletableBranch {
+                    name: info.name,
+                    contained_in,
+                    rewritten_merge: info.rewritten_merge,
+                }
+            })
+            .collect();
+
+        Ok(Report {
+            default_branch,
+            protected,
+            deletable,
+            skipped,
+        })
     }
 
     fn is_sha1_contained_in_another_branch(
@@ -281,7 +1330,7 @@ impl App {
         sha1: &str,
         branches: &HashSet<String>,
     ) -> Result<bool, GitError> {
-        for branch in self.repo.list_branches_containing(sha1).unwrap() {
+        for branch in self.repo.list_branches_containing(sha1)? {
             if !branches.contains(&branch) {
                 return Ok(true);
             }
@@ -289,139 +1338,618 @@ impl App {
         Ok(false)
     }
 
-    pub fn do_delete_identical_branches(
+    /// Returns what to do with each group of identical branches, sorted by sha1
+    pub fn plan_identical_branches(&self) -> Result<Vec<IdenticalGroupPlan>, AppError> {
+        let undeletable_branches = self.get_undeletable_branches()?;
+        let mut plans = Vec::new();
+        for (sha1, branch_set) in self.find_identical_branches()? {
+            let contained_elsewhere =
+                self.is_sha1_contained_in_another_branch(&sha1, &branch_set)?;
+            plans.push(IdenticalGroupPlan::new(
+                &sha1,
+                &branch_set,
+                &undeletable_branches,
+                |x| self.is_included(x),
+                contained_elsewhere,
+            ));
+        }
+        plans.sort_by(|a, b| a.sha1.cmp(&b.sha1));
+        Ok(plans)
+    }
+
+    /// Returns the branches of the group selected for deletion. With --auto-keep-one, all the
+    /// branches of a group which must keep one but the preferred one are selected without asking.
+    fn execute_identical_group_plan(
         &self,
-        sha1: &str,
-        branch_set: &HashSet<String>,
-    ) -> Result<(), AppError> {
-        let unprotected_branch_set: HashSet<_> =
-            branch_set.difference(&self.protected_branches).collect();
-        if !self
-            .is_sha1_contained_in_another_branch(sha1, branch_set)
-            .unwrap()
-        {
-            let contains_protected_branches = unprotected_branch_set.len() < branch_set.len();
-            let branches: Vec<String> = unprotected_branch_set
-                .iter()
-                .map(|x| x.to_string())
-                .collect();
-            if !contains_protected_branches {
-                let selected_branches: Vec<String> = self
-                    .ui
-                    .select_identical_branches_to_delete_keep_one(&branches)
-                    .iter()
-                    .map(|x| x.to_string())
-                    .collect();
-                self.delete_branches(&selected_branches)?;
-                return Ok(());
+        plan: &IdenticalGroupPlan,
+    ) -> Result<Vec<String>, AppError> {
+        if plan.keep_one {
+            let preferred = self.get_branch_to_keep(&plan.deletable)?;
+            if self.auto_keep_one {
+                return Ok(batchappui::select_all_but_one(&plan.deletable, &preferred));
             }
+            return Ok(self
+                .ui
+                .select_identical_branches_to_delete_keep_one(&plan.deletable, &preferred));
         }
-        if unprotected_branch_set.is_empty() {
-            // Aliases are only protected branches, do nothing
-            return Ok(());
+        if plan.deletable.is_empty() {
+            // Aliases are only protected branches, or branches excluded by --include: explain why
+            // they are kept
+            if plan.protected.is_empty() {
+                // All the branches are excluded by --include, nothing is held by a protected branch
+                return Ok(Vec::new());
+            }
+            let protected_str = plan.protected.join(", ");
+            if plan.excluded.is_empty() {
+                self.ui.log_info(&format!(
+                    "Keeping {}: these protected branches point to the same commit",
+                    protected_str
+                ));
+            }
+            for branch in &plan.excluded {
+                self.ui.log_info(&format!(
+                    "Keeping {}: it points to the same commit as protected branch(es) {}",
+                    branch, protected_str
+                ));
+            }
+            return Ok(Vec::new());
         }
-        let branches: Vec<String> = unprotected_branch_set
-            .iter()
-            .map(|x| x.to_string())
+        Ok(self.ui.select_identical_branches_to_delete(&plan.deletable))
+    }
+
+    /// Implements --list: prints the deletable branches and the identical branches, without
+    /// fetching, updating or deleting anything
+    fn list_branches(&self) -> Result<(), AppError> {
+        let deletable: Vec<String> = self
+            .deletable_branches()?
+            .into_iter()
+            .map(|x| x.name)
             .collect();
-        let selected_branches: Vec<_> = self
-            .ui
-            .select_identical_branches_to_delete(&branches)
-            .iter()
-            .map(|x| x.to_string())
+
+        let undeletable_branches = self.get_undeletable_branches()?;
+        let mut identical: Vec<Vec<String>> = self
+            .find_identical_branches()?
+            .into_values()
+            .filter_map(|branch_set| {
+                let mut branches: Vec<String> = branch_set
+                    .difference(&undeletable_branches)
+                    .filter(|x| self.is_included(x))
+                    .cloned()
+                    .collect();
+                branches.sort();
+                (!branches.is_empty()).then_some(branches)
+            })
             .collect();
-        self.delete_branches(&selected_branches)?;
+        identical.sort();
+
+        self.ui.show_list(&deletable, &identical);
         Ok(())
     }
 
-    pub fn delete_identical_branches(&self) -> Result<(), AppError> {
-        // Create a hashmap sha1 => set(branches)
+    /// Among identical branches, returns the one to keep if only one can be kept: the current
+    /// branch, then the default branch, then the first one in alphabetical order
+    fn get_branch_to_keep(&self, branches: &[String]) -> Result<String, AppError> {
+        let candidates = vec![self.repo.current_branch()?, self.get_default_branch()?];
+        for candidate in candidates.into_iter().flatten() {
+            if branches.contains(&candidate) {
+                return Ok(candidate);
+            }
+        }
+        Ok(branches.iter().min().cloned().unwrap_or_default())
+    }
+
+    /// Returns a hashmap full sha1 => set(branches), for the commits pointed to by more than one
+    /// branch
+    fn find_identical_branches(&self) -> Result<HashMap<String, HashSet<String>>, GitError> {
         let mut branches_for_sha1: HashMap<String, HashSet<String>> = HashMap::new();
+        for (branch, sha1) in self.repo.list_branches_with_sha1s()? {
+            branches_for_sha1.entry(sha1).or_default().insert(branch);
+        }
+        branches_for_sha1.retain(|_, branch_set| branch_set.len() > 1);
+        Ok(branches_for_sha1)
+    }
+
+    pub fn delete_identical_branches(&self) -> Result<(), AppError> {
+        let selected_branches = self.select_identical_branches()?;
+        self.delete_selected_branches(&selected_branches)
+    }
 
-        match self.repo.list_branches_with_sha1s() {
+    /// Asks which branches to delete in each group of identical branches
+    fn select_identical_branches(&self) -> Result<Vec<String>, AppError> {
+        let plans = match self.plan_identical_branches() {
             Ok(x) => x,
             Err(x) => {
-                self.ui.log_error("Failed to list branches");
-                return Err(AppError::Git(x));
-            }
-        }
-        .iter()
-        .for_each(|(branch, sha1)| {
-            let branch_set = branches_for_sha1
-                .entry(sha1.to_string())
-                .or_insert_with(HashSet::<String>::new);
-            branch_set.insert(branch.to_string());
-        });
-
-        // Delete identical branches if there are more than one for the same sha1
-        for (sha1, branch_set) in branches_for_sha1 {
-            if branch_set.len() == 1 {
-                continue;
-            }
-            if let Err(x) = self.do_delete_identical_branches(&sha1, &branch_set) {
                 self.ui.log_error("Failed to list branches");
                 return Err(x);
             }
-        }
+        };
 
-        Ok(())
+        let mut selected_branches = Vec::new();
+        for plan in &plans {
+            selected_branches.extend(self.execute_identical_group_plan(plan)?);
+        }
+        Ok(selected_branches)
     }
 
     pub fn safe_delete_branch(&self, branch: &str) -> Result<(), AppError> {
-        // A branch is only safe to delete if at least another branch contains it
-        let contained_in = self.repo.list_branches_containing(branch).unwrap();
-        if contained_in.len() < 2 {
+        // A branch is only safe to delete if at least another branch contains it. That other
+        // branch can be the current one: contained_in includes the branch itself.
+        let contained_in = self.repo.list_branches_containing(branch)?;
+        if contained_in.len() < 2 && !self.is_rewritten_merge_of_base(branch)? {
             self.ui.log_error(&format!(
                 "Not deleting {}, no other branches contain it",
                 branch
             ));
             return Err(AppError::UnsafeDelete);
         }
+        self.backup_branch(branch)?;
         self.repo.delete_branch(branch)?;
         Ok(())
     }
 
+    /// Returns true if --detect-squashed or --detect-rebased is set and the branch has been
+    /// squash-merged or rebase-merged into the base branch
+    fn is_rewritten_merge_of_base(&self, branch: &str) -> Result<bool, AppError> {
+        if !self.detect_squashed && !self.detect_rebased {
+            return Ok(false);
+        }
+        let base = self.get_base_branch()?;
+        Ok(self.detect_rewritten_merge(branch, &base)?.is_some())
+    }
+
+    /// Checks if the changes of the branch are in `base` even though its commits are not, using
+    /// the detections enabled by --detect-rebased and --detect-squashed
+    fn detect_rewritten_merge(
+        &self,
+        branch: &str,
+        base: &str,
+    ) -> Result<Option<RewrittenMerge>, AppError> {
+        if branch == base {
+            return Ok(None);
+        }
+        if self.detect_rebased && self.repo.cherry_missing_commits(base, branch)?.is_empty() {
+            return Ok(Some(RewrittenMerge::RebaseMerged));
+        }
+        if self.detect_squashed && self.repo.is_squash_merged(branch, base)? {
+            return Ok(Some(RewrittenMerge::SquashMerged));
+        }
+        Ok(None)
+    }
+
+    /// Delete the remote counterpart of a deleted branch, if it exists and all its commits are in
+    /// local branches
+    fn delete_remote_branch(&self, branch: &str) -> Result<(), AppError> {
+        let remote_branch = format!("{}/{}", self.remote, branch);
+        let remote_ref = format!("refs/remotes/{}", remote_branch);
+        if self.repo.rev_parse(&remote_ref).is_err() {
+            return Ok(());
+        }
+        if self.repo.list_branches_containing(&remote_ref)?.is_empty() {
+            self.ui.log_warning(&format!(
+                "Not deleting {}, it contains commits which are not in any local branch",
+                remote_branch
+            ));
+            return Ok(());
+        }
+        if !self.ui.confirm_delete_remote(&self.remote, branch) {
+            return Ok(());
+        }
+        self.ui.log_info(&format!("Deleting {}", remote_branch));
+        if let Err(x) = self.repo.delete_remote_branch(&self.remote, branch) {
+            if self.fail_fast {
+                return Err(AppError::Git(x));
+            }
+            self.ui
+                .log_warning(&format!("Failed to delete remote branch: {}", x));
+            self.run_report
+                .borrow_mut()
+                .errors
+                .push(format!("Failed to delete {}: {}", remote_branch, x));
+        }
+        Ok(())
+    }
+
+    /// Store the commit of the branch in a backup ref, after removing the backup refs of the
+    /// previous run
+    fn backup_branch(&self, branch: &str) -> Result<(), AppError> {
+        if !self.backup_refs_reset.get() {
+            self.repo.delete_backup_refs()?;
+            self.backup_refs_reset.set(true);
+        }
+        self.repo.create_backup_ref(branch)?;
+        Ok(())
+    }
+
+    /// Recreate the branches deleted by the last run, using the backup refs
+    pub fn restore_branches(&self) -> Result<(), AppError> {
+        let backups = self.repo.list_backup_refs()?;
+        if backups.is_empty() {
+            self.ui.log_info("No branches to restore");
+            return Ok(());
+        }
+        let existing_branches: HashSet<String> = self.repo.list_branches()?.into_iter().collect();
+        for (branch, sha1) in backups {
+            if existing_branches.contains(&branch) {
+                self.ui
+                    .log_info(&format!("Not restoring {}, it already exists", branch));
+                continue;
+            }
+            if !self.repo.has_commit(&sha1) {
+                self.ui.log_warning(&format!(
+                    "Cannot restore {}, commit {} no longer exists",
+                    branch, sha1
+                ));
+                continue;
+            }
+            self.repo.create_branch(&branch, Some(&sha1))?;
+            self.ui.log_info(&format!(
+                "Restored {} at {}",
+                branch,
+                self.repo.abbreviated_sha(&sha1)?
+            ));
+        }
+        Ok(())
+    }
+
+    /// Implements --current-only: the current branch becomes the --merged-into target
+    fn use_current_branch_as_merged_into(&mut self) -> Result<(), AppError> {
+        let branch = self
+            .repo
+            .current_branch()?
+            .ok_or(AppError::NoCurrentBranch)?;
+        self.protected_branches
+            .entry(branch.clone())
+            .or_insert(ProtectionSource::MergedInto);
+        self.merged_into = Some(branch);
+        Ok(())
+    }
+
+    /// Implements --protect-current
+    fn add_current_branch_to_protected_branches(&mut self) -> Result<(), AppError> {
+        if let Some(branch) = self.repo.current_branch()? {
+            self.protected_branches
+                .entry(branch)
+                .or_insert(ProtectionSource::CurrentBranch);
+        }
+        Ok(())
+    }
+
     pub fn add_default_branch_to_protected_branches(&mut self) -> Result<(), AppError> {
-        let default_branch = match self.get_default_branch()? {
+        if let Some(branch) = &self.assume_default {
+            if !self.repo.branch_exists(branch)? {
+                return Err(AppError::NoSuchBranch(branch.clone()));
+            }
+            self.protected_branches
+                .insert(branch.clone(), ProtectionSource::DefaultBranch);
+            return Ok(());
+        }
+        let stored_default_branch = if self.reset_default_branch {
+            None
+        } else {
+            self.get_default_branch()?
+        };
+        // The stored default branch may have been renamed or deleted since it has been stored
+        let stored_default_branch = match stored_default_branch {
+            Some(branch) if !self.repo.branch_exists(&branch)? => {
+                self.ui.log_warning(&format!(
+                    "Default branch {} does not exist anymore, looking for it again",
+                    branch
+                ));
+                None
+            }
+            x => x,
+        };
+        let default_branch = match stored_default_branch {
             Some(x) => x,
             None => {
-                if self.fetch {
+                let branch = if self.fetch {
                     self.find_default_branch_from_git()?
                 } else {
                     self.find_default_branch_from_user()?
+                };
+                if !self.is_read_only() {
+                    self.repo
+                        .set_config_key(DEFAULT_BRANCH_CONFIG_KEY, &branch)?;
                 }
+                branch
             }
         };
-        self.protected_branches.insert(default_branch);
+        self.found_default_branch = Some(default_branch.clone());
+        self.protected_branches
+            .insert(default_branch, ProtectionSource::DefaultBranch);
         Ok(())
     }
 
-    pub fn run(&mut self) -> Result<(), AppError> {
-        self.add_default_branch_to_protected_branches()?;
+    /// Runs all the steps, and returns what has been done. The errors which did not stop the run,
+    /// such as a branch which could not be updated, are listed in the returned report. If an
+    /// error stopped the run, it is returned with what had been done before it happened.
+    pub fn run(&mut self) -> Result<RunReport, RunError> {
+        // Without the list of branches, the plan cannot be reported, but the run can still happen
+        let plan_before = if self.dry_run
+            && !self.report_only
+            && !self.list
+            && !self.show_protected
+            && !self.restore
+        {
+            self.repo.list_branches().ok()
+        } else {
+            None
+        };
+        let result = self.run_steps();
+        if let Err(error) = &result {
+            self.ui.log_error(&error.to_string());
+            self.run_report.borrow_mut().errors.push(error.to_string());
+        }
+        if let (Some(before), Ok(())) = (plan_before, &result) {
+            let planned_deletions = self.planned_deletions.borrow();
+            let after: Vec<String> = before
+                .iter()
+                .filter(|x| !planned_deletions.contains(x))
+                .cloned()
+                .collect();
+            self.ui.report_plan(&before, &after);
+        }
+        if !self.report_only && !self.list && !self.show_protected {
+            self.ui.show_summary(&self.run_report.borrow());
+        }
+        let report = self.run_report.take();
+        match result {
+            Ok(()) => Ok(report),
+            Err(error) => Err(RunError {
+                error,
+                report: Box::new(report),
+            }),
+        }
+    }
+
+    fn run_steps(&mut self) -> Result<(), AppError> {
+        if self.restore {
+            return self.restore_branches();
+        }
+        if self.current_only {
+            self.use_current_branch_as_merged_into()?;
+        }
+        if self.protect_current {
+            self.add_current_branch_to_protected_branches()?;
+        }
+        if self.check_default_branch {
+            self.add_default_branch_to_protected_branches()?;
+        } else {
+            self.ui.log_warning(
+                "Not checking the default branch: it is not protected, and safety checks relying \
+                on it are disabled!",
+            );
+            if !self.maintain
+                && !self.report_only
+                && !self.list
+                && !self.show_protected
+                && self.merged_into.is_none()
+            {
+                return Err(AppError::NoDeletionTarget);
+            }
+        }
+        if self.show_protected {
+            let protected = self.list_protected_branches()?;
+            self.ui.show_protected(&protected);
+            return Ok(());
+        }
+        if self.report_only {
+            let report = self.build_report()?;
+            self.ui.show_report(&report);
+            return Ok(());
+        }
+        if self.list {
+            return self.list_branches();
+        }
         if self.fetch {
             self.fetch_changes()?;
         }
 
-        self.update_tracking_branches()?;
-        self.delete_identical_branches()?;
-        self.remove_merged_branches()?;
-        Ok(())
+        if self.update {
+            self.update_tracking_branches()?;
+        }
+        if self.maintain {
+            return Ok(());
+        }
+        if self.repo.list_branches()?.len() <= 1 {
+            self.ui.log_info("Only one branch present, nothing to do");
+            return Ok(());
+        }
+        if self.prune_empty {
+            self.prune_empty_branches()?;
+        }
+        if self.single_prompt {
+            return self.remove_branches_with_single_prompt();
+        }
+        // Select the branches of both passes first, so that the deletion is confirmed once
+        let mut selected_branches = self.select_identical_branches()?;
+        selected_branches.extend(self.select_merged_branches(&selected_branches)?);
+        self.delete_selected_branches(&selected_branches)
     }
 }
 
-pub fn run(args: CliArgs, dir: &str) -> i32 {
-    let ui: Box<dyn AppUi> = match args.yes {
-        false => Box::new(InteractiveAppUi {}),
-        true => Box::new(BatchAppUi {}),
+/// Returns the date `days` days ago, or None if it cannot be represented, in which case it is
+/// older than any commit
+fn cutoff(days: u64) -> Option<SystemTime> {
+    let secs = days.checked_mul(SECONDS_PER_DAY)?;
+    SystemTime::now().checked_sub(Duration::from_secs(secs))
+}
+
+/// Returns true if `date` is less than `days` days ago
+fn is_less_than_days_old(date: SystemTime, days: u64) -> bool {
+    match cutoff(days) {
+        Some(cutoff) => date > cutoff,
+        None => true,
+    }
+}
+
+/// Stashes the changes of the working tree, if there are any, for --autostash
+fn autostash<'a>(repo: &'a Repository, ui: &dyn AppUi) -> Result<Option<StashGuard<'a>>, GitError> {
+    if !repo.has_staged_changes()?
+        && !repo.has_unstaged_changes()?
+        && !repo.has_untracked_files()?
+    {
+        return Ok(None);
+    }
+    ui.log_info("Stashing changes");
+    StashGuard::push(repo).map(Some)
+}
+
+/// Checks out the branch stored in `restore_point`, if any, pops the changes stashed by
+/// --autostash, and clears it. BranchRestorer and StashGuard cannot do this when git-bonsai is
+/// interrupted, because the process exits without running destructors.
+pub fn restore_interrupted_run(restore_point: &InterruptRestorePoint) -> Result<(), GitError> {
+    let restore_point = restore_point
+        .lock()
+        .unwrap_or_else(|x| x.into_inner())
+        .take();
+    let restore_point = match restore_point {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let repo = Repository::new(&restore_point.path);
+    let mut result = repo.checkout(&restore_point.rev);
+    if restore_point.stashed {
+        // Popping the stash on another branch could conflict: if the checkout failed, leave the
+        // changes in the stash
+        if result.is_ok() {
+            result = repo.stash_pop();
+        }
+        if result.is_err() {
+            eprintln!("Your changes are still stashed. Use `git stash pop` to restore them.");
+        }
+    }
+    result
+}
+
+/// Installs a SIGINT handler which restores the original branch and the stashed changes before
+/// exiting, and records the current branch of `repo` as the one to restore
+fn set_interrupt_restore_point(repo: &Repository, stashed: bool) -> Result<(), GitError> {
+    let rev = match repo.current_branch()? {
+        Some(branch) => branch,
+        None => repo.rev_parse("HEAD")?,
     };
-    let mut app = App::new(&args, ui, dir);
+    *INTERRUPT_RESTORE_POINT
+        .lock()
+        .unwrap_or_else(|x| x.into_inner()) = Some(RunRestorePoint {
+        path: repo.path.clone(),
+        rev,
+        stashed,
+    });
 
-    if !app.is_working_tree_clean() {
+    INSTALL_INTERRUPT_HANDLER.call_once(|| {
+        let result = ctrlc::set_handler(|| {
+            if let Err(x) = restore_interrupted_run(&INTERRUPT_RESTORE_POINT) {
+                eprintln!("Failed to restore the repository: {}", x);
+            }
+            std::process::exit(EXIT_SIGINT);
+        });
+        if let Err(x) = result {
+            eprintln!("Failed to install the interrupt handler: {}", x);
+        }
+    });
+    Ok(())
+}
+
+pub fn run(args: CliArgs, dir: &str) -> i32 {
+    tui::init_colors(args.no_color);
+    let verbosity = if args.quiet {
+        Verbosity::Quiet
+    } else {
+        Verbosity::Normal
+    };
+    let ui: Box<dyn AppUi> = if args.events == Some(EventFormat::Ndjson) {
+        Box::new(NdjsonAppUi::new(verbosity))
+    } else if args.format == OutputFormat::Json {
+        Box::new(JsonAppUi::new(verbosity))
+    } else if args.yes {
+        Box::new(BatchAppUi::new(verbosity))
+    } else {
+        Box::new(InteractiveAppUi::new(verbosity, !args.no_preselect))
+    };
+    if args.delete_remote
+        && !args.yes
+        && (args.events.is_some() || args.format == OutputFormat::Json)
+    {
+        ui.log_error("--delete-remote requires --yes with --format json or --events");
+        return 1;
+    }
+    let path = PathBuf::from(dir);
+    if !path.is_dir() {
+        ui.log_error(&format!("{} is not a directory", dir));
+        return 1;
+    }
+    let repo = Repository::new(&path);
+    match repo.git_version() {
+        Ok(version) if version >= MIN_GIT_VERSION => (),
+        Ok((major, minor, patch)) => {
+            let (min_major, min_minor, min_patch) = MIN_GIT_VERSION;
+            ui.log_error(&format!(
+                "git {}.{}.{} is too old, git-bonsai requires git {}.{}.{} or later",
+                major, minor, patch, min_major, min_minor, min_patch
+            ));
+            return 1;
+        }
+        Err(x) => {
+            ui.log_error(&format!("Failed to find the version of git: {}", x));
+            return 1;
+        }
+    }
+    if !repo.is_git_repo() {
+        ui.log_error("Not inside a git repository");
         return 1;
     }
+    if !repo.has_commits() {
+        ui.log_info("Repository has no commits, nothing to do");
+        return 0;
+    }
+    let config = match repo.get_toplevel() {
+        Ok(toplevel) => ConfigFile::load(&toplevel),
+        // A bare repository, it cannot contain a config file
+        Err(_) => Ok(ConfigFile::default()),
+    };
+    let config = match config {
+        Ok(x) => x,
+        Err(x) => {
+            ui.log_error(&x.to_string());
+            return 1;
+        }
+    };
+    // Declared before `app` so that changes are restored after the app is done
+    let mut stash_guard = None;
+    let read_only =
+        args.dry_run || args.report_only || args.list || args.show_protected || args.restore;
+    if args.autostash && !read_only {
+        match autostash(&repo, ui.as_ref()) {
+            Ok(x) => stash_guard = x,
+            Err(x) => {
+                ui.log_error(&format!("Failed to stash changes: {}", x));
+                return EXIT_GIT_FAILED;
+            }
+        }
+    }
+    let mut app = match App::new(&args, &config, ui, dir) {
+        Ok(x) => x,
+        // The error has already been logged
+        Err(_) => return EXIT_GIT_FAILED,
+    };
 
-    match app.run() {
-        Ok(()) => 0,
-        Err(_) => 1,
+    if !read_only && !app.is_working_tree_clean() {
+        return EXIT_DIRTY_TREE;
     }
+
+    if let Err(x) = set_interrupt_restore_point(&repo, stash_guard.is_some()) {
+        app.ui
+            .log_error(&format!("Failed to get the current branch: {}", x));
+        return EXIT_GIT_FAILED;
+    }
+    let exit_code = match app.run() {
+        Ok(_) => 0,
+        Err(x) => x.error.exit_code(),
+    };
+    INTERRUPT_RESTORE_POINT
+        .lock()
+        .unwrap_or_else(|x| x.into_inner())
+        .take();
+    exit_code
 }
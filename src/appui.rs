@@ -19,27 +19,118 @@
 /**
  * This module provides a "high-level" interface for the UI
  */
+use std::cmp::Ordering;
 use std::collections::HashSet;
 
+use crate::report::{ProtectedBranch, Report, RewrittenMerge, RunReport};
+
+/// How much the UI should log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Only log warnings and errors
+    Quiet,
+    Normal,
+}
+
 #[derive(Clone, Debug)]
 pub struct BranchToDeleteInfo {
     pub name: String,
     pub contained_in: HashSet<String>,
+    /// Set if the branch is not contained in another branch, but has been detected as merged
+    pub rewritten_merge: Option<RewrittenMerge>,
+    /// Number of commits of the branch which are not in the base branch
+    pub unique_commits: usize,
+    /// Summary of the changes between the base branch and the branch, set with --show-stat
+    pub diffstat: Option<String>,
+    /// Other branches pointing to the same commit, sorted. Set with --single-prompt, since
+    /// identical branches are then selected together with the other branches.
+    pub identical_to: Vec<String>,
 }
 
+// BranchToDeleteInfo instances are compared and sorted by branch name
+impl PartialEq for BranchToDeleteInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for BranchToDeleteInfo {}
+
+impl PartialOrd for BranchToDeleteInfo {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BranchToDeleteInfo {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.name.cmp(&other.name)
+    }
+}
+
+/// The interface between App and the user. All the methods used by App are part of it, so that a
+/// UI can be implemented outside of this crate, as the tests do.
 pub trait AppUi {
     fn log_info(&self, msg: &str);
     fn log_warning(&self, msg: &str);
     fn log_error(&self, msg: &str);
 
+    /// Reports progress on a long operation: `label` is the step `current` of `total`, starting
+    /// at 1
+    fn report_progress(&self, current: usize, total: usize, label: &str);
+
+    /// Selects the branches to delete among `branch_infos`. Returning an empty list deletes
+    /// nothing.
     fn select_branches_to_delete(
         &self,
         branch_infos: &[BranchToDeleteInfo],
     ) -> Vec<BranchToDeleteInfo>;
 
+    /// Selects the branches to delete among identical branches whose commit is contained in
+    /// another branch, so all of them can be deleted
     fn select_identical_branches_to_delete(&self, branches: &[String]) -> Vec<String>;
 
-    fn select_identical_branches_to_delete_keep_one(&self, branches: &[String]) -> Vec<String>;
+    /// Selects the branches to delete among identical branches, at least one must be kept.
+    /// `preferred` is the branch to keep if the user does not select otherwise.
+    fn select_identical_branches_to_delete_keep_one(
+        &self,
+        branches: &[String],
+        preferred: &str,
+    ) -> Vec<String>;
 
+    /// Picks the default branch among `branches`, when it is not configured and cannot be found
+    /// using git. Returning None stops the run with AppError::InterruptedByUser.
     fn select_default_branch(&self, branches: &[String]) -> Option<String>;
+
+    /// Asks for a last confirmation before deleting `branch`. `log` lists the commits which are
+    /// only in `branch`. Only called with --confirm-each.
+    fn confirm_delete(&self, branch: &str, log: &str) -> bool;
+
+    /// Asks for a final confirmation before deleting all the selected `branches`. Not called
+    /// with --no-final-confirm or --confirm-each.
+    fn confirm_bulk_delete(&self, branches: &[String]) -> bool;
+
+    /// Asks for confirmation before deleting `branch` from `remote`. Only called with
+    /// --delete-remote.
+    fn confirm_delete_remote(&self, remote: &str, branch: &str) -> bool;
+
+    /// Lists the branches which are kept, with the reason why. Only called with --explain.
+    fn report_kept(&self, kept: &[(String, String)]);
+
+    fn show_report(&self, report: &Report);
+
+    /// Prints the branches which could be deleted, and the groups of branches pointing to the
+    /// same commit. Only called with --list.
+    fn show_list(&self, deletable: &[String], identical: &[Vec<String>]);
+
+    /// Prints the protected branches, with the reason why they are protected. Only called with
+    /// --show-protected.
+    fn show_protected(&self, protected: &[ProtectedBranch]);
+
+    /// Prints the branches present before the run, and the ones which would remain after it.
+    /// Only called with --dry-run, at the end of a successful run.
+    fn report_plan(&self, before: &[String], after: &[String]);
+
+    /// Called at the end of a run, even if it failed
+    fn show_summary(&self, summary: &RunReport);
 }
@@ -16,13 +16,70 @@
  * You should have received a copy of the GNU General Public License along with
  * this program.  If not, see <http://www.gnu.org/licenses/>.
  */
-use crate::appui::{AppUi, BranchToDeleteInfo};
+use crate::appui::{AppUi, BranchToDeleteInfo, Verbosity};
+use crate::report::{ProtectedBranch, Report, RunReport};
 use crate::tui;
 
-pub struct BatchAppUi;
+pub struct BatchAppUi {
+    verbosity: Verbosity,
+}
+
+impl BatchAppUi {
+    pub fn new(verbosity: Verbosity) -> BatchAppUi {
+        BatchAppUi { verbosity }
+    }
+}
+
+/// Policy used to decide which branches to delete from a group of identical branches when one
+/// of them must be kept: keep `keep` and delete the others, in alphabetical order
+pub fn select_all_but_one(branches: &[String], keep: &str) -> Vec<String> {
+    let mut to_delete: Vec<String> = branches.iter().filter(|x| *x != keep).cloned().collect();
+    to_delete.sort();
+    to_delete
+}
+
+/// Policy used to pick the default branch without asking: "main" or "master", whichever exists
+/// first. Returns None if neither exists.
+pub fn guess_default_branch(branches: &[String]) -> Option<String> {
+    ["main", "master"]
+        .iter()
+        .find(|x| branches.iter().any(|branch| branch == *x))
+        .map(|x| x.to_string())
+}
+
+/// Prints the deletable branches, and the groups of identical branches, for --list
+pub fn print_list(deletable: &[String], identical: &[Vec<String>]) {
+    println!("Deletable branches:");
+    for branch in deletable {
+        println!("- {}", branch);
+    }
+    if !identical.is_empty() {
+        println!("Identical branches:");
+        for branches in identical {
+            println!("- {}", branches.join(", "));
+        }
+    }
+}
+
+/// Prints the protected branches, with the reason why they are protected
+pub fn print_protected(protected: &[ProtectedBranch]) {
+    println!("Protected branches:");
+    for branch in protected {
+        println!("- {} ({})", branch.name, branch.reason);
+    }
+}
+
+/// Prints the branches present before the run, and the ones which would remain after it
+pub fn print_plan(before: &[String], after: &[String]) {
+    println!("Before: [{}]", before.join(", "));
+    println!("After: [{}]", after.join(", "));
+}
 
 impl AppUi for BatchAppUi {
     fn log_info(&self, msg: &str) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
         tui::log_info(msg);
     }
 
@@ -34,6 +91,10 @@ impl AppUi for BatchAppUi {
         tui::log_error(msg);
     }
 
+    fn report_progress(&self, current: usize, total: usize, label: &str) {
+        self.log_info(&format!("[{}/{}] {}", current, total, label));
+    }
+
     fn select_branches_to_delete(
         &self,
         branch_infos: &[BranchToDeleteInfo],
@@ -45,14 +106,54 @@ impl AppUi for BatchAppUi {
         branches.to_vec()
     }
 
-    fn select_identical_branches_to_delete_keep_one(&self, branches: &[String]) -> Vec<String> {
-        let mut to_delete = branches.to_vec();
-        to_delete.sort();
-        to_delete.remove(0);
-        to_delete
+    fn select_identical_branches_to_delete_keep_one(
+        &self,
+        branches: &[String],
+        preferred: &str,
+    ) -> Vec<String> {
+        select_all_but_one(branches, preferred)
+    }
+
+    fn select_default_branch(&self, branches: &[String]) -> Option<String> {
+        guess_default_branch(branches)
+    }
+
+    fn confirm_delete(&self, _branch: &str, _log: &str) -> bool {
+        true
+    }
+
+    fn confirm_bulk_delete(&self, _branches: &[String]) -> bool {
+        true
+    }
+
+    fn confirm_delete_remote(&self, _remote: &str, _branch: &str) -> bool {
+        // Batch mode is only used with --yes
+        true
+    }
+
+    fn report_kept(&self, kept: &[(String, String)]) {
+        for (branch, reason) in kept {
+            println!("{} kept: {}", branch, reason);
+        }
+    }
+
+    fn show_report(&self, report: &Report) {
+        print!("{}", report);
+    }
+
+    fn show_list(&self, deletable: &[String], identical: &[Vec<String>]) {
+        print_list(deletable, identical);
+    }
+
+    fn show_protected(&self, protected: &[ProtectedBranch]) {
+        print_protected(protected);
+    }
+
+    fn report_plan(&self, before: &[String], after: &[String]) {
+        print_plan(before, after);
     }
 
-    fn select_default_branch(&self, _branches: &[String]) -> Option<String> {
-        None
+    fn show_summary(&self, _summary: &RunReport) {
+        // Actions have already been logged as they happened
     }
 }
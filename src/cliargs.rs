@@ -16,23 +16,361 @@
  * You should have received a copy of the GNU General Public License along with
  * this program.  If not, see <http://www.gnu.org/licenses/>.
  */
+use std::path::PathBuf;
+use std::str::FromStr;
+
 use structopt::StructOpt;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Invalid format '{}', must be 'text' or 'json'", s)),
+        }
+    }
+}
+
+/// Format of the events streamed with --events
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventFormat {
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl FromStr for EventFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(EventFormat::Ndjson),
+            _ => Err(format!("Invalid event format '{}', must be 'ndjson'", s)),
+        }
+    }
+}
+
+/// How tracking branches are updated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateStrategy {
+    /// Fast-forward, skip branches which have diverged from their upstream
+    FfOnly,
+    /// Rebase local commits on top of the upstream
+    Rebase,
+}
+
+impl FromStr for UpdateStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ff-only" => Ok(UpdateStrategy::FfOnly),
+            "rebase" => Ok(UpdateStrategy::Rebase),
+            _ => Err(format!(
+                "Invalid update strategy '{}', must be 'ff-only' or 'rebase'",
+                s
+            )),
+        }
+    }
+}
+
+/// Order of the branches in the list of branches to delete
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Name,
+    /// Oldest last commit first
+    Date,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortOrder::Name),
+            "date" => Ok(SortOrder::Date),
+            _ => Err(format!(
+                "Invalid sort order '{}', must be 'name' or 'date'",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(StructOpt)]
 /// Keep a git repository clean and tidy.
 ///
 /// Branches can be declared as protected from suppression using `git config --add
 /// git-bonsai.protected-branches <branch>`.
 pub struct CliArgs {
+    /// Run as if git-bonsai was started in this directory instead of the current one
+    #[structopt(short = "C", long = "repo", default_value = ".")]
+    pub repo: String,
+
     /// Other branches to protect from suppression.
     #[structopt(short = "x", long)]
     pub excluded: Vec<String>,
 
+    /// Protect all branches matching this glob pattern from suppression. Can be repeated.
+    #[structopt(long = "exclude-pattern")]
+    pub exclude_pattern: Vec<String>,
+
+    /// Also delete branches whose changes are already in the default branch, even if their
+    /// commits are not, as happens after a squash-merge. This is a heuristic, so such branches
+    /// are labeled "squash-merged".
+    #[structopt(long = "detect-squashed")]
+    pub detect_squashed: bool,
+
+    /// Also delete branches whose commits have all been applied to the default branch with
+    /// different sha1s, as happens after a rebase-merge. Such branches are labeled
+    /// "rebase-merged".
+    #[structopt(long = "detect-rebased")]
+    pub detect_rebased: bool,
+
+    /// Only consider branches matching this glob pattern for deletion. Can be repeated.
+    /// Patterns are matched against the full branch name, `*` matches any sequence of
+    /// characters, including `/`.
+    #[structopt(long = "include")]
+    pub include: Vec<String>,
+
+    /// Name of the remote to fetch from and to update tracking branches from [default: origin]
+    #[structopt(long = "remote")]
+    pub remote: Option<String>,
+
     /// Do not fetch changes
     #[structopt(long = "no-fetch")]
     pub no_fetch: bool,
 
+    /// Fetch all remotes instead of only the one selected with --remote, and update branches
+    /// tracking any of them
+    #[structopt(long = "fetch-all")]
+    pub fetch_all: bool,
+
+    /// Extra argument to pass to `git fetch`, like --tags. Can be repeated.
+    #[structopt(
+        long = "fetch-arg",
+        value_name = "arg",
+        number_of_values = 1,
+        allow_hyphen_values = true
+    )]
+    pub fetch_args: Vec<String>,
+
+    /// How to update tracking branches: "ff-only" skips branches which have diverged from their
+    /// upstream, "rebase" rebases their local commits on top of the upstream. If the rebase
+    /// fails, it is aborted and the branch is left unchanged.
+    #[structopt(long = "update-strategy", default_value = "ff-only")]
+    pub update_strategy: UpdateStrategy,
+
+    /// Do not update tracking branches to their remote counterparts
+    #[structopt(long = "no-update")]
+    pub no_update: bool,
+
+    /// Only delete branches whose last commit is older than this number of days
+    #[structopt(long = "max-age")]
+    pub max_age: Option<u64>,
+
+    /// Only delete branches merged into this branch
+    #[structopt(long = "merged-into")]
+    pub merged_into: Option<String>,
+
+    /// Only delete branches merged into the current branch, like --merged-into with the current
+    /// branch. The current branch itself is protected.
+    #[structopt(long = "current-only", conflicts_with = "merged-into")]
+    pub current_only: bool,
+
+    /// Never delete branches merged into this branch, even if they are also merged into other
+    /// branches
+    #[structopt(long = "keep-merged-into", value_name = "branch")]
+    pub keep_merged_into: Option<String>,
+
+    /// Never delete branches whose last commit is tagged
+    #[structopt(long = "protect-tagged")]
+    pub protect_tagged: bool,
+
+    /// Never delete branches whose upstream still exists on the remote
+    #[structopt(long = "keep-tracked")]
+    pub keep_tracked: bool,
+
+    /// Before looking for merged branches, delete the branches pointing to the tip of the base
+    /// branch, after confirmation
+    #[structopt(long = "prune-empty")]
+    pub prune_empty: bool,
+
+    /// Do not determine the default branch. Without a default branch, some safety checks are
+    /// weaker, so deleting branches requires --merged-into
+    #[structopt(long = "exclude-default-branch-check")]
+    pub exclude_default_branch_check: bool,
+
+    /// Only fetch changes and update tracking branches, do not delete any branch
+    #[structopt(long = "maintain")]
+    pub maintain: bool,
+
+    /// Never delete branches checked out in a worktree, including the current branch
+    #[structopt(long = "keep-if-open-worktree")]
+    pub keep_if_open_worktree: bool,
+
+    /// Recreate the branches deleted by the last run
+    #[structopt(long = "restore")]
+    pub restore: bool,
+
+    /// Only print a report of the protected, deletable and skipped branches. Does not fetch,
+    /// update or delete anything
+    #[structopt(long = "report-only")]
+    pub report_only: bool,
+
+    /// List the branches which could be deleted, then exit without fetching, updating or deleting
+    /// anything
+    #[structopt(long = "list")]
+    pub list: bool,
+
+    /// Print the protected branches and why they are protected, then exit without fetching,
+    /// updating or deleting anything
+    #[structopt(long = "show-protected")]
+    pub show_protected: bool,
+
+    /// Output format: "text" or "json". In json mode, the report printed by --report-only or a
+    /// summary of the actions is printed to stdout, and no question is asked
+    #[structopt(long = "format", default_value = "text")]
+    pub format: OutputFormat,
+
+    /// Stream what happens to stdout as events, instead of logging it. Only "ndjson" is
+    /// supported: each event is a JSON object on its own line. No question is asked, like in
+    /// json mode.
+    #[structopt(long = "events", value_name = "format", conflicts_with = "format")]
+    pub events: Option<EventFormat>,
+
+    /// Show the commits of each selected branch which are not in the base branch, and ask for
+    /// confirmation before deleting it
+    #[structopt(long = "confirm-each")]
+    pub confirm_each: bool,
+
+    /// Stop at the first branch which cannot be deleted, instead of skipping it
+    #[structopt(long = "fail-fast")]
+    pub fail_fast: bool,
+
+    /// Explain why each branch which is not deleted is kept
+    #[structopt(long = "explain")]
+    pub explain: bool,
+
+    /// Kill git commands which take longer than this number of seconds
+    #[structopt(long = "timeout")]
+    pub timeout: Option<u64>,
+
+    /// In interactive mode, do not select any branch by default: each branch to delete must be
+    /// explicitly checked
+    #[structopt(long = "no-preselect")]
+    pub no_preselect: bool,
+
+    /// Instead of deleting branches, rename them by adding this prefix to their names, for
+    /// example "archive/". Branches starting with this prefix are then protected.
+    #[structopt(long = "archive-prefix")]
+    pub archive_prefix: Option<String>,
+
+    /// Print the git commands before running them. Setting the GB_DEBUG environment variable
+    /// has the same effect.
+    #[structopt(short = "v", long = "verbose")]
+    pub verbose: bool,
+
+    /// After deleting a local branch, also delete the branch with the same name from the remote,
+    /// if all its commits are in local branches. Asks for confirmation unless --yes is set.
+    #[structopt(long = "delete-remote")]
+    pub delete_remote: bool,
+
+    /// Shell command to run after each deleted branch. The branch name is passed as the first
+    /// argument and in the GB_BRANCH environment variable.
+    #[structopt(long = "on-delete", value_name = "command")]
+    pub on_delete: Option<String>,
+
+    /// Append a timestamped line to this file for each deleted or updated branch
+    #[structopt(long = "log-file", value_name = "path", parse(from_os_str))]
+    pub log_file: Option<PathBuf>,
+
+    /// Never delete the current branch
+    #[structopt(long = "protect-current")]
+    pub protect_current: bool,
+
+    /// Show a summary of the changes between the base branch and each branch in the list of
+    /// branches to delete
+    #[structopt(long = "show-stat")]
+    pub show_stat: bool,
+
+    /// Order of the branches to delete: "name", or "date" to list the branches whose last commit
+    /// is the oldest first
+    #[structopt(long = "sort", default_value = "name")]
+    pub sort: SortOrder,
+
+    /// Ignore the default branch stored in git config and determine it again
+    #[structopt(long = "reset-default-branch")]
+    pub reset_default_branch: bool,
+
+    /// Use this branch as the default branch for this run, without determining it nor storing
+    /// it in git config
+    #[structopt(long = "assume-default", value_name = "branch")]
+    pub assume_default: Option<String>,
+
+    /// Branch to check out while deleting branches, instead of the default branch. This branch
+    /// is never deleted.
+    #[structopt(long = "park-branch", value_name = "branch")]
+    pub park_branch: Option<String>,
+
+    /// Only delete branches whose last commit is contained in <ref>, for example the tag of the
+    /// last release
+    #[structopt(long = "before-ref", value_name = "ref")]
+    pub before_ref: Option<String>,
+
+    /// Only delete branches merged into the base branch more than this number of days ago,
+    /// according to the date of their merge commit. For branches merged without a merge
+    /// commit, the date of their last commit is used.
+    #[structopt(long = "merged-before", value_name = "days")]
+    pub merged_before: Option<u64>,
+
+    /// In interactive mode, do not ask for a final confirmation before deleting the selected
+    /// branches
+    #[structopt(long = "no-final-confirm")]
+    pub no_final_confirm: bool,
+
+    /// Show what would be done, without fetching, updating or deleting anything
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Do not use colors. Colors are also disabled if the NO_COLOR environment variable is set or
+    /// if the output is not a terminal.
+    #[structopt(long = "no-color")]
+    pub no_color: bool,
+
+    /// Only print warnings and errors
+    #[structopt(short = "q", long = "quiet")]
+    pub quiet: bool,
+
     /// Do not ask for confirmation
     #[structopt(short = "y", long = "yes")]
     pub yes: bool,
+
+    /// Select the identical branches and the merged branches to delete in a single prompt,
+    /// instead of one prompt per group of identical branches followed by one for the merged
+    /// branches
+    #[structopt(long = "single-prompt")]
+    pub single_prompt: bool,
+
+    /// When identical branches are not contained in any other branch, automatically keep the
+    /// first one in alphabetical order and delete the others, even in interactive mode
+    #[structopt(long = "auto-keep-one")]
+    pub auto_keep_one: bool,
+
+    /// If the working tree contains changes, stash them before running and restore them
+    /// afterwards
+    #[structopt(long = "autostash")]
+    pub autostash: bool,
+
+    /// Run even if the working tree contains untracked files. Staged and unstaged changes still
+    /// prevent git-bonsai from running.
+    #[structopt(long = "allow-untracked")]
+    pub allow_untracked: bool,
 }
@@ -0,0 +1,110 @@
+/*
+ * Copyright 2026 Aurélien Gâteau <mail@agateau.com>
+ *
+ * This file is part of git-bonsai.
+ *
+ * Git-bonsai is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 3 of the License, or (at your option)
+ * any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+/**
+ * This module reads the optional .git-bonsai.toml file stored at the root of a repository
+ */
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+pub static CONFIG_FILE_NAME: &str = ".git-bonsai.toml";
+
+/// The content of a .git-bonsai.toml file. Values set on the command line take precedence over
+/// the ones set here.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Branches which must never be deleted
+    pub protected: Vec<String>,
+    pub no_fetch: Option<bool>,
+    pub remote: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigFileError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFileError::Io(x) => write!(f, "Failed to read {}: {}", CONFIG_FILE_NAME, x),
+            ConfigFileError::Parse(x) => write!(f, "Failed to parse {}: {}", CONFIG_FILE_NAME, x),
+        }
+    }
+}
+
+impl ConfigFile {
+    /// Loads the config file from `repo_root`. Returns the default config if there is no such
+    /// file.
+    pub fn load(repo_root: &Path) -> Result<ConfigFile, ConfigFileError> {
+        let path = repo_root.join(CONFIG_FILE_NAME);
+        if !path.exists() {
+            return Ok(ConfigFile::default());
+        }
+        let content = fs::read_to_string(&path).map_err(ConfigFileError::Io)?;
+        ConfigFile::parse(&content)
+    }
+
+    fn parse(content: &str) -> Result<ConfigFile, ConfigFileError> {
+        toml::from_str(content).map_err(ConfigFileError::Parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full_file() {
+        let config = ConfigFile::parse(
+            "protected = [\"main\", \"release\"]\nno_fetch = true\nremote = \"upstream\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            ConfigFile {
+                protected: vec!["main".to_string(), "release".to_string()],
+                no_fetch: Some(true),
+                remote: Some("upstream".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_empty_file() {
+        assert_eq!(ConfigFile::parse("").unwrap(), ConfigFile::default());
+    }
+
+    #[test]
+    fn parse_invalid_file() {
+        assert!(ConfigFile::parse("protected = \"main\"").is_err());
+        assert!(ConfigFile::parse("unknown_key = 1").is_err());
+    }
+
+    #[test]
+    fn load_missing_file() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        assert_eq!(ConfigFile::load(dir.path()).unwrap(), ConfigFile::default());
+    }
+}
@@ -16,25 +16,73 @@
  * You should have received a copy of the GNU General Public License along with
  * this program.  If not, see <http://www.gnu.org/licenses/>.
  */
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt;
 use std::fs::File;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // Define this environment variable to print all executed git commands to stderr
 const GIT_BONSAI_DEBUG: &str = "GB_DEBUG";
 
-// If a branch is checked out in a separate worktree, then `git branch` prefixes it with this
-// string
-const WORKTREE_BRANCH_PREFIX: &str = "+ ";
+// `git branch` prefixes the current branch with this string
+const CURRENT_BRANCH_PREFIX: &str = "* ";
+
+/// Parses a line of `git branch` output, returning the line without its 2-character status
+/// marker. Returns None for the detached HEAD pseudo-branch.
+fn parse_branch_line(line: &str) -> Option<&str> {
+    let rest = line.get(2..)?;
+    // Detached HEAD is listed as "(HEAD detached at 1234abc)", or "(no branch, rebasing foo)"
+    // during a rebase. Branch names cannot start with "(", so this is not ambiguous.
+    if rest.starts_with('(') {
+        return None;
+    }
+    Some(rest)
+}
+
+/// Parses a line of `git status --porcelain` output, returning its index and work tree status
+/// codes
+fn parse_status_line(line: &str) -> Result<(char, char), GitError> {
+    let mut chars = line.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(index), Some(work_tree), Some(' ')) => Ok((index, work_tree)),
+        _ => Err(GitError::ParseError {
+            line: line.to_string(),
+        }),
+    }
+}
+
+// Before deleting a branch, git-bonsai stores a ref pointing to its commit under this prefix, so
+// that the branch can be restored
+const BACKUP_REF_PREFIX: &str = "refs/bonsai/";
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum GitError {
     FailedToRunGit,
-    CommandFailed { exit_code: i32 },
+    CommandFailed {
+        command: String,
+        exit_code: i32,
+        stderr: String,
+    },
+    /// The command failed because another git process holds a lock on the index or on a ref
+    Locked {
+        command: String,
+        stderr: String,
+    },
     TerminatedBySignal,
+    TimedOut {
+        command: String,
+    },
     UnexpectedOutput(String),
+    /// A line of git output does not have the expected shape
+    ParseError {
+        line: String,
+    },
+    NoDefaultBranch,
 }
 
 impl fmt::Display for GitError {
@@ -43,57 +91,406 @@ impl fmt::Display for GitError {
             GitError::FailedToRunGit => {
                 write!(f, "Failed to run git")
             }
-            GitError::CommandFailed { exit_code: e } => {
-                write!(f, "Command exited with code {}", e)
+            GitError::CommandFailed {
+                command,
+                exit_code,
+                stderr,
+            } => {
+                write!(f, "'git {}' exited with code {}", command, exit_code)?;
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr)?;
+                }
+                Ok(())
+            }
+            GitError::Locked { command, stderr } => {
+                write!(
+                    f,
+                    "'git {}' failed because the repository is locked ({}). Another git process \
+                    may be running: close it and try again",
+                    command, stderr
+                )
             }
             GitError::TerminatedBySignal => {
                 write!(f, "Terminated by signal")
             }
+            GitError::TimedOut { command } => {
+                write!(f, "'git {}' timed out", command)
+            }
             GitError::UnexpectedOutput(message) => {
                 write!(f, "UnexpectedOutput: {}", message)
             }
+            GitError::ParseError { line } => {
+                write!(f, "Could not parse git output line '{}'", line)
+            }
+            GitError::NoDefaultBranch => {
+                write!(f, "Could not find the default branch")
+            }
+        }
+    }
+}
+
+/// Creates the error for a git command which exited with a non-zero code, recognizing failures
+/// caused by another git process holding a lock
+fn command_failed_error(command: String, exit_code: i32, stderr: String) -> GitError {
+    if stderr.contains("index.lock") || stderr.contains("cannot lock ref") {
+        GitError::Locked { command, stderr }
+    } else {
+        GitError::CommandFailed {
+            command,
+            exit_code,
+            stderr,
+        }
+    }
+}
+
+/// Oldest git version git-bonsai works with: `git config --type=bool` appeared in git 2.18
+pub const MIN_GIT_VERSION: (u32, u32, u32) = (2, 18, 0);
+
+/// Parses the output of `git --version`, like "git version 2.39.1" or "git version
+/// 2.37.1 (Apple Git-137.1)", into a (major, minor, patch) tuple
+fn parse_git_version(output: &str) -> Result<(u32, u32, u32), GitError> {
+    let unexpected = || GitError::UnexpectedOutput(output.to_string());
+    let version = output
+        .trim()
+        .strip_prefix("git version ")
+        .and_then(|x| x.split_whitespace().next())
+        .ok_or_else(unexpected)?;
+    // Extra components, like in "2.39.5.windows.1", are ignored
+    let mut numbers = version.split('.').map(|x| x.parse::<u32>());
+    match (numbers.next(), numbers.next(), numbers.next()) {
+        (Some(Ok(major)), Some(Ok(minor)), patch) => {
+            Ok((major, minor, patch.and_then(|x| x.ok()).unwrap_or(0)))
+        }
+        _ => Err(unexpected()),
+    }
+}
+
+/// Parses a line of `git for-each-ref --format='%(refname)%00%(objectname)'` output into a
+/// (branch, sha1) tuple. The NUL separator cannot be part of a ref name, so it is not ambiguous.
+fn parse_branch_sha1_line(line: &str) -> Result<(String, String), GitError> {
+    let parse_error = || GitError::ParseError {
+        line: line.to_string(),
+    };
+    let (refname, sha1) = line.split_once('\0').ok_or_else(parse_error)?;
+    let branch = refname
+        .strip_prefix("refs/heads/")
+        .ok_or_else(parse_error)?;
+    if sha1.is_empty() {
+        return Err(parse_error());
+    }
+    Ok((branch.to_string(), sha1.to_string()))
+}
+
+/// Parses a line of `git for-each-ref --format='%(refname) %(upstream:short) %(upstream:track)'`
+/// output into a (branch, upstream, gone) tuple
+fn parse_branch_upstream_line(line: &str) -> Result<(String, Option<String>, bool), GitError> {
+    let parse_error = || GitError::ParseError {
+        line: line.to_string(),
+    };
+    let mut it = line.splitn(3, ' ');
+    let branch = it
+        .next()
+        .and_then(|x| x.strip_prefix("refs/heads/"))
+        .ok_or_else(parse_error)?;
+    let upstream = it.next().ok_or_else(parse_error)?;
+    let track = it.next().ok_or_else(parse_error)?;
+    let upstream = if upstream.is_empty() {
+        None
+    } else {
+        Some(upstream.to_string())
+    };
+    Ok((branch.to_string(), upstream, track == "[gone]"))
+}
+
+/// Parses a line of `git for-each-ref --format='%(objectname) %(refname)'` output for a backup
+/// ref into a (branch, sha1) tuple
+fn parse_backup_ref_line(line: &str) -> Result<(String, String), GitError> {
+    let parse_error = || GitError::ParseError {
+        line: line.to_string(),
+    };
+    let (sha1, refname) = line.split_once(' ').ok_or_else(parse_error)?;
+    let branch = refname
+        .strip_prefix(BACKUP_REF_PREFIX)
+        .ok_or_else(parse_error)?;
+    Ok((branch.to_string(), sha1.to_string()))
+}
+
+/// A worktree, as listed by `git worktree list --porcelain`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Worktree {
+    pub path: PathBuf,
+    /// The branch checked out in the worktree, None if HEAD is detached or the worktree is bare
+    pub branch: Option<String>,
+    pub bare: bool,
+    pub detached: bool,
+}
+
+/// Parses the output of `git worktree list --porcelain`
+fn parse_worktree_list(stdout: &str) -> Result<Vec<Worktree>, GitError> {
+    /* Output looks like this:
+     *
+     * worktree /path/to/main
+     * HEAD 960389f1c69e8b9c3fe06d29866d0d193375a6cb
+     * branch refs/heads/master
+     *
+     * worktree /path/to/other
+     * HEAD 960389f1c69e8b9c3fe06d29866d0d193375a6cb
+     * detached
+     */
+    let mut worktrees = Vec::new();
+    for block in stdout.split("\n\n").filter(|x| !x.trim().is_empty()) {
+        let mut lines = block.lines();
+        let first_line = lines.next().unwrap_or_default();
+        let path = first_line
+            .strip_prefix("worktree ")
+            .ok_or_else(|| GitError::ParseError {
+                line: first_line.to_string(),
+            })?;
+        let mut worktree = Worktree {
+            path: PathBuf::from(path),
+            branch: None,
+            bare: false,
+            detached: false,
+        };
+        for line in lines {
+            if let Some(branch) = line.strip_prefix("branch refs/heads/") {
+                worktree.branch = Some(branch.to_string());
+            } else if line == "bare" {
+                worktree.bare = true;
+            } else if line == "detached" {
+                worktree.detached = true;
+            }
+        }
+        worktrees.push(worktree);
+    }
+    Ok(worktrees)
+}
+
+/// Filters the branches returned by `Repository::list_branches_filtered()`
+pub enum MergeFilter<'a> {
+    All,
+    /// Branches whose tip is reachable from the given commit, like `git branch --merged`
+    MergedInto(&'a str),
+    /// Branches whose tip is not reachable from the given commit, like `git branch --no-merged`
+    NotMerged(&'a str),
+}
+
+/// What BranchRestorer checks out when restoring
+enum RestorePoint {
+    Branch(String),
+    /// HEAD was detached at this sha1
+    DetachedHead(String),
+}
+
+impl fmt::Display for RestorePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestorePoint::Branch(branch) => write!(f, "branch {}", branch),
+            RestorePoint::DetachedHead(sha1) => write!(f, "detached HEAD at {}", sha1),
         }
     }
 }
 
 /**
- * Restores the current git branch when dropped
- * Assumes we are on a real branch
+ * Restores the current git branch when dropped, or when restore() is called
+ * If HEAD is detached, restores it to the same commit
+ * If the branch cannot be checked out, checks out the fallback branch instead, if any
+ * Creating a restorer fails if the current branch or commit cannot be found
  */
 pub struct BranchRestorer<'a> {
-    repository: &'a Repository,
-    branch: String,
+    repository: &'a dyn GitRepository,
+    restore_point: RestorePoint,
+    fallback_branch: Option<String>,
+    done: bool,
 }
 
 impl BranchRestorer<'_> {
-    pub fn new(repo: &Repository) -> BranchRestorer {
-        let current_branch = repo.get_current_branch().expect("Can't get current branch");
-        BranchRestorer {
+    pub fn new(repo: &dyn GitRepository) -> Result<BranchRestorer<'_>, GitError> {
+        let restore_point = match repo.current_branch()? {
+            Some(branch) => RestorePoint::Branch(branch),
+            None => RestorePoint::DetachedHead(repo.rev_parse("HEAD")?),
+        };
+        Ok(BranchRestorer {
             repository: repo,
-            branch: current_branch,
+            restore_point,
+            fallback_branch: None,
+            done: false,
+        })
+    }
+
+    pub fn with_fallback<'a>(
+        repo: &'a dyn GitRepository,
+        fallback_branch: &str,
+    ) -> Result<BranchRestorer<'a>, GitError> {
+        let mut restorer = BranchRestorer::new(repo)?;
+        restorer.fallback_branch = Some(fallback_branch.to_string());
+        Ok(restorer)
+    }
+
+    /// Checks out the original branch, or the fallback branch if it fails. Returns the error of
+    /// the last checkout if both failed. Does nothing if restore() has already been called, or if
+    /// the original branch is still checked out.
+    pub fn restore(&mut self) -> Result<(), GitError> {
+        if self.done {
+            return Ok(());
+        }
+        self.done = true;
+        let rev = match &self.restore_point {
+            RestorePoint::Branch(branch) => {
+                if self.repository.current_branch().ok().flatten().as_ref() == Some(branch) {
+                    return Ok(());
+                }
+                branch
+            }
+            RestorePoint::DetachedHead(sha1) => sha1,
+        };
+        let error = match self.repository.checkout(rev) {
+            Ok(()) => return Ok(()),
+            Err(x) => x,
+        };
+        match &self.fallback_branch {
+            Some(fallback_branch) => self.repository.checkout(fallback_branch),
+            None => Err(error),
         }
     }
 }
 
 impl Drop for BranchRestorer<'_> {
     fn drop(&mut self) {
-        if let Err(_x) = self.repository.checkout(&self.branch) {
-            println!("Failed to restore original branch {}", self.branch);
+        if let Err(x) = self.restore() {
+            eprintln!("Failed to restore original {}: {}", self.restore_point, x);
+        }
+    }
+}
+
+/**
+ * Stashes the changes of the working tree, including untracked files, and restores them when
+ * dropped, or when pop() is called
+ */
+pub struct StashGuard<'a> {
+    repository: &'a Repository,
+    done: bool,
+}
+
+impl StashGuard<'_> {
+    pub fn push(repo: &Repository) -> Result<StashGuard<'_>, GitError> {
+        repo.stash_push()?;
+        Ok(StashGuard {
+            repository: repo,
+            done: false,
+        })
+    }
+
+    /// Restores the stashed changes. Does nothing if pop() has already been called.
+    pub fn pop(&mut self) -> Result<(), GitError> {
+        if self.done {
+            return Ok(());
+        }
+        self.done = true;
+        self.repository.stash_pop()
+    }
+}
+
+impl Drop for StashGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(x) = self.pop() {
+            eprintln!(
+                "Failed to restore stashed changes: {}. Use `git stash pop` to restore them.",
+                x
+            );
         }
     }
 }
 
+/// Reads everything from `reader` in a separate thread, so that reading the output of a child
+/// process does not block waiting for it
+fn read_in_thread<R: Read + Send + 'static>(mut reader: R) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = reader.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+/// Like Command::output(), but writes `input` to the standard input of the process, and kills the
+/// process if it does not finish before `timeout`. Returns None in this case.
+fn run_command(
+    cmd: &mut Command,
+    input: Option<&str>,
+    timeout: Option<Duration>,
+) -> std::io::Result<Option<Output>> {
+    let stdin = if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    };
+    cmd.stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = cmd.spawn()?;
+    if let Some(input) = input {
+        let mut stdin = child.stdin.take().unwrap();
+        let input = input.to_string();
+        // Write from a thread, so that the process does not block on a full stdout pipe while we
+        // block writing to its stdin
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    let stdout_reader = read_in_thread(child.stdout.take().unwrap());
+    let stderr_reader = read_in_thread(child.stderr.take().unwrap());
+
+    let status = match timeout {
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if Instant::now() >= deadline {
+                    child.kill()?;
+                    child.wait()?;
+                    // Do not join the reader threads: children of the killed process may still
+                    // hold the pipes open
+                    return Ok(None);
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        None => child.wait()?,
+    };
+    Ok(Some(Output {
+        status,
+        stdout: stdout_reader.join().unwrap(),
+        stderr: stderr_reader.join().unwrap(),
+    }))
+}
+
 pub struct Repository {
     pub path: PathBuf,
+    timeout: Option<Duration>,
+    // If true, print the git commands to stderr, like GB_DEBUG does
+    debug: bool,
 }
 
 impl Repository {
     pub fn new(path: &Path) -> Repository {
         Repository {
             path: path.to_path_buf(),
+            timeout: None,
+            debug: false,
         }
     }
 
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+
+    /// Sets the maximum duration of git commands. They are killed if they take longer.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
     #[allow(dead_code)]
     pub fn clone(path: &Path, url: &str) -> Result<Repository, GitError> {
         let repo = Repository::new(path);
@@ -101,7 +498,51 @@ impl Repository {
         Ok(repo)
     }
 
+    /// Returns the version of the installed git, as a (major, minor, patch) tuple
+    pub fn git_version(&self) -> Result<(u32, u32, u32), GitError> {
+        let out = self.git("--version", &[])?;
+        parse_git_version(&out)
+    }
+
+    /// Returns true if the repository path is inside a git working tree
+    pub fn is_git_repo(&self) -> bool {
+        match self.git("rev-parse", &["--is-inside-work-tree"]) {
+            Ok(out) => out.trim() == "true",
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the root directory of the working tree
+    pub fn get_toplevel(&self) -> Result<PathBuf, GitError> {
+        let out = self.git("rev-parse", &["--show-toplevel"])?;
+        Ok(PathBuf::from(out.trim_end()))
+    }
+
     pub fn git(&self, subcommand: &str, args: &[&str]) -> Result<String, GitError> {
+        let output = self.run_git(subcommand, args, None)?;
+        let out = String::from_utf8(output.stdout).expect("Failed to decode command stdout");
+        Ok(out)
+    }
+
+    /// Like git(), but writes `input` to the standard input of the command
+    fn git_with_input(
+        &self,
+        subcommand: &str,
+        args: &[&str],
+        input: &str,
+    ) -> Result<String, GitError> {
+        let output = self.run_git(subcommand, args, Some(input))?;
+        let out = String::from_utf8(output.stdout).expect("Failed to decode command stdout");
+        Ok(out)
+    }
+
+    /// Runs a git command and returns its raw output, if it succeeded
+    fn run_git(
+        &self,
+        subcommand: &str,
+        args: &[&str],
+        input: Option<&str>,
+    ) -> Result<Output, GitError> {
         let mut cmd = Command::new("git");
         cmd.current_dir(&self.path);
         cmd.env("LANG", "C");
@@ -109,7 +550,7 @@ impl Repository {
         for arg in args {
             cmd.arg(arg);
         }
-        if env::var(GIT_BONSAI_DEBUG).is_ok() {
+        if self.debug || env::var(GIT_BONSAI_DEBUG).is_ok() {
             eprintln!(
                 "DEBUG: pwd={}: git {} {}",
                 self.path.to_str().unwrap(),
@@ -117,39 +558,94 @@ impl Repository {
                 args.join(" ")
             );
         }
-        let output = match cmd.output() {
-            Ok(x) => x,
+        let output = match run_command(&mut cmd, input, self.timeout) {
+            Ok(Some(x)) => x,
+            Ok(None) => {
+                return Err(GitError::TimedOut {
+                    command: format!("{} {}", subcommand, args.join(" ")),
+                });
+            }
             Err(_x) => {
-                println!("Failed to execute process");
+                eprintln!("Failed to execute process");
                 return Err(GitError::FailedToRunGit);
             }
         };
         if !output.status.success() {
-            // TODO: store error message in GitError
-            println!(
-                "{}",
-                String::from_utf8(output.stderr).expect("Failed to decode command stderr")
-            );
             return match output.status.code() {
-                Some(code) => Err(GitError::CommandFailed { exit_code: code }),
+                Some(code) => Err(command_failed_error(
+                    format!("{} {}", subcommand, args.join(" ")),
+                    code,
+                    String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                )),
                 None => Err(GitError::TerminatedBySignal),
             };
         }
-        let out = String::from_utf8(output.stdout).expect("Failed to decode command stdout");
-        Ok(out)
+        Ok(output)
+    }
+
+    pub fn list_remotes(&self) -> Result<Vec<String>, GitError> {
+        let stdout = self.git("remote", &[])?;
+        Ok(stdout.lines().map(|x| x.to_string()).collect())
     }
 
-    pub fn fetch(&self) -> Result<(), GitError> {
-        self.git("fetch", &["--prune"])?;
+    pub fn fetch(&self, remote: &str) -> Result<(), GitError> {
+        self.fetch_with_args(&["--prune", remote])
+    }
+
+    /// Runs `git fetch` with `args`, which must include the remote, or `--all`
+    pub fn fetch_with_args(&self, args: &[&str]) -> Result<(), GitError> {
+        self.git("fetch", args)?;
         Ok(())
     }
 
+    /// Fetches all remotes
+    pub fn fetch_all(&self) -> Result<(), GitError> {
+        self.fetch_with_args(&["--all", "--prune"])
+    }
+
+    /// Shows what `fetch()` would do, without updating any ref. Returns git report of what
+    /// would be fetched.
+    pub fn fetch_dry_run(&self, remote: &str) -> Result<String, GitError> {
+        let output = self.run_git("fetch", &["--prune", "--dry-run", remote], None)?;
+        let out = String::from_utf8(output.stderr).expect("Failed to decode command stderr");
+        Ok(out)
+    }
+
     /// Reads config keys defined with `git config --add <key> <value>`
     pub fn get_config_keys(&self, key: &str) -> Result<Vec<String>, GitError> {
-        let stdout = match self.git("config", &["--get-all", key]) {
+        self.get_config_keys_internal(&["--get-all", key])
+    }
+
+    /// Like get_config_keys(), but only reads the global git config (~/.gitconfig)
+    pub fn get_global_config_keys(&self, key: &str) -> Result<Vec<String>, GitError> {
+        self.get_config_keys_internal(&["--global", "--get-all", key])
+    }
+
+    /// Reads a single-value config key. If the key is defined several times, the last value wins,
+    /// like git does.
+    pub fn get_config_value(&self, key: &str) -> Result<Option<String>, GitError> {
+        Ok(self.get_config_keys(key)?.pop())
+    }
+
+    /// Reads a boolean config key. Accepts all the spellings git accepts, like "yes" or "on".
+    pub fn get_config_bool(&self, key: &str) -> Result<Option<bool>, GitError> {
+        match self
+            .get_config_keys_internal(&["--type=bool", "--get", key])?
+            .pop()
+            .as_deref()
+        {
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(x) => Err(GitError::UnexpectedOutput(x.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn get_config_keys_internal(&self, args: &[&str]) -> Result<Vec<String>, GitError> {
+        let stdout = match self.git("config", args) {
             Ok(x) => x,
             Err(x) => match x {
-                GitError::CommandFailed { exit_code: 1 } => {
+                GitError::CommandFailed { exit_code: 1, .. } => {
                     // Happens when reading a non-existing key
                     return Ok([].to_vec());
                 }
@@ -168,8 +664,51 @@ impl Repository {
         Ok(())
     }
 
-    pub fn find_default_branch(&self) -> Result<String, GitError> {
-        let stdout = self.git("ls-remote", &["--symref", "origin", "HEAD"])?;
+    /// Adds a value to a multi-valued config key
+    pub fn add_config_key(&self, key: &str, value: &str) -> Result<(), GitError> {
+        self.git("config", &["--add", key, value])?;
+        Ok(())
+    }
+
+    /// Finds the default branch, trying these strategies in order:
+    ///
+    /// 1. The local copy of the remote HEAD (refs/remotes/<remote>/HEAD)
+    /// 2. The HEAD of the remote repository
+    /// 3. The `init.defaultBranch` git config key, if this branch exists
+    /// 4. The `main` or `master` branch, if it exists
+    pub fn find_default_branch(&self, remote: &str) -> Result<String, GitError> {
+        if let Ok(branch) = self.find_default_branch_from_remote_ref(remote) {
+            return Ok(branch);
+        }
+        if let Ok(branch) = self.find_default_branch_from_remote_head(remote) {
+            return Ok(branch);
+        }
+        let candidates = self
+            .get_config_keys("init.defaultBranch")
+            .unwrap_or_default()
+            .into_iter()
+            .chain(["main".to_string(), "master".to_string()]);
+        for branch in candidates {
+            if self.rev_parse(&format!("refs/heads/{}", branch)).is_ok() {
+                return Ok(branch);
+            }
+        }
+        Err(GitError::NoDefaultBranch)
+    }
+
+    fn find_default_branch_from_remote_ref(&self, remote: &str) -> Result<String, GitError> {
+        let remote_head = format!("refs/remotes/{}/HEAD", remote);
+        let stdout = self.git("symbolic-ref", &["--quiet", &remote_head])?;
+        let prefix = format!("refs/remotes/{}/", remote);
+        stdout
+            .trim()
+            .strip_prefix(&prefix)
+            .map(|x| x.to_string())
+            .ok_or_else(|| GitError::UnexpectedOutput(format!("unexpected ref: {}", stdout)))
+    }
+
+    fn find_default_branch_from_remote_head(&self, remote: &str) -> Result<String, GitError> {
+        let stdout = self.git("ls-remote", &["--symref", remote, "HEAD"])?;
         /* Output looks like this:
          *
          * ref: refs/heads/master\tHEAD
@@ -193,54 +732,191 @@ impl Repository {
     }
 
     pub fn list_branches(&self) -> Result<Vec<String>, GitError> {
-        self.list_branches_internal(&[])
+        self.list_branches_filtered(MergeFilter::All)
     }
 
-    pub fn list_branches_with_sha1s(&self) -> Result<Vec<(String, String)>, GitError> {
-        let mut list: Vec<(String, String)> = Vec::new();
+    pub fn list_branches_filtered(&self, filter: MergeFilter) -> Result<Vec<String>, GitError> {
+        match filter {
+            MergeFilter::All => self.list_branches_internal(&[]),
+            MergeFilter::MergedInto(target) => self.list_branches_internal(&["--merged", target]),
+            MergeFilter::NotMerged(target) => self.list_branches_internal(&["--no-merged", target]),
+        }
+    }
 
-        let lines = self.list_branches_internal(&["-v"])?;
+    /// Returns the branches whose commits are all in `base`, including `base` itself
+    pub fn list_merged_branches(&self, base: &str) -> Result<Vec<String>, GitError> {
+        self.list_branches_filtered(MergeFilter::MergedInto(base))
+    }
 
-        for line in lines {
-            let mut it = line.split_whitespace();
-            let branch = it.next().unwrap().to_string();
-            let sha1 = it.next().unwrap().to_string();
-            list.push((branch, sha1));
-        }
-        Ok(list)
+    /// Returns a list of (branch, sha1), with full sha1s
+    pub fn list_branches_with_sha1s(&self) -> Result<Vec<(String, String)>, GitError> {
+        // Unlike `git branch -v`, for-each-ref does not mix the commit message with the sha1
+        let stdout = self.git(
+            "for-each-ref",
+            &["--format=%(refname)%00%(objectname)", "refs/heads"],
+        )?;
+        stdout.lines().map(parse_branch_sha1_line).collect()
     }
 
+    /// Runs `git branch` with `args`, returning the lines without their status marker
     fn list_branches_internal(&self, args: &[&str]) -> Result<Vec<String>, GitError> {
-        let mut branches: Vec<String> = Vec::new();
-
         let stdout = self.git("branch", args)?;
-
-        for line in stdout.lines() {
-            if line.starts_with(WORKTREE_BRANCH_PREFIX) {
-                continue;
-            }
-            let branch = line.get(2..).expect("Invalid branch name");
-            branches.push(branch.to_string());
-        }
-        Ok(branches)
+        Ok(stdout
+            .lines()
+            .filter_map(parse_branch_line)
+            .map(|x| x.to_string())
+            .collect())
     }
 
     pub fn list_branches_containing(&self, commit: &str) -> Result<Vec<String>, GitError> {
         self.list_branches_internal(&["--contains", commit])
     }
 
-    pub fn list_tracking_branches(&self) -> Result<Vec<String>, GitError> {
-        let mut branches: Vec<String> = Vec::new();
-
-        let lines = self.list_branches_internal(&["-vv"])?;
+    /// Returns a map of branch => branches containing it, including itself. This gives the same
+    /// result as calling list_branches_containing() for each branch, but runs a single walk of
+    /// the history instead of one git command per branch.
+    pub fn build_containment_map(&self) -> Result<HashMap<String, HashSet<String>>, GitError> {
+        let branches = self.list_branches()?;
+        let words = branches.len().div_ceil(64);
+
+        // Map the tip of each branch to the set of branches containing it, stored as a bitset
+        // of indices in `branches`
+        let mut tip_sha1s: HashMap<String, String> = HashMap::new();
+        for line in self
+            .git(
+                "for-each-ref",
+                &["--format=%(refname) %(objectname)", "refs/heads"],
+            )?
+            .lines()
+        {
+            if let Some((refname, sha1)) = line.split_once(' ') {
+                if let Some(branch) = refname.strip_prefix("refs/heads/") {
+                    tip_sha1s.insert(branch.to_string(), sha1.to_string());
+                }
+            }
+        }
+        let mut containers: HashMap<String, Vec<u64>> = HashMap::new();
+        for (idx, branch) in branches.iter().enumerate() {
+            let sha1 = tip_sha1s
+                .get(branch)
+                .ok_or_else(|| GitError::UnexpectedOutput(format!("No sha1 for {}", branch)))?;
+            let bits = containers
+                .entry(sha1.clone())
+                .or_insert_with(|| vec![0; words]);
+            bits[idx / 64] |= 1 << (idx % 64);
+        }
 
-        for line in lines {
-            if line.contains("[origin/") && !line.contains(": gone]") {
-                let branch = line.split(' ').next();
-                branches.push(branch.unwrap().to_string());
+        // Walk the history, children before parents, propagating the containing branches of
+        // each commit to its parents
+        let tips: HashSet<&str> = tip_sha1s.values().map(|x| x.as_str()).collect();
+        let mut tip_containers: HashMap<String, Vec<u64>> = HashMap::new();
+        let out = self.git("rev-list", &["--topo-order", "--parents", "--branches"])?;
+        for line in out.lines() {
+            let mut it = line.split(' ');
+            let sha1 = it.next().ok_or_else(|| GitError::ParseError {
+                line: line.to_string(),
+            })?;
+            let bits = match containers.remove(sha1) {
+                Some(x) => x,
+                // Not reachable from any listed branch
+                None => continue,
+            };
+            for parent in it {
+                let parent_bits = containers
+                    .entry(parent.to_string())
+                    .or_insert_with(|| vec![0; words]);
+                for (parent_word, word) in parent_bits.iter_mut().zip(&bits) {
+                    *parent_word |= word;
+                }
+            }
+            if tips.contains(sha1) {
+                tip_containers.insert(sha1.to_string(), bits);
             }
         }
-        Ok(branches)
+
+        let mut map: HashMap<String, HashSet<String>> = HashMap::new();
+        for branch in &branches {
+            let bits = tip_sha1s
+                .get(branch)
+                .and_then(|x| tip_containers.get(x))
+                .ok_or_else(|| {
+                    GitError::UnexpectedOutput(format!("{} not found in rev-list", branch))
+                })?;
+            let containing_branches = branches
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| bits[idx / 64] & (1 << (idx % 64)) != 0)
+                .map(|(_, name)| name.clone())
+                .collect();
+            map.insert(branch.clone(), containing_branches);
+        }
+        Ok(map)
+    }
+
+    /// Returns the branches tracking an existing branch of `remote`
+    pub fn list_tracking_branches(&self, remote: &str) -> Result<Vec<String>, GitError> {
+        let remote_prefix = format!("{}/", remote);
+        Ok(self
+            .list_branches_with_upstream()?
+            .into_iter()
+            .filter(|(_, upstream, gone)| {
+                !gone
+                    && upstream
+                        .as_ref()
+                        .is_some_and(|x| x.starts_with(&remote_prefix))
+            })
+            .map(|(branch, _, _)| branch)
+            .collect())
+    }
+
+    /// Returns a list of (branch, upstream, gone) for all branches. `upstream` is None if the
+    /// branch has no upstream. `gone` is true if the upstream no longer exists on the remote.
+    pub fn list_branches_with_upstream(
+        &self,
+    ) -> Result<Vec<(String, Option<String>, bool)>, GitError> {
+        let stdout = self.git(
+            "for-each-ref",
+            &[
+                "--format=%(refname) %(upstream:short) %(upstream:track)",
+                "refs/heads",
+            ],
+        )?;
+        stdout.lines().map(parse_branch_upstream_line).collect()
+    }
+
+    /// Returns the configured upstream of `branch`, like "origin/topic", or None if it has no
+    /// upstream or does not exist. The upstream is returned even if it is gone from the remote.
+    pub fn get_upstream(&self, branch: &str) -> Result<Option<String>, GitError> {
+        // Unlike `rev-parse <branch>@{upstream}`, this does not fail if the upstream is gone, and
+        // does not require parsing error messages to detect branches without upstream
+        let refname = format!("refs/heads/{}", branch);
+        let out = self.git("for-each-ref", &["--format=%(upstream:short)", &refname])?;
+        let upstream = out.trim();
+        Ok(if upstream.is_empty() {
+            None
+        } else {
+            Some(upstream.to_string())
+        })
+    }
+
+    /// Returns all the worktrees of the repository, starting with the main one
+    pub fn list_worktrees(&self) -> Result<Vec<Worktree>, GitError> {
+        let stdout = self.git("worktree", &["list", "--porcelain"])?;
+        parse_worktree_list(&stdout)
+    }
+
+    /// Returns the branches checked out in any worktree, including the main one
+    pub fn branches_in_use_by_worktrees(&self) -> Result<HashSet<String>, GitError> {
+        Ok(self
+            .list_worktrees()?
+            .into_iter()
+            .filter_map(|x| x.branch)
+            .collect())
+    }
+
+    /// Returns true if `branch` is checked out in any worktree, including the main one
+    pub fn is_checked_out_in_worktree(&self, branch: &str) -> Result<bool, GitError> {
+        Ok(self.branches_in_use_by_worktrees()?.contains(branch))
     }
 
     pub fn checkout(&self, branch: &str) -> Result<(), GitError> {
@@ -253,112 +929,1543 @@ impl Repository {
         Ok(())
     }
 
-    pub fn get_current_branch(&self) -> Option<String> {
-        let stdout = self.git("branch", &[]);
-        if stdout.is_err() {
-            return None;
-        }
-        for line in stdout.unwrap().lines() {
-            if line.starts_with('*') {
-                return Some(line[2..].to_string());
-            }
-        }
-        None
+    /// Deletes `branch` from `remote`. This modifies the remote repository!
+    pub fn delete_remote_branch(&self, remote: &str, branch: &str) -> Result<(), GitError> {
+        self.git("push", &[remote, "--delete", branch])?;
+        Ok(())
     }
 
-    pub fn update_branch(&self) -> Result<(), GitError> {
-        let out = self.git("merge", &["--ff-only"])?;
-        println!("{}", out);
+    pub fn rename_branch(&self, old: &str, new: &str) -> Result<(), GitError> {
+        self.git("branch", &["-m", old, new])?;
         Ok(())
     }
 
-    pub fn has_changes(&self) -> Result<bool, GitError> {
-        let out = self.git("status", &["--short"])?;
-        Ok(!out.is_empty())
+    /// Creates a branch starting at `start_point`, or at HEAD if `start_point` is None
+    pub fn create_branch(&self, name: &str, start_point: Option<&str>) -> Result<(), GitError> {
+        let mut args = vec![name];
+        if let Some(start_point) = start_point {
+            args.push(start_point);
+        }
+        self.git("branch", &args)?;
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn get_current_sha1(&self) -> Result<String, GitError> {
-        let out = self.git("show", &["--no-patch", "--oneline"])?;
-        let sha1 = out.split(' ').next().unwrap().to_string();
-        Ok(sha1)
+    /// Returns true if the local branch `name` exists
+    pub fn branch_exists(&self, name: &str) -> Result<bool, GitError> {
+        let refname = format!("refs/heads/{}", name);
+        match self.git("show-ref", &["--verify", "--quiet", &refname]) {
+            Ok(_) => Ok(true),
+            Err(GitError::CommandFailed { exit_code: 1, .. }) => Ok(false),
+            Err(x) => Err(x),
+        }
     }
-}
 
-// Used by test code
-#[allow(dead_code)]
-pub fn create_test_repository(path: &Path) -> Repository {
-    let repo = Repository::new(path);
+    /// Returns the full sha1 `rev` points to
+    pub fn rev_parse(&self, rev: &str) -> Result<String, GitError> {
+        let out = self.git("rev-parse", &["--verify", rev])?;
+        Ok(out.trim().to_string())
+    }
 
-    repo.git("init", &[]).expect("init failed");
-    repo.git("config", &["user.name", "test"])
-        .expect("setting username failed");
-    repo.git("config", &["user.email", "test@example.com"])
-        .expect("setting email failed");
+    /// Returns the abbreviated form of `sha1`, suitable to be shown to the user
+    pub fn abbreviated_sha(&self, sha1: &str) -> Result<String, GitError> {
+        let out = self.git("rev-parse", &["--verify", "--short", sha1])?;
+        Ok(out.trim().to_string())
+    }
 
-    // Create a file so that we have more than the start commit
-    File::create(path.join("f")).unwrap();
-    repo.git("add", &["."]).expect("add failed");
-    repo.git("commit", &["-m", "init"]).expect("commit failed");
+    /// Returns the commits of `branch` which have no equivalent commit in `base`, according to
+    /// `git cherry`
+    pub fn cherry_missing_commits(
+        &self,
+        base: &str,
+        branch: &str,
+    ) -> Result<Vec<String>, GitError> {
+        let out = self.git("cherry", &[base, branch])?;
+        Ok(out
+            .lines()
+            .filter_map(|x| x.strip_prefix("+ "))
+            .map(|x| x.to_string())
+            .collect())
+    }
 
-    repo
-}
+    /// Returns true if the changes of `branch` are already in `base`, even if its commits are not,
+    /// as happens when a branch has been squash-merged.
+    ///
+    /// This is a heuristic: it computes the patch-id of all the changes of `branch` since its
+    /// merge-base with `base`, and looks for a commit of `base` with the same patch-id, like
+    /// `git cherry` does. A branch with no common history with `base` is not squash-merged.
+    pub fn is_squash_merged(&self, branch: &str, base: &str) -> Result<bool, GitError> {
+        let merge_base = match self.merge_base(base, branch) {
+            Ok(x) => x,
+            Err(GitError::CommandFailed { exit_code: 1, .. }) => return Ok(false),
+            Err(x) => return Err(x),
+        };
+        let diff = self.git(
+            "diff-tree",
+            &["-p", "--no-color", &merge_base, branch, "--"],
+        )?;
+        let out = self.git_with_input("patch-id", &["--stable"], &diff)?;
+        let patch_id = match out.split_whitespace().next() {
+            Some(x) => x.to_string(),
+            // No changes
+            None => return Ok(false),
+        };
+        let range = format!("{}..{}", merge_base, base);
+        let log = self.git("log", &["-p", "--no-color", "--no-merges", &range, "--"])?;
+        let out = self.git_with_input("patch-id", &["--stable"], &log)?;
+        Ok(out
+            .lines()
+            .any(|x| x.split_whitespace().next() == Some(&patch_id)))
+    }
 
-#[cfg(test)]
-mod tests {
-    extern crate assert_fs;
+    /// Returns the number of commits of `branch` which are not in `base`
+    pub fn count_unique_commits(&self, branch: &str, base: &str) -> Result<usize, GitError> {
+        let out = self.git(
+            "rev-list",
+            &["--count", &format!("{}..{}", base, branch), "--"],
+        )?;
+        out.trim()
+            .parse()
+            .map_err(|_| GitError::UnexpectedOutput(out.clone()))
+    }
 
-    use super::*;
-    use std::fs;
+    /// Returns the number of commits `branch` is ahead and behind its upstream
+    pub fn ahead_behind(&self, branch: &str) -> Result<(usize, usize), GitError> {
+        let range = format!("{}...{}@{{upstream}}", branch, branch);
+        let out = self.git("rev-list", &["--left-right", "--count", &range, "--"])?;
+        let counts: Vec<usize> = out
+            .split_whitespace()
+            .map(|x| x.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| GitError::UnexpectedOutput(out.clone()))?;
+        match counts[..] {
+            [ahead, behind] => Ok((ahead, behind)),
+            _ => Err(GitError::UnexpectedOutput(out.clone())),
+        }
+    }
 
-    #[test]
-    fn get_current_branch() {
-        let dir = assert_fs::TempDir::new().unwrap();
-        let repo = create_test_repository(dir.path());
-        assert_eq!(repo.get_current_branch().unwrap(), "master");
+    /// Returns the one-line log of the commits reachable from `to` but not from `from`, newest
+    /// first
+    pub fn log_range(&self, from: &str, to: &str) -> Result<String, GitError> {
+        let out = self.git("log", &["--oneline", &format!("{}..{}", from, to), "--"])?;
+        Ok(out.trim_end().to_string())
+    }
 
-        repo.git("checkout", &["-b", "test"])
-            .expect("create branch failed");
-        assert_eq!(repo.get_current_branch().unwrap(), "test");
+    /// Returns the summary of the changes between `from` and `to`, like "2 files changed, 3
+    /// insertions(+)". Returns an empty string if there are no changes.
+    pub fn diff_stat(&self, from: &str, to: &str) -> Result<String, GitError> {
+        let out = self.git("diff", &["--shortstat", &format!("{}..{}", from, to), "--"])?;
+        Ok(out.trim().to_string())
     }
 
-    #[test]
-    fn delete_branch() {
-        // GIVEN a repository with a test branch containing unique content
-        let dir = assert_fs::TempDir::new().unwrap();
-        let repo = create_test_repository(dir.path());
-        assert_eq!(repo.get_current_branch().unwrap(), "master");
+    /// Returns the committer date of the last commit of `branch`
+    pub fn get_branch_commit_date(&self, branch: &str) -> Result<SystemTime, GitError> {
+        let out = self.git("log", &["-1", "--format=%ct", branch, "--"])?;
+        let timestamp: u64 = out
+            .trim()
+            .parse()
+            .map_err(|_| GitError::UnexpectedOutput(out.clone()))?;
+        Ok(UNIX_EPOCH + Duration::from_secs(timestamp))
+    }
 
-        repo.git("checkout", &["-b", "test"]).unwrap();
-        File::create(dir.path().join("test")).unwrap();
-        repo.git("add", &["test"]).unwrap();
-        repo.git("commit", &["-m", &format!("Create file")])
-            .unwrap();
+    /// Returns the date of the merge commit which brought `branch` into `base`: the oldest merge
+    /// commit of `base` descending from the tip of `branch`. Returns None if `branch` is not
+    /// merged into `base`, or has been merged without a merge commit.
+    pub fn merge_commit_date(
+        &self,
+        branch: &str,
+        base: &str,
+    ) -> Result<Option<SystemTime>, GitError> {
+        let range = format!("{}..{}", branch, base);
+        let out = self.git(
+            "log",
+            &[
+                "--merges",
+                "--ancestry-path",
+                "--reverse",
+                "--format=%ct",
+                &range,
+                "--",
+            ],
+        )?;
+        let line = match out.lines().next() {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let timestamp: u64 = line.trim().parse().map_err(|_| GitError::ParseError {
+            line: line.to_string(),
+        })?;
+        Ok(Some(UNIX_EPOCH + Duration::from_secs(timestamp)))
+    }
 
-        repo.checkout("master").unwrap();
+    /// Returns the full sha1 of the best common ancestor of `a` and `b`. Fails if they have no
+    /// common history.
+    pub fn merge_base(&self, a: &str, b: &str) -> Result<String, GitError> {
+        let out = self.git("merge-base", &[a, b])?;
+        Ok(out.trim().to_string())
+    }
 
-        // WHEN I call delete_branch
-        let result = repo.delete_branch("test");
+    /// Returns true if `maybe_ancestor` is an ancestor of `descendant`. A commit is considered an
+    /// ancestor of itself.
+    pub fn is_ancestor(&self, maybe_ancestor: &str, descendant: &str) -> Result<bool, GitError> {
+        match self.git("merge-base", &["--is-ancestor", maybe_ancestor, descendant]) {
+            Ok(_) => Ok(true),
+            // merge-base exits with 1 if maybe_ancestor is not an ancestor, and with other codes
+            // on errors
+            Err(GitError::CommandFailed { exit_code: 1, .. }) => Ok(false),
+            Err(x) => Err(x),
+        }
+    }
 
-        // THEN the branch is deleted
-        assert_eq!(result, Ok(()));
+    /// Returns false if HEAD does not point to any commit, as in a newly created repository
+    pub fn has_commits(&self) -> bool {
+        self.git("rev-parse", &["--verify", "--quiet", "HEAD"])
+            .is_ok()
+    }
 
-        // AND only the master branch remains
-        assert_eq!(repo.list_branches().unwrap(), &["master"]);
+    pub fn has_commit(&self, sha1: &str) -> bool {
+        self.git("cat-file", &["-e", &format!("{}^{{commit}}", sha1)])
+            .is_ok()
     }
 
-    #[test]
-    fn list_branches_with_sha1s() {
-        // GIVEN a repository with two branches
-        let dir = assert_fs::TempDir::new().unwrap();
-        let repo = create_test_repository(dir.path());
+    /// Stores the commit of `branch` in a backup ref, so that the branch can be restored after
+    /// it has been deleted
+    pub fn create_backup_ref(&self, branch: &str) -> Result<(), GitError> {
+        let backup_ref = format!("{}{}", BACKUP_REF_PREFIX, branch);
+        let branch_ref = format!("refs/heads/{}", branch);
+        self.git("update-ref", &[&backup_ref, &branch_ref])?;
+        Ok(())
+    }
 
-        repo.git("checkout", &["-b", "test"]).unwrap();
-        File::create(dir.path().join("test")).unwrap();
-        repo.git("add", &["test"]).unwrap();
-        repo.git("commit", &["-m", &format!("Create file")])
+    /// Returns a list of (branch, sha1) for all backup refs
+    pub fn list_backup_refs(&self) -> Result<Vec<(String, String)>, GitError> {
+        let stdout = self.git(
+            "for-each-ref",
+            &["--format=%(objectname) %(refname)", BACKUP_REF_PREFIX],
+        )?;
+        stdout.lines().map(parse_backup_ref_line).collect()
+    }
+
+    /// Returns the full sha1 of the commits pointed to by tags. Annotated tags are peeled to the
+    /// commit they point to.
+    pub fn list_tag_commits(&self) -> Result<HashSet<String>, GitError> {
+        // %(*objectname) is only set for annotated tags
+        let stdout = self.git(
+            "for-each-ref",
+            &["--format=%(objectname) %(*objectname)", "refs/tags"],
+        )?;
+        Ok(stdout
+            .lines()
+            .filter_map(|line| line.split_whitespace().last())
+            .map(|x| x.to_string())
+            .collect())
+    }
+
+    pub fn delete_backup_refs(&self) -> Result<(), GitError> {
+        for (branch, _) in self.list_backup_refs()? {
+            let backup_ref = format!("{}{}", BACKUP_REF_PREFIX, branch);
+            self.git("update-ref", &["-d", &backup_ref])?;
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the current branch, or None if HEAD is detached
+    pub fn current_branch(&self) -> Result<Option<String>, GitError> {
+        let stdout = self.git("branch", &[])?;
+        for line in stdout.lines() {
+            if line.starts_with(CURRENT_BRANCH_PREFIX) {
+                return Ok(parse_branch_line(line).map(|x| x.to_string()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns true if HEAD does not point to a branch
+    pub fn is_detached_head(&self) -> Result<bool, GitError> {
+        match self.git("symbolic-ref", &["--quiet", "HEAD"]) {
+            Ok(_) => Ok(false),
+            // symbolic-ref --quiet exits with 1 if HEAD is not a symbolic ref
+            Err(GitError::CommandFailed { exit_code: 1, .. }) => Ok(true),
+            Err(x) => Err(x),
+        }
+    }
+
+    pub fn update_branch(&self) -> Result<(), GitError> {
+        self.git("merge", &["--ff-only"])?;
+        Ok(())
+    }
+
+    /// Rebases the current branch on its upstream. If the rebase fails, for example because of
+    /// conflicts, it is aborted so that the branch is left unchanged.
+    pub fn rebase_onto_upstream(&self) -> Result<(), GitError> {
+        if let Err(x) = self.git("rebase", &["@{upstream}"]) {
+            // The rebase may have failed before starting, so there may be nothing to abort
+            let _ = self.git("rebase", &["--abort"]);
+            return Err(x);
+        }
+        Ok(())
+    }
+
+    /// Stashes all changes, including untracked files
+    pub fn stash_push(&self) -> Result<(), GitError> {
+        self.git(
+            "stash",
+            &["push", "--include-untracked", "-m", "git-bonsai autostash"],
+        )?;
+        Ok(())
+    }
+
+    pub fn stash_pop(&self) -> Result<(), GitError> {
+        self.git("stash", &["pop"])?;
+        Ok(())
+    }
+
+    /// Returns the index and work tree status codes of all the entries of `git status`
+    fn get_status_codes(&self) -> Result<Vec<(char, char)>, GitError> {
+        let out = self.git("status", &["--porcelain"])?;
+        out.lines().map(parse_status_line).collect()
+    }
+
+    /// Returns true if changes have been added to the index
+    pub fn has_staged_changes(&self) -> Result<bool, GitError> {
+        Ok(self
+            .get_status_codes()?
+            .iter()
+            .any(|&(index, _)| index != ' ' && index != '?' && index != '!'))
+    }
+
+    /// Returns true if tracked files have been modified but not added to the index
+    pub fn has_unstaged_changes(&self) -> Result<bool, GitError> {
+        Ok(self
+            .get_status_codes()?
+            .iter()
+            .any(|&(_, work_tree)| work_tree != ' ' && work_tree != '?' && work_tree != '!'))
+    }
+
+    pub fn has_untracked_files(&self) -> Result<bool, GitError> {
+        Ok(self.get_status_codes()?.contains(&('?', '?')))
+    }
+
+    #[allow(dead_code)]
+    pub fn get_current_sha1(&self) -> Result<String, GitError> {
+        self.rev_parse("HEAD")
+    }
+}
+
+/// The git operations App relies on. Repository implements them by running git, tests can
+/// implement them to check the decisions of App without creating a repository. See Repository
+/// for the documentation of each method.
+pub trait GitRepository {
+    /// The directory git commands run in
+    fn path(&self) -> &Path;
+    fn list_remotes(&self) -> Result<Vec<String>, GitError>;
+    fn fetch_with_args(&self, args: &[&str]) -> Result<(), GitError>;
+    fn fetch_dry_run(&self, remote: &str) -> Result<String, GitError>;
+    fn get_config_keys(&self, key: &str) -> Result<Vec<String>, GitError>;
+    fn get_global_config_keys(&self, key: &str) -> Result<Vec<String>, GitError>;
+    fn get_config_value(&self, key: &str) -> Result<Option<String>, GitError>;
+    fn set_config_key(&self, key: &str, value: &str) -> Result<(), GitError>;
+    fn add_config_key(&self, key: &str, value: &str) -> Result<(), GitError>;
+    fn find_default_branch(&self, remote: &str) -> Result<String, GitError>;
+    fn list_branches(&self) -> Result<Vec<String>, GitError>;
+    fn list_branches_filtered(&self, filter: MergeFilter) -> Result<Vec<String>, GitError>;
+    fn list_merged_branches(&self, base: &str) -> Result<Vec<String>, GitError>;
+    fn list_branches_with_sha1s(&self) -> Result<Vec<(String, String)>, GitError>;
+    fn list_branches_containing(&self, commit: &str) -> Result<Vec<String>, GitError>;
+    fn build_containment_map(&self) -> Result<HashMap<String, HashSet<String>>, GitError>;
+    fn list_tracking_branches(&self, remote: &str) -> Result<Vec<String>, GitError>;
+    fn list_branches_with_upstream(&self) -> Result<Vec<(String, Option<String>, bool)>, GitError>;
+    fn branches_in_use_by_worktrees(&self) -> Result<HashSet<String>, GitError>;
+    fn is_checked_out_in_worktree(&self, branch: &str) -> Result<bool, GitError>;
+    fn checkout(&self, branch: &str) -> Result<(), GitError>;
+    fn delete_branch(&self, branch: &str) -> Result<(), GitError>;
+    fn delete_remote_branch(&self, remote: &str, branch: &str) -> Result<(), GitError>;
+    fn rename_branch(&self, old: &str, new: &str) -> Result<(), GitError>;
+    fn create_branch(&self, name: &str, start_point: Option<&str>) -> Result<(), GitError>;
+    fn branch_exists(&self, name: &str) -> Result<bool, GitError>;
+    fn rev_parse(&self, rev: &str) -> Result<String, GitError>;
+    fn abbreviated_sha(&self, sha1: &str) -> Result<String, GitError>;
+    fn cherry_missing_commits(&self, base: &str, branch: &str) -> Result<Vec<String>, GitError>;
+    fn is_squash_merged(&self, branch: &str, base: &str) -> Result<bool, GitError>;
+    fn count_unique_commits(&self, branch: &str, base: &str) -> Result<usize, GitError>;
+    fn ahead_behind(&self, branch: &str) -> Result<(usize, usize), GitError>;
+    fn log_range(&self, from: &str, to: &str) -> Result<String, GitError>;
+    fn diff_stat(&self, from: &str, to: &str) -> Result<String, GitError>;
+    fn get_branch_commit_date(&self, branch: &str) -> Result<SystemTime, GitError>;
+    fn merge_commit_date(&self, branch: &str, base: &str) -> Result<Option<SystemTime>, GitError>;
+    fn is_ancestor(&self, maybe_ancestor: &str, descendant: &str) -> Result<bool, GitError>;
+    fn has_commit(&self, sha1: &str) -> bool;
+    fn create_backup_ref(&self, branch: &str) -> Result<(), GitError>;
+    fn list_backup_refs(&self) -> Result<Vec<(String, String)>, GitError>;
+    fn list_tag_commits(&self) -> Result<HashSet<String>, GitError>;
+    fn delete_backup_refs(&self) -> Result<(), GitError>;
+    fn current_branch(&self) -> Result<Option<String>, GitError>;
+    fn is_detached_head(&self) -> Result<bool, GitError>;
+    fn update_branch(&self) -> Result<(), GitError>;
+    fn rebase_onto_upstream(&self) -> Result<(), GitError>;
+    fn has_staged_changes(&self) -> Result<bool, GitError>;
+    fn has_unstaged_changes(&self) -> Result<bool, GitError>;
+    fn has_untracked_files(&self) -> Result<bool, GitError>;
+}
+
+impl GitRepository for Repository {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn list_remotes(&self) -> Result<Vec<String>, GitError> {
+        Repository::list_remotes(self)
+    }
+
+    fn fetch_with_args(&self, args: &[&str]) -> Result<(), GitError> {
+        Repository::fetch_with_args(self, args)
+    }
+
+    fn fetch_dry_run(&self, remote: &str) -> Result<String, GitError> {
+        Repository::fetch_dry_run(self, remote)
+    }
+
+    fn get_config_keys(&self, key: &str) -> Result<Vec<String>, GitError> {
+        Repository::get_config_keys(self, key)
+    }
+
+    fn get_global_config_keys(&self, key: &str) -> Result<Vec<String>, GitError> {
+        Repository::get_global_config_keys(self, key)
+    }
+
+    fn get_config_value(&self, key: &str) -> Result<Option<String>, GitError> {
+        Repository::get_config_value(self, key)
+    }
+
+    fn set_config_key(&self, key: &str, value: &str) -> Result<(), GitError> {
+        Repository::set_config_key(self, key, value)
+    }
+
+    fn add_config_key(&self, key: &str, value: &str) -> Result<(), GitError> {
+        Repository::add_config_key(self, key, value)
+    }
+
+    fn find_default_branch(&self, remote: &str) -> Result<String, GitError> {
+        Repository::find_default_branch(self, remote)
+    }
+
+    fn list_branches(&self) -> Result<Vec<String>, GitError> {
+        Repository::list_branches(self)
+    }
+
+    fn list_branches_filtered(&self, filter: MergeFilter) -> Result<Vec<String>, GitError> {
+        Repository::list_branches_filtered(self, filter)
+    }
+
+    fn list_merged_branches(&self, base: &str) -> Result<Vec<String>, GitError> {
+        Repository::list_merged_branches(self, base)
+    }
+
+    fn list_branches_with_sha1s(&self) -> Result<Vec<(String, String)>, GitError> {
+        Repository::list_branches_with_sha1s(self)
+    }
+
+    fn list_branches_containing(&self, commit: &str) -> Result<Vec<String>, GitError> {
+        Repository::list_branches_containing(self, commit)
+    }
+
+    fn build_containment_map(&self) -> Result<HashMap<String, HashSet<String>>, GitError> {
+        Repository::build_containment_map(self)
+    }
+
+    fn list_tracking_branches(&self, remote: &str) -> Result<Vec<String>, GitError> {
+        Repository::list_tracking_branches(self, remote)
+    }
+
+    fn list_branches_with_upstream(&self) -> Result<Vec<(String, Option<String>, bool)>, GitError> {
+        Repository::list_branches_with_upstream(self)
+    }
+
+    fn branches_in_use_by_worktrees(&self) -> Result<HashSet<String>, GitError> {
+        Repository::branches_in_use_by_worktrees(self)
+    }
+
+    fn is_checked_out_in_worktree(&self, branch: &str) -> Result<bool, GitError> {
+        Repository::is_checked_out_in_worktree(self, branch)
+    }
+
+    fn checkout(&self, branch: &str) -> Result<(), GitError> {
+        Repository::checkout(self, branch)
+    }
+
+    fn delete_branch(&self, branch: &str) -> Result<(), GitError> {
+        Repository::delete_branch(self, branch)
+    }
+
+    fn delete_remote_branch(&self, remote: &str, branch: &str) -> Result<(), GitError> {
+        Repository::delete_remote_branch(self, remote, branch)
+    }
+
+    fn rename_branch(&self, old: &str, new: &str) -> Result<(), GitError> {
+        Repository::rename_branch(self, old, new)
+    }
+
+    fn create_branch(&self, name: &str, start_point: Option<&str>) -> Result<(), GitError> {
+        Repository::create_branch(self, name, start_point)
+    }
+
+    fn branch_exists(&self, name: &str) -> Result<bool, GitError> {
+        Repository::branch_exists(self, name)
+    }
+
+    fn rev_parse(&self, rev: &str) -> Result<String, GitError> {
+        Repository::rev_parse(self, rev)
+    }
+
+    fn abbreviated_sha(&self, sha1: &str) -> Result<String, GitError> {
+        Repository::abbreviated_sha(self, sha1)
+    }
+
+    fn cherry_missing_commits(&self, base: &str, branch: &str) -> Result<Vec<String>, GitError> {
+        Repository::cherry_missing_commits(self, base, branch)
+    }
+
+    fn is_squash_merged(&self, branch: &str, base: &str) -> Result<bool, GitError> {
+        Repository::is_squash_merged(self, branch, base)
+    }
+
+    fn count_unique_commits(&self, branch: &str, base: &str) -> Result<usize, GitError> {
+        Repository::count_unique_commits(self, branch, base)
+    }
+
+    fn ahead_behind(&self, branch: &str) -> Result<(usize, usize), GitError> {
+        Repository::ahead_behind(self, branch)
+    }
+
+    fn log_range(&self, from: &str, to: &str) -> Result<String, GitError> {
+        Repository::log_range(self, from, to)
+    }
+
+    fn diff_stat(&self, from: &str, to: &str) -> Result<String, GitError> {
+        Repository::diff_stat(self, from, to)
+    }
+
+    fn get_branch_commit_date(&self, branch: &str) -> Result<SystemTime, GitError> {
+        Repository::get_branch_commit_date(self, branch)
+    }
+
+    fn merge_commit_date(&self, branch: &str, base: &str) -> Result<Option<SystemTime>, GitError> {
+        Repository::merge_commit_date(self, branch, base)
+    }
+
+    fn is_ancestor(&self, maybe_ancestor: &str, descendant: &str) -> Result<bool, GitError> {
+        Repository::is_ancestor(self, maybe_ancestor, descendant)
+    }
+
+    fn has_commit(&self, sha1: &str) -> bool {
+        Repository::has_commit(self, sha1)
+    }
+
+    fn create_backup_ref(&self, branch: &str) -> Result<(), GitError> {
+        Repository::create_backup_ref(self, branch)
+    }
+
+    fn list_backup_refs(&self) -> Result<Vec<(String, String)>, GitError> {
+        Repository::list_backup_refs(self)
+    }
+
+    fn list_tag_commits(&self) -> Result<HashSet<String>, GitError> {
+        Repository::list_tag_commits(self)
+    }
+
+    fn delete_backup_refs(&self) -> Result<(), GitError> {
+        Repository::delete_backup_refs(self)
+    }
+
+    fn current_branch(&self) -> Result<Option<String>, GitError> {
+        Repository::current_branch(self)
+    }
+
+    fn is_detached_head(&self) -> Result<bool, GitError> {
+        Repository::is_detached_head(self)
+    }
+
+    fn update_branch(&self) -> Result<(), GitError> {
+        Repository::update_branch(self)
+    }
+
+    fn rebase_onto_upstream(&self) -> Result<(), GitError> {
+        Repository::rebase_onto_upstream(self)
+    }
+
+    fn has_staged_changes(&self) -> Result<bool, GitError> {
+        Repository::has_staged_changes(self)
+    }
+
+    fn has_unstaged_changes(&self) -> Result<bool, GitError> {
+        Repository::has_unstaged_changes(self)
+    }
+
+    fn has_untracked_files(&self) -> Result<bool, GitError> {
+        Repository::has_untracked_files(self)
+    }
+}
+
+// Used by test code
+#[allow(dead_code)]
+pub fn create_test_repository(path: &Path) -> Repository {
+    let repo = Repository::new(path);
+
+    repo.git("init", &[]).expect("init failed");
+    repo.git("config", &["user.name", "test"])
+        .expect("setting username failed");
+    repo.git("config", &["user.email", "test@example.com"])
+        .expect("setting email failed");
+
+    // Create a file so that we have more than the start commit
+    File::create(path.join("f")).unwrap();
+    repo.git("add", &["."]).expect("add failed");
+    repo.git("commit", &["-m", "init"]).expect("commit failed");
+
+    repo
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate assert_fs;
+
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn parse_branch_line_regular_branches() {
+        assert_eq!(parse_branch_line("  topic"), Some("topic"));
+        assert_eq!(parse_branch_line("* master"), Some("master"));
+        assert_eq!(
+            parse_branch_line("  topic 1234abc Commit message"),
+            Some("topic 1234abc Commit message")
+        );
+    }
+
+    #[test]
+    fn parse_branch_line_keeps_worktree_branches() {
+        // Branches checked out in another worktree are protected by App, not filtered here
+        assert_eq!(parse_branch_line("+ topic"), Some("topic"));
+    }
+
+    #[test]
+    fn parse_worktree_list_valid() {
+        let stdout = "worktree /path/to/main\n\
+            HEAD 960389f1c69e8b9c3fe06d29866d0d193375a6cb\n\
+            branch refs/heads/master\n\
+            \n\
+            worktree /path/to/linked\n\
+            HEAD 960389f1c69e8b9c3fe06d29866d0d193375a6cb\n\
+            branch refs/heads/feature/a\n\
+            locked\n\
+            \n\
+            worktree /path/to/detached\n\
+            HEAD 960389f1c69e8b9c3fe06d29866d0d193375a6cb\n\
+            detached\n\
+            \n";
+        assert_eq!(
+            parse_worktree_list(stdout),
+            Ok(vec![
+                Worktree {
+                    path: PathBuf::from("/path/to/main"),
+                    branch: Some("master".to_string()),
+                    bare: false,
+                    detached: false,
+                },
+                Worktree {
+                    path: PathBuf::from("/path/to/linked"),
+                    branch: Some("feature/a".to_string()),
+                    bare: false,
+                    detached: false,
+                },
+                Worktree {
+                    path: PathBuf::from("/path/to/detached"),
+                    branch: None,
+                    bare: false,
+                    detached: true,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_worktree_list_bare() {
+        assert_eq!(
+            parse_worktree_list("worktree /path/to/repo.git\nbare\n\n"),
+            Ok(vec![Worktree {
+                path: PathBuf::from("/path/to/repo.git"),
+                branch: None,
+                bare: true,
+                detached: false,
+            }])
+        );
+    }
+
+    #[test]
+    fn parse_worktree_list_malformed() {
+        assert_eq!(
+            parse_worktree_list("HEAD 960389f1c69e8b9c3fe06d29866d0d193375a6cb\n"),
+            Err(GitError::ParseError {
+                line: "HEAD 960389f1c69e8b9c3fe06d29866d0d193375a6cb".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_branch_line_skips_detached_head() {
+        assert_eq!(parse_branch_line("* (HEAD detached at 1234abc)"), None);
+        assert_eq!(parse_branch_line("* (no branch, rebasing topic)"), None);
+    }
+
+    #[test]
+    fn parse_branch_line_invalid_lines() {
+        assert_eq!(parse_branch_line(""), None);
+        assert_eq!(parse_branch_line("*"), None);
+    }
+
+    #[test]
+    fn parse_git_version_valid() {
+        for (output, expected) in [
+            ("git version 2.39.1\n", (2, 39, 1)),
+            ("git version 2.37.1 (Apple Git-137.1)\n", (2, 37, 1)),
+            ("git version 2.39.5.windows.1\n", (2, 39, 5)),
+            ("git version 3.0\n", (3, 0, 0)),
+        ] {
+            assert_eq!(parse_git_version(output), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn parse_git_version_malformed() {
+        for output in ["", "git version", "git version two", "hg version 2.39.1"] {
+            assert_eq!(
+                parse_git_version(output),
+                Err(GitError::UnexpectedOutput(output.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn git_version() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = Repository::new(dir.path());
+        assert!(repo.git_version().unwrap() >= MIN_GIT_VERSION);
+    }
+
+    #[test]
+    fn parse_branch_sha1_line_valid() {
+        assert_eq!(
+            parse_branch_sha1_line("refs/heads/topic\0abc1234"),
+            Ok(("topic".to_string(), "abc1234".to_string()))
+        );
+        assert_eq!(
+            parse_branch_sha1_line("refs/heads/feature/v1.2\0abc1234"),
+            Ok(("feature/v1.2".to_string(), "abc1234".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_branch_sha1_line_malformed() {
+        for line in [
+            "",
+            "refs/heads/topic 1234abc",
+            "topic\0abc1234",
+            "refs/heads/topic\0",
+        ] {
+            assert_eq!(
+                parse_branch_sha1_line(line),
+                Err(GitError::ParseError {
+                    line: line.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn parse_branch_upstream_line_valid() {
+        assert_eq!(
+            parse_branch_upstream_line("refs/heads/topic origin/topic [behind 2]"),
+            Ok(("topic".to_string(), Some("origin/topic".to_string()), false))
+        );
+        assert_eq!(
+            parse_branch_upstream_line("refs/heads/feature/a origin/feature/a [gone]"),
+            Ok((
+                "feature/a".to_string(),
+                Some("origin/feature/a".to_string()),
+                true
+            ))
+        );
+        assert_eq!(
+            parse_branch_upstream_line("refs/heads/local  "),
+            Ok(("local".to_string(), None, false))
+        );
+    }
+
+    #[test]
+    fn parse_branch_upstream_line_malformed() {
+        for line in ["", "refs/heads/topic", "refs/tags/v1.0  "] {
+            assert_eq!(
+                parse_branch_upstream_line(line),
+                Err(GitError::ParseError {
+                    line: line.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn parse_backup_ref_line_valid() {
+        assert_eq!(
+            parse_backup_ref_line("1234abc refs/bonsai/feature/a"),
+            Ok(("feature/a".to_string(), "1234abc".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_backup_ref_line_malformed() {
+        for line in ["", "1234abc", "1234abc refs/heads/topic"] {
+            assert_eq!(
+                parse_backup_ref_line(line),
+                Err(GitError::ParseError {
+                    line: line.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn parse_status_line_valid() {
+        assert_eq!(parse_status_line("M  f"), Ok(('M', ' ')));
+        assert_eq!(parse_status_line(" M f"), Ok((' ', 'M')));
+        assert_eq!(parse_status_line("?? new"), Ok(('?', '?')));
+    }
+
+    #[test]
+    fn parse_status_line_malformed() {
+        for line in ["", "M", "Mf"] {
+            assert_eq!(
+                parse_status_line(line),
+                Err(GitError::ParseError {
+                    line: line.to_string()
+                })
+            );
+        }
+    }
+
+    fn assert_status(repo: &Repository, staged: bool, unstaged: bool, untracked: bool) {
+        assert_eq!(repo.has_staged_changes(), Ok(staged));
+        assert_eq!(repo.has_unstaged_changes(), Ok(unstaged));
+        assert_eq!(repo.has_untracked_files(), Ok(untracked));
+    }
+
+    #[test]
+    fn status_clean() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        assert_status(&repo, false, false, false);
+    }
+
+    #[test]
+    fn status_untracked() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        File::create(dir.path().join("new")).unwrap();
+        assert_status(&repo, false, false, true);
+    }
+
+    #[test]
+    fn status_unstaged() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        fs::write(dir.path().join("f"), "changed").unwrap();
+        assert_status(&repo, false, true, false);
+    }
+
+    #[test]
+    fn status_staged() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        fs::write(dir.path().join("f"), "changed").unwrap();
+        repo.git("add", &["f"]).unwrap();
+        assert_status(&repo, true, false, false);
+
+        // A file added to the index then modified is both staged and unstaged
+        fs::write(dir.path().join("f"), "changed again").unwrap();
+        assert_status(&repo, true, true, false);
+    }
+
+    #[test]
+    fn has_commits() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = Repository::new(dir.path());
+        repo.git("init", &[]).unwrap();
+        assert!(!repo.has_commits());
+
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        assert!(repo.has_commits());
+    }
+
+    #[test]
+    fn stash_guard() {
+        // GIVEN a repository with an unstaged change and an untracked file
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        fs::write(dir.path().join("f"), "changed").unwrap();
+        File::create(dir.path().join("new")).unwrap();
+
+        {
+            // WHEN the changes are stashed
+            let _guard = StashGuard::push(&repo).unwrap();
+
+            // THEN the working tree is clean
+            assert_status(&repo, false, false, false);
+        }
+
+        // AND the changes are back once the guard is dropped
+        assert_status(&repo, false, true, true);
+        assert_eq!(fs::read_to_string(dir.path().join("f")).unwrap(), "changed");
+    }
+
+    #[test]
+    fn current_branch_detached_head() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.git("checkout", &["--detach"]).unwrap();
+
+        assert_eq!(repo.current_branch(), Ok(None));
+        assert_eq!(repo.list_branches().unwrap(), &["master"]);
+    }
+
+    #[test]
+    fn current_branch() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        assert_eq!(repo.current_branch(), Ok(Some("master".to_string())));
+
+        repo.git("checkout", &["-b", "test"])
+            .expect("create branch failed");
+        assert_eq!(repo.current_branch(), Ok(Some("test".to_string())));
+    }
+
+    #[test]
+    fn current_branch_not_a_repository() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = Repository::new(dir.path());
+        assert!(matches!(
+            repo.current_branch(),
+            Err(GitError::CommandFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn branch_restorer_falls_back_if_branch_is_gone() {
+        // GIVEN a repository with a topic and an other branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.create_branch("topic", None).unwrap();
+        repo.create_branch("other", None).unwrap();
+
+        // AND a restorer created while on the topic branch, with master as fallback
+        repo.checkout("topic").unwrap();
+        {
+            let _restorer = BranchRestorer::with_fallback(&repo, "master").unwrap();
+
+            // WHEN the topic branch is deleted before the restorer is dropped
+            repo.checkout("other").unwrap();
+            repo.delete_branch("topic").unwrap();
+        }
+
+        // THEN the fallback branch has been checked out
+        assert_eq!(repo.current_branch().unwrap().unwrap(), "master");
+    }
+
+    #[test]
+    fn branch_restorer_without_fallback_fails_if_branch_is_gone() {
+        // GIVEN a repository with a topic and an other branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.create_branch("topic", None).unwrap();
+        repo.create_branch("other", None).unwrap();
+
+        // AND a restorer created while on the topic branch
+        repo.checkout("topic").unwrap();
+        let mut restorer = BranchRestorer::new(&repo).unwrap();
+
+        // WHEN the topic branch is deleted before the restorer runs
+        repo.checkout("other").unwrap();
+        repo.delete_branch("topic").unwrap();
+
+        // THEN restoring fails, and the current branch does not change
+        assert!(restorer.restore().is_err());
+        assert_eq!(repo.current_branch().unwrap().unwrap(), "other");
+    }
+
+    #[test]
+    fn branch_restorer_restores_detached_head() {
+        // GIVEN a repository with a detached HEAD
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.git("checkout", &["--detach"]).unwrap();
+        let sha1 = repo.rev_parse("HEAD").unwrap();
+
+        // WHEN a restorer is dropped after checking out a branch
+        {
+            let _restorer = BranchRestorer::new(&repo).unwrap();
+            repo.checkout("master").unwrap();
+        }
+
+        // THEN HEAD is detached again, at the same commit
+        assert_eq!(repo.current_branch(), Ok(None));
+        assert_eq!(repo.rev_parse("HEAD").unwrap(), sha1);
+    }
+
+    #[test]
+    fn list_tag_commits() {
+        // GIVEN a repository without tags
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        assert_eq!(repo.list_tag_commits(), Ok(HashSet::new()));
+
+        // WHEN a lightweight tag is added to the first commit and an annotated one to a second
+        // commit
+        let first_sha1 = repo.rev_parse("HEAD").unwrap();
+        repo.git("tag", &["light"]).unwrap();
+        repo.git("commit", &["--allow-empty", "-m", "Second"])
+            .unwrap();
+        let second_sha1 = repo.rev_parse("HEAD").unwrap();
+        repo.git("tag", &["-a", "annotated", "-m", "Annotated tag"])
+            .unwrap();
+
+        // THEN the commits pointed to by both tags are listed
+        assert_eq!(
+            repo.list_tag_commits(),
+            Ok(HashSet::from([first_sha1, second_sha1]))
+        );
+    }
+
+    #[test]
+    fn branch_exists() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.create_branch("topic", None).unwrap();
+        repo.git("tag", &["v1.0"]).unwrap();
+
+        assert_eq!(repo.branch_exists("master"), Ok(true));
+        assert_eq!(repo.branch_exists("topic"), Ok(true));
+        assert_eq!(repo.branch_exists("nonexistent"), Ok(false));
+        // Other refs are not branches
+        assert_eq!(repo.branch_exists("v1.0"), Ok(false));
+    }
+
+    #[test]
+    fn create_branch_from_head() {
+        // GIVEN a repository
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+
+        // WHEN I create a branch without start point
+        repo.create_branch("topic", None).unwrap();
+
+        // THEN it points to HEAD, and the current branch has not changed
+        assert_eq!(repo.list_branches().unwrap(), &["master", "topic"]);
+        assert_eq!(repo.rev_parse("topic"), repo.rev_parse("HEAD"));
+        assert_eq!(repo.current_branch(), Ok(Some("master".to_string())));
+
+        // AND creating it again fails
+        assert!(repo.create_branch("topic", None).is_err());
+    }
+
+    #[test]
+    fn create_branch_from_start_point() {
+        // GIVEN a repository with a topic branch, ahead of master
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.git("checkout", &["-b", "topic"]).unwrap();
+        repo.git("commit", &["--allow-empty", "-m", "Topic"])
+            .unwrap();
+        repo.checkout("master").unwrap();
+
+        // WHEN I create a branch starting at topic
+        repo.create_branch("topic2", Some("topic")).unwrap();
+
+        // THEN it points to the same commit as topic
+        assert_eq!(repo.rev_parse("topic2"), repo.rev_parse("topic"));
+        assert_ne!(repo.rev_parse("topic2"), repo.rev_parse("master"));
+
+        // AND a nonexistent start point is an error
+        assert!(repo.create_branch("topic3", Some("nonexistent")).is_err());
+    }
+
+    #[test]
+    fn rename_branch() {
+        // GIVEN a repository with a topic branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.create_branch("topic", None).unwrap();
+        let sha1 = repo.rev_parse("topic").unwrap();
+
+        // WHEN I rename it
+        repo.rename_branch("topic", "archive/topic").unwrap();
+
+        // THEN only the new name exists, pointing to the same commit
+        assert_eq!(repo.list_branches().unwrap(), &["archive/topic", "master"]);
+        assert_eq!(repo.rev_parse("archive/topic").unwrap(), sha1);
+
+        // AND renaming to an existing branch fails
+        assert!(repo.rename_branch("archive/topic", "master").is_err());
+    }
+
+    #[test]
+    fn delete_branch() {
+        // GIVEN a repository with a test branch containing unique content
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        assert_eq!(repo.current_branch().unwrap().unwrap(), "master");
+
+        repo.git("checkout", &["-b", "test"]).unwrap();
+        File::create(dir.path().join("test")).unwrap();
+        repo.git("add", &["test"]).unwrap();
+        repo.git("commit", &["-m", "Create file"]).unwrap();
+
+        repo.checkout("master").unwrap();
+
+        // WHEN I call delete_branch
+        let result = repo.delete_branch("test");
+
+        // THEN the branch is deleted
+        assert_eq!(result, Ok(()));
+
+        // AND only the master branch remains
+        assert_eq!(repo.list_branches().unwrap(), &["master"]);
+    }
+
+    /// Creates a repository with a "merged" branch merged in master, and an "unmerged" branch
+    fn create_repository_with_merged_branches(path: &Path) -> Repository {
+        let repo = create_test_repository(path);
+        repo.create_branch("merged", None).unwrap();
+
+        repo.git("checkout", &["-b", "unmerged"]).unwrap();
+        File::create(path.join("unmerged")).unwrap();
+        repo.git("add", &["unmerged"]).unwrap();
+        repo.git("commit", &["-m", "Create unmerged"]).unwrap();
+
+        repo.checkout("master").unwrap();
+        repo
+    }
+
+    #[test]
+    fn is_squash_merged() {
+        // GIVEN a repository with a squash-merged branch and an unmerged one
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path();
+        let repo = create_repository_with_merged_branches(path);
+        repo.git("checkout", &["-b", "squashed"]).unwrap();
+        for name in ["squashed1", "squashed2"] {
+            File::create(path.join(name)).unwrap();
+            repo.git("add", &[name]).unwrap();
+            repo.git("commit", &["-m", name]).unwrap();
+        }
+        repo.checkout("master").unwrap();
+        repo.git("merge", &["--squash", "squashed"]).unwrap();
+        repo.git("commit", &["-m", "Squash-merge squashed"])
+            .unwrap();
+
+        // THEN only the squash-merged branch is detected as such
+        assert!(repo.is_squash_merged("squashed", "master").unwrap());
+        assert!(!repo.is_squash_merged("unmerged", "master").unwrap());
+
+        // AND no objects have been left behind
+        let unreachable = repo
+            .git("fsck", &["--unreachable", "--no-reflogs"])
+            .unwrap();
+        assert_eq!(unreachable, "");
+    }
+
+    #[test]
+    fn is_squash_merged_unrelated_branch() {
+        // GIVEN a repository with an orphan branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.git("checkout", &["--orphan", "gh-pages"]).unwrap();
+        repo.git("commit", &["-m", "gh-pages"]).unwrap();
+        repo.checkout("master").unwrap();
+
+        // THEN it is not squash-merged, and checking it does not fail
+        assert_eq!(repo.is_squash_merged("gh-pages", "master"), Ok(false));
+    }
+
+    #[test]
+    fn count_unique_commits() {
+        // GIVEN a repository with a merged branch and an unmerged branch containing 1 commit
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_repository_with_merged_branches(dir.path());
+
+        // AND a branch containing 2 commits
+        repo.git("checkout", &["-b", "topic"]).unwrap();
+        for name in ["topic1", "topic2"] {
+            File::create(dir.path().join(name)).unwrap();
+            repo.git("add", &[name]).unwrap();
+            repo.git("commit", &["-m", name]).unwrap();
+        }
+
+        // THEN the commits which are not in master are counted
+        assert_eq!(repo.count_unique_commits("merged", "master"), Ok(0));
+        assert_eq!(repo.count_unique_commits("unmerged", "master"), Ok(1));
+        assert_eq!(repo.count_unique_commits("topic", "master"), Ok(2));
+        assert_eq!(repo.count_unique_commits("master", "topic"), Ok(0));
+    }
+
+    #[test]
+    fn is_ancestor() {
+        // GIVEN a repository with a merged branch and an unmerged branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_repository_with_merged_branches(dir.path());
+
+        // THEN ancestry is reported in the right direction
+        assert_eq!(repo.is_ancestor("merged", "master"), Ok(true));
+        assert_eq!(repo.is_ancestor("master", "unmerged"), Ok(true));
+        assert_eq!(repo.is_ancestor("unmerged", "master"), Ok(false));
+        assert_eq!(repo.is_ancestor("master", "master"), Ok(true));
+    }
+
+    #[test]
+    fn is_ancestor_unrelated_commits() {
+        // GIVEN a repository with an orphan branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.git("checkout", &["--orphan", "orphan"]).unwrap();
+        repo.git("commit", &["-m", "orphan"]).unwrap();
+
+        // THEN neither branch is an ancestor of the other
+        assert_eq!(repo.is_ancestor("orphan", "master"), Ok(false));
+        assert_eq!(repo.is_ancestor("master", "orphan"), Ok(false));
+    }
+
+    #[test]
+    fn merge_base() {
+        // GIVEN a repository with a merged branch and an unmerged branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_repository_with_merged_branches(dir.path());
+
+        // THEN the merge base of the unmerged branch and master is the tip of master
+        let master_sha1 = repo.rev_parse("master").unwrap();
+        assert_eq!(
+            repo.merge_base("unmerged", "master"),
+            Ok(master_sha1.clone())
+        );
+        assert_eq!(repo.merge_base("master", "unmerged"), Ok(master_sha1));
+    }
+
+    #[test]
+    fn merge_base_unrelated_commits() {
+        // GIVEN a repository with an orphan branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.git("checkout", &["--orphan", "orphan"]).unwrap();
+        repo.git("commit", &["-m", "orphan"]).unwrap();
+
+        // THEN the branches have no merge base
+        assert!(matches!(
+            repo.merge_base("orphan", "master"),
+            Err(GitError::CommandFailed { exit_code: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn is_ancestor_invalid_rev() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        assert!(matches!(
+            repo.is_ancestor("does-not-exist", "master"),
+            Err(GitError::CommandFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn log_range() {
+        // GIVEN a repository with a topic branch containing two commits
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path();
+        let repo = create_test_repository(path);
+        repo.git("checkout", &["-b", "topic"]).unwrap();
+        for name in ["topic1", "topic2"] {
+            File::create(path.join(name)).unwrap();
+            repo.git("add", &[name]).unwrap();
+            repo.git("commit", &["-m", &format!("Create {}", name)])
+                .unwrap();
+        }
+
+        // WHEN I get the log from master to topic
+        let log = repo.log_range("master", "topic").unwrap();
+
+        // THEN it contains one line per commit, newest first
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let topic_sha1 = repo.rev_parse("topic").unwrap();
+        assert!(topic_sha1.starts_with(lines[0].split(' ').next().unwrap()));
+        assert!(lines[0].ends_with(" Create topic2"));
+        assert!(lines[1].ends_with(" Create topic1"));
+
+        // AND the log from topic to master is empty
+        assert_eq!(repo.log_range("topic", "master").unwrap(), "");
+    }
+
+    #[test]
+    fn diff_stat() {
+        // GIVEN a repository with a topic branch adding a file and modifying another
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path();
+        let repo = create_test_repository(path);
+        repo.git("checkout", &["-b", "topic"]).unwrap();
+        fs::write(path.join("f"), "line1\nline2\n").unwrap();
+        fs::write(path.join("new"), "line1\n").unwrap();
+        repo.git("add", &["f", "new"]).unwrap();
+        repo.git("commit", &["-m", "Change files"]).unwrap();
+
+        // THEN the diff stat summarizes the changes
+        assert_eq!(
+            repo.diff_stat("master", "topic").unwrap(),
+            "2 files changed, 3 insertions(+)"
+        );
+
+        // AND it is empty if there are no changes
+        assert_eq!(repo.diff_stat("topic", "topic").unwrap(), "");
+    }
+
+    #[test]
+    fn is_detached_head() {
+        // GIVEN a repository
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+
+        // THEN HEAD is not detached while a branch is checked out
+        assert_eq!(repo.is_detached_head(), Ok(false));
+
+        // AND it is detached after checking out a commit
+        repo.git("checkout", &["--detach"]).unwrap();
+        assert_eq!(repo.is_detached_head(), Ok(true));
+    }
+
+    #[test]
+    fn get_branch_commit_date() {
+        // GIVEN a repository with a commit dated 2001-09-09
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        let status = Command::new("git")
+            .current_dir(dir.path())
+            .env("GIT_COMMITTER_DATE", "1000000000 +0000")
+            .args(["commit", "--allow-empty", "-m", "Old"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // THEN its date is returned
+        assert_eq!(
+            repo.get_branch_commit_date("master").unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+        );
+    }
+
+    /// Merges `branch` into the current branch, with a merge commit dated `timestamp`
+    fn merge_branch_at(repo: &Repository, branch: &str, timestamp: u64) {
+        let status = Command::new("git")
+            .current_dir(&repo.path)
+            .env("GIT_COMMITTER_DATE", format!("{} +0000", timestamp))
+            .args(["merge", "--no-ff", branch, "-m", "Merge"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn merge_commit_date() {
+        // GIVEN a repository with a branch merged in 2001, and another one merged in 2004
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        for (branch, timestamp) in [("old", 1_000_000_000), ("recent", 1_100_000_000)] {
+            repo.git("checkout", &["-b", branch]).unwrap();
+            repo.git("commit", &["--allow-empty", "-m", branch])
+                .unwrap();
+            repo.checkout("master").unwrap();
+            merge_branch_at(&repo, branch, timestamp);
+        }
+
+        // AND an unmerged branch
+        repo.git("checkout", &["-b", "unmerged"]).unwrap();
+        repo.git("commit", &["--allow-empty", "-m", "unmerged"])
+            .unwrap();
+        repo.checkout("master").unwrap();
+
+        // THEN the date of the merge commit of each merged branch is returned
+        assert_eq!(
+            repo.merge_commit_date("old", "master"),
+            Ok(Some(UNIX_EPOCH + Duration::from_secs(1_000_000_000)))
+        );
+        assert_eq!(
+            repo.merge_commit_date("recent", "master"),
+            Ok(Some(UNIX_EPOCH + Duration::from_secs(1_100_000_000)))
+        );
+
+        // AND there is no date for the unmerged branch
+        assert_eq!(repo.merge_commit_date("unmerged", "master"), Ok(None));
+    }
+
+    #[test]
+    fn cherry_missing_commits() {
+        // GIVEN a repository with a topic branch containing two commits
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path();
+        let repo = create_test_repository(path);
+        repo.git("checkout", &["-b", "topic"]).unwrap();
+        for name in ["topic1", "topic2"] {
+            File::create(path.join(name)).unwrap();
+            repo.git("add", &[name]).unwrap();
+            repo.git("commit", &["-m", name]).unwrap();
+        }
+        let topic2_sha1 = repo.rev_parse("topic").unwrap();
+
+        // AND only the first commit has been cherry-picked in master
+        repo.checkout("master").unwrap();
+        repo.git("cherry-pick", &["topic~1"]).unwrap();
+
+        // THEN only the second commit is missing from master
+        assert_eq!(
+            repo.cherry_missing_commits("master", "topic").unwrap(),
+            &[topic2_sha1]
+        );
+
+        // WHEN the second commit is cherry-picked too
+        repo.git("cherry-pick", &["topic"]).unwrap();
+
+        // THEN no commits are missing
+        assert!(repo
+            .cherry_missing_commits("master", "topic")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn list_branches_filtered_all() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_repository_with_merged_branches(dir.path());
+
+        let branches = repo.list_branches_filtered(MergeFilter::All).unwrap();
+
+        assert_eq!(branches, &["master", "merged", "unmerged"]);
+    }
+
+    #[test]
+    fn list_branches_filtered_merged_into() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_repository_with_merged_branches(dir.path());
+
+        let branches = repo
+            .list_branches_filtered(MergeFilter::MergedInto("master"))
+            .unwrap();
+
+        assert_eq!(branches, &["master", "merged"]);
+    }
+
+    #[test]
+    fn list_merged_branches() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_repository_with_merged_branches(dir.path());
+        assert_eq!(
+            repo.list_merged_branches("master").unwrap(),
+            &["master", "merged"]
+        );
+        assert_eq!(
+            repo.list_merged_branches("unmerged").unwrap(),
+            &["master", "merged", "unmerged"]
+        );
+    }
+
+    #[test]
+    fn list_branches_filtered_not_merged() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_repository_with_merged_branches(dir.path());
+
+        let branches = repo
+            .list_branches_filtered(MergeFilter::NotMerged("master"))
             .unwrap();
 
+        assert_eq!(branches, &["unmerged"]);
+    }
+
+    #[test]
+    fn backup_refs() {
+        // GIVEN a repository with a topic branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.create_branch("topic", None).unwrap();
+        let sha1 = repo.rev_parse("topic").unwrap();
+
+        // WHEN I create a backup ref for it
+        repo.create_backup_ref("topic").unwrap();
+
+        // THEN it is listed
+        assert_eq!(
+            repo.list_backup_refs().unwrap(),
+            &[("topic".to_string(), sha1)]
+        );
+
+        // AND it is gone after deleting backup refs
+        repo.delete_backup_refs().unwrap();
+        assert_eq!(repo.list_backup_refs().unwrap(), &[]);
+    }
+
+    #[test]
+    fn build_containment_map_matches_list_branches_containing() {
+        // GIVEN a repository with many branches, some merged, some stacked, some identical
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path();
+        let repo = create_test_repository(path);
+        for idx in 0..50 {
+            if idx % 3 == 0 {
+                repo.checkout("master").unwrap();
+            }
+            let name = format!("topic{}", idx);
+            repo.git("checkout", &["-b", &name]).unwrap();
+            File::create(path.join(&name)).unwrap();
+            repo.git("add", &[&name]).unwrap();
+            repo.git("commit", &["-m", &name]).unwrap();
+            if idx % 10 == 0 {
+                repo.create_branch(&format!("alias{}", idx), None).unwrap();
+            }
+            if idx % 7 == 0 {
+                repo.checkout("master").unwrap();
+                repo.git("merge", &["--no-ff", &name, "-m", "Merge"])
+                    .unwrap();
+            }
+        }
+
+        // WHEN I build the containment map
+        let map = repo.build_containment_map().unwrap();
+
+        // THEN it matches the result of listing the branches containing each branch
+        let branches = repo.list_branches().unwrap();
+        assert_eq!(map.len(), branches.len());
+        for branch in branches {
+            let expected: HashSet<String> = repo
+                .list_branches_containing(&branch)
+                .unwrap()
+                .into_iter()
+                .collect();
+            assert_eq!(map[&branch], expected, "Mismatch for {}", branch);
+        }
+    }
+
+    #[test]
+    fn list_branches_with_sha1s() {
+        // GIVEN a repository with two branches
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+
+        repo.git("checkout", &["-b", "test"]).unwrap();
+        File::create(dir.path().join("test")).unwrap();
+        repo.git("add", &["test"]).unwrap();
+        repo.git("commit", &["-m", "Create file"]).unwrap();
+
         // WHEN I list branches with sha1
         let branches_with_sha1 = repo.list_branches_with_sha1s().unwrap();
 
@@ -373,19 +2480,80 @@ mod tests {
     }
 
     #[test]
-    fn list_branches_skip_worktree_branches() {
+    fn list_branches_with_sha1s_unusual_names() {
+        // GIVEN a repository with a branch whose name contains a slash and a dot
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.create_branch("feature/v1.2", None).unwrap();
+
+        // WHEN I list branches with sha1
+        let branches_with_sha1 = repo.list_branches_with_sha1s().unwrap();
+
+        // THEN the branch name is returned unchanged
+        let sha1 = repo.rev_parse("HEAD").unwrap();
+        assert_eq!(
+            branches_with_sha1,
+            vec![
+                ("feature/v1.2".to_string(), sha1.clone()),
+                ("master".to_string(), sha1)
+            ]
+        );
+    }
+
+    #[test]
+    fn list_branches_with_sha1s_returns_full_sha1s() {
+        // GIVEN a repository with two branches pointing to the same commit
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.create_branch("topic", None).unwrap();
+
+        // AND a short default abbreviation length
+        repo.git("config", &["core.abbrev", "4"]).unwrap();
+
+        // WHEN I list branches with sha1
+        let branches_with_sha1 = repo.list_branches_with_sha1s().unwrap();
+
+        // THEN both branches have the full sha1 of the commit
+        let sha1 = repo.rev_parse("HEAD").unwrap();
+        assert_eq!(sha1.len(), 40);
+        assert_eq!(
+            branches_with_sha1,
+            vec![
+                ("master".to_string(), sha1.clone()),
+                ("topic".to_string(), sha1)
+            ]
+        );
+    }
+
+    #[test]
+    fn abbreviated_sha() {
+        // GIVEN a repository
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        let sha1 = repo.rev_parse("HEAD").unwrap();
+
+        // WHEN I abbreviate the sha1 of its commit
+        let short_sha1 = repo.abbreviated_sha(&sha1).unwrap();
+
+        // THEN it is a prefix of the full sha1
+        assert!(short_sha1.len() < sha1.len());
+        assert!(sha1.starts_with(&short_sha1));
+    }
+
+    #[test]
+    fn list_branches_includes_worktree_branches() {
         // GIVEN a source repository with two branches
         let tmp_dir = assert_fs::TempDir::new().unwrap();
 
         let source_path = tmp_dir.path().join("source");
         fs::create_dir_all(&source_path).unwrap();
         let source_repo = create_test_repository(&source_path);
-        source_repo.git("branch", &["topic1"]).unwrap();
+        source_repo.create_branch("topic1", None).unwrap();
 
         // AND a clone of this repository
         let clone_path = tmp_dir.path().join("clone");
         fs::create_dir_all(&clone_path).unwrap();
-        let clone_repo = Repository::clone(&clone_path, &source_path.to_str().unwrap()).unwrap();
+        let clone_repo = Repository::clone(&clone_path, source_path.to_str().unwrap()).unwrap();
 
         // with the topic1 branch checked-out in a separate worktree
         let worktree_dir = assert_fs::TempDir::new().unwrap();
@@ -397,9 +2565,377 @@ mod tests {
         // WHEN I list branches
         let branches = clone_repo.list_branches().unwrap();
 
-        // THEN it does not list worktree branches
-        assert_eq!(branches.len(), 1);
-        assert_eq!(branches, &["master"]);
+        // THEN it lists the worktree branch too
+        assert_eq!(branches, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn fetch_dry_run_does_not_update_refs() {
+        // GIVEN a source repository
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let source_path = tmp_dir.path().join("source");
+        fs::create_dir_all(&source_path).unwrap();
+        let source_repo = create_test_repository(&source_path);
+
+        // AND a clone of this repository
+        let clone_path = tmp_dir.path().join("clone");
+        fs::create_dir_all(&clone_path).unwrap();
+        let clone_repo = Repository::clone(&clone_path, source_path.to_str().unwrap()).unwrap();
+        let old_sha1 = clone_repo.git("rev-parse", &["origin/master"]).unwrap();
+
+        // AND a new commit in the source repository
+        File::create(source_path.join("new")).unwrap();
+        source_repo.git("add", &["new"]).unwrap();
+        source_repo.git("commit", &["-m", "Create new"]).unwrap();
+
+        // WHEN I call fetch_dry_run() on the clone
+        let result = clone_repo.fetch_dry_run("origin").unwrap();
+
+        // THEN it reports the incoming changes
+        assert!(result.contains("master"));
+
+        // AND the remote branch has not been updated
+        let sha1 = clone_repo.git("rev-parse", &["origin/master"]).unwrap();
+        assert_eq!(sha1, old_sha1);
+    }
+
+    #[test]
+    fn ahead_behind() {
+        // GIVEN a source repository
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let source_path = tmp_dir.path().join("source");
+        fs::create_dir_all(&source_path).unwrap();
+        let source_repo = create_test_repository(&source_path);
+
+        // AND a clone of this repository
+        let clone_path = tmp_dir.path().join("clone");
+        fs::create_dir_all(&clone_path).unwrap();
+        let clone_repo = Repository::clone(&clone_path, source_path.to_str().unwrap()).unwrap();
+        clone_repo.git("config", &["user.name", "test"]).unwrap();
+        clone_repo
+            .git("config", &["user.email", "test@example.com"])
+            .unwrap();
+
+        // THEN master is in sync with its upstream
+        assert_eq!(clone_repo.ahead_behind("master"), Ok((0, 0)));
+
+        // WHEN a commit is added to the source repository
+        source_repo
+            .git("commit", &["--allow-empty", "-m", "Remote change"])
+            .unwrap();
+        clone_repo.fetch("origin").unwrap();
+
+        // THEN master is behind its upstream
+        assert_eq!(clone_repo.ahead_behind("master"), Ok((0, 1)));
+
+        // WHEN two commits are added to the clone
+        for msg in ["Local change 1", "Local change 2"] {
+            clone_repo
+                .git("commit", &["--allow-empty", "-m", msg])
+                .unwrap();
+        }
+
+        // THEN master has diverged from its upstream
+        assert_eq!(clone_repo.ahead_behind("master"), Ok((2, 1)));
+
+        // AND a branch without upstream is an error
+        clone_repo.create_branch("local", None).unwrap();
+        assert!(clone_repo.ahead_behind("local").is_err());
+    }
+
+    #[test]
+    fn get_config_value() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+
+        // An absent key has no value
+        assert_eq!(repo.get_config_value("git-bonsai.test"), Ok(None));
+
+        // A present key has a value
+        repo.set_config_key("git-bonsai.test", "first").unwrap();
+        assert_eq!(
+            repo.get_config_value("git-bonsai.test"),
+            Ok(Some("first".to_string()))
+        );
+
+        // The last value of a multi-valued key wins
+        repo.add_config_key("git-bonsai.test", "second").unwrap();
+        assert_eq!(
+            repo.get_config_value("git-bonsai.test"),
+            Ok(Some("second".to_string()))
+        );
+    }
+
+    #[test]
+    fn get_config_bool() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+
+        // An absent key has no value
+        assert_eq!(repo.get_config_bool("git-bonsai.test"), Ok(None));
+
+        // Git spellings of booleans are supported
+        for (value, expected) in [("true", true), ("yes", true), ("off", false), ("0", false)] {
+            repo.set_config_key("git-bonsai.test", value).unwrap();
+            assert_eq!(repo.get_config_bool("git-bonsai.test"), Ok(Some(expected)));
+        }
+
+        // The last value of a multi-valued key wins
+        repo.add_config_key("git-bonsai.test", "true").unwrap();
+        assert_eq!(repo.get_config_bool("git-bonsai.test"), Ok(Some(true)));
+
+        // A value which is not a boolean is an error
+        repo.set_config_key("git-bonsai.other", "maybe").unwrap();
+        assert!(repo.get_config_bool("git-bonsai.other").is_err());
+    }
+
+    #[test]
+    fn rebase_onto_upstream() {
+        // GIVEN a source repository
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let source_path = tmp_dir.path().join("source");
+        fs::create_dir_all(&source_path).unwrap();
+        let source_repo = create_test_repository(&source_path);
+
+        // AND a clone of this repository
+        let clone_path = tmp_dir.path().join("clone");
+        fs::create_dir_all(&clone_path).unwrap();
+        let clone_repo = Repository::clone(&clone_path, source_path.to_str().unwrap()).unwrap();
+        clone_repo.git("config", &["user.name", "test"]).unwrap();
+        clone_repo
+            .git("config", &["user.email", "test@example.com"])
+            .unwrap();
+
+        // AND a master branch which has diverged from its upstream, without conflicts
+        let commit_file = |repo: &Repository, name: &str, content: &str| {
+            fs::write(repo.path.join(name), content).unwrap();
+            repo.git("add", &[name]).unwrap();
+            repo.git("commit", &["-m", name]).unwrap();
+        };
+        commit_file(&source_repo, "remote", "remote");
+        commit_file(&clone_repo, "local", "local");
+        clone_repo.fetch("origin").unwrap();
+
+        // WHEN master is rebased on its upstream
+        let result = clone_repo.rebase_onto_upstream();
+
+        // THEN it succeeds, and master is now only ahead of its upstream
+        assert_eq!(result, Ok(()));
+        assert_eq!(clone_repo.ahead_behind("master"), Ok((1, 0)));
+
+        // WHEN master diverges again, with a conflict
+        commit_file(&source_repo, "f", "remote");
+        commit_file(&clone_repo, "f", "local");
+        clone_repo.fetch("origin").unwrap();
+        let sha1 = clone_repo.rev_parse("master").unwrap();
+
+        // AND master is rebased on its upstream
+        let result = clone_repo.rebase_onto_upstream();
+
+        // THEN it fails
+        assert!(result.is_err());
+
+        // AND the rebase has been aborted, leaving master unchanged
+        assert_eq!(clone_repo.current_branch(), Ok(Some("master".to_string())));
+        assert_eq!(clone_repo.rev_parse("master"), Ok(sha1));
+        assert_status(&clone_repo, false, false, false);
+    }
+
+    #[test]
+    fn list_branches_with_upstream() {
+        // GIVEN a source repository with two topic branches
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let source_path = tmp_dir.path().join("source");
+        fs::create_dir_all(&source_path).unwrap();
+        let source_repo = create_test_repository(&source_path);
+        source_repo.create_branch("topic1", None).unwrap();
+        source_repo.create_branch("topic2", None).unwrap();
+
+        // AND a clone of this repository, with branches tracking them and a local-only branch
+        let clone_path = tmp_dir.path().join("clone");
+        fs::create_dir_all(&clone_path).unwrap();
+        let clone_repo = Repository::clone(&clone_path, source_path.to_str().unwrap()).unwrap();
+        for branch in ["topic1", "topic2"] {
+            clone_repo
+                .git(
+                    "branch",
+                    &["--track", branch, &format!("origin/{}", branch)],
+                )
+                .unwrap();
+        }
+        clone_repo.create_branch("local", None).unwrap();
+
+        // AND topic2 is gone from the source repository
+        source_repo.delete_branch("topic2").unwrap();
+        clone_repo.fetch("origin").unwrap();
+
+        // THEN the upstream of each branch is listed, and whether it is gone
+        assert_eq!(
+            clone_repo.list_branches_with_upstream(),
+            Ok(vec![
+                ("local".to_string(), None, false),
+                (
+                    "master".to_string(),
+                    Some("origin/master".to_string()),
+                    false
+                ),
+                (
+                    "topic1".to_string(),
+                    Some("origin/topic1".to_string()),
+                    false
+                ),
+                (
+                    "topic2".to_string(),
+                    Some("origin/topic2".to_string()),
+                    true
+                ),
+            ])
+        );
+
+        // AND only the branches whose upstream still exists are tracking branches
+        assert_eq!(
+            clone_repo.list_tracking_branches("origin"),
+            Ok(vec!["master".to_string(), "topic1".to_string()])
+        );
+        assert_eq!(clone_repo.list_tracking_branches("other"), Ok(vec![]));
+    }
+
+    #[test]
+    fn get_upstream() {
+        // GIVEN a source repository with a topic branch
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let source_path = tmp_dir.path().join("source");
+        fs::create_dir_all(&source_path).unwrap();
+        let source_repo = create_test_repository(&source_path);
+        source_repo.create_branch("topic", None).unwrap();
+
+        // AND a clone of this repository, with a branch tracking topic and a local-only branch
+        let clone_path = tmp_dir.path().join("clone");
+        fs::create_dir_all(&clone_path).unwrap();
+        let clone_repo = Repository::clone(&clone_path, source_path.to_str().unwrap()).unwrap();
+        clone_repo
+            .git("branch", &["--track", "topic", "origin/topic"])
+            .unwrap();
+        clone_repo.create_branch("local", None).unwrap();
+
+        // THEN the upstream of the tracking branches is reported
+        assert_eq!(
+            clone_repo.get_upstream("master"),
+            Ok(Some("origin/master".to_string()))
+        );
+        assert_eq!(
+            clone_repo.get_upstream("topic"),
+            Ok(Some("origin/topic".to_string()))
+        );
+
+        // AND the local-only branch has no upstream
+        assert_eq!(clone_repo.get_upstream("local"), Ok(None));
+
+        // WHEN the upstream branch is gone
+        source_repo.delete_branch("topic").unwrap();
+        clone_repo.fetch("origin").unwrap();
+
+        // THEN it is still reported
+        assert_eq!(
+            clone_repo.get_upstream("topic"),
+            Ok(Some("origin/topic".to_string()))
+        );
+    }
+
+    #[test]
+    fn list_worktrees() {
+        // GIVEN a repository with two branches
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(tmp_dir.path());
+        repo.create_branch("topic1", None).unwrap();
+
+        // AND topic1 checked out in a linked worktree
+        let linked_dir = assert_fs::TempDir::new().unwrap();
+        repo.git(
+            "worktree",
+            &["add", linked_dir.path().to_str().unwrap(), "topic1"],
+        )
+        .unwrap();
+
+        // AND a linked worktree with a detached HEAD
+        let detached_dir = assert_fs::TempDir::new().unwrap();
+        repo.git(
+            "worktree",
+            &["add", "--detach", detached_dir.path().to_str().unwrap()],
+        )
+        .unwrap();
+
+        // WHEN I list the worktrees
+        let worktrees = repo.list_worktrees().unwrap();
+
+        // THEN the main worktree comes first, followed by the linked ones
+        let canonicalize = |path: &Path| fs::canonicalize(path).unwrap();
+        let summary: Vec<(PathBuf, Option<&str>, bool, bool)> = worktrees
+            .iter()
+            .map(|x| {
+                (
+                    canonicalize(&x.path),
+                    x.branch.as_deref(),
+                    x.bare,
+                    x.detached,
+                )
+            })
+            .collect();
+        assert_eq!(summary.len(), 3);
+        assert_eq!(
+            summary[0],
+            (canonicalize(tmp_dir.path()), Some("master"), false, false)
+        );
+        assert!(summary.contains(&(
+            canonicalize(linked_dir.path()),
+            Some("topic1"),
+            false,
+            false
+        )));
+        assert!(summary.contains(&(canonicalize(detached_dir.path()), None, false, true)));
+    }
+
+    #[test]
+    fn branches_in_use_by_worktrees() {
+        // GIVEN a repository with two branches
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(tmp_dir.path());
+        repo.create_branch("topic1", None).unwrap();
+        repo.create_branch("topic2", None).unwrap();
+
+        // with the topic1 branch checked-out in a separate worktree
+        let worktree_dir = assert_fs::TempDir::new().unwrap();
+        let worktree_path_str = worktree_dir.path().to_str().unwrap();
+        repo.git("worktree", &["add", worktree_path_str, "topic1"])
+            .unwrap();
+
+        // WHEN I list the branches in use by worktrees
+        let branches = repo.branches_in_use_by_worktrees().unwrap();
+
+        // THEN it contains the branches of the main and the linked worktrees
+        let expected: HashSet<String> =
+            ["master", "topic1"].iter().map(|x| x.to_string()).collect();
+        assert_eq!(branches, expected);
+    }
+
+    #[test]
+    fn is_checked_out_in_worktree() {
+        // GIVEN a repository with a topic branch checked out in a linked worktree
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.create_branch("topic", None).unwrap();
+        repo.create_branch("other", None).unwrap();
+        let worktree_dir = assert_fs::TempDir::new().unwrap();
+        repo.git(
+            "worktree",
+            &["add", worktree_dir.path().to_str().unwrap(), "topic"],
+        )
+        .unwrap();
+
+        // THEN the branches of both worktrees are reported as checked out
+        assert_eq!(repo.is_checked_out_in_worktree("topic"), Ok(true));
+        assert_eq!(repo.is_checked_out_in_worktree("master"), Ok(true));
+        assert_eq!(repo.is_checked_out_in_worktree("other"), Ok(false));
     }
 
     #[test]
@@ -413,25 +2949,181 @@ mod tests {
         // AND a clone of this repository
         let clone_path = tmp_dir.path().join("clone");
         fs::create_dir_all(&clone_path).unwrap();
-        let clone_repo = Repository::clone(&clone_path, &source_path.to_str().unwrap()).unwrap();
+        let clone_repo = Repository::clone(&clone_path, source_path.to_str().unwrap()).unwrap();
 
         // WHEN I call find_default_branch() on the clone
-        let branch = clone_repo.find_default_branch();
+        let branch = clone_repo.find_default_branch("origin");
 
         // THEN it finds the default branch name
         assert_eq!(branch, Ok("master".to_string()));
     }
 
     #[test]
-    fn find_default_branch_no_remote() {
-        // GIVEN a repository without a remote
+    fn find_default_branch_from_remote_head() {
+        // GIVEN a source repository whose default branch is "trunk"
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let source_path = tmp_dir.path().join("source");
+        fs::create_dir_all(&source_path).unwrap();
+        let source_repo = create_test_repository(&source_path);
+        source_repo
+            .git("branch", &["-m", "master", "trunk"])
+            .unwrap();
+
+        // AND a clone of this repository, without a local copy of the remote HEAD
+        let clone_path = tmp_dir.path().join("clone");
+        fs::create_dir_all(&clone_path).unwrap();
+        let clone_repo = Repository::clone(&clone_path, source_path.to_str().unwrap()).unwrap();
+        clone_repo
+            .git("remote", &["set-head", "origin", "--delete"])
+            .unwrap();
+
+        // WHEN I call find_default_branch() on the clone
+        let branch = clone_repo.find_default_branch("origin");
+
+        // THEN it finds the default branch name by querying the remote
+        assert_eq!(branch, Ok("trunk".to_string()));
+    }
+
+    #[test]
+    fn find_default_branch_from_init_default_branch() {
+        // GIVEN a repository without a remote, whose only branch is "trunk"
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(tmp_dir.path());
+        repo.git("branch", &["-m", "master", "trunk"]).unwrap();
+
+        // AND init.defaultBranch is set to "trunk"
+        repo.set_config_key("init.defaultBranch", "trunk").unwrap();
+
+        // WHEN I call find_default_branch()
+        let branch = repo.find_default_branch("origin");
+
+        // THEN it returns the branch from init.defaultBranch
+        assert_eq!(branch, Ok("trunk".to_string()));
+    }
+
+    #[test]
+    fn find_default_branch_from_well_known_names() {
+        // GIVEN a repository without a remote, with a master branch
+        let tmp_dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(tmp_dir.path());
+
+        // AND init.defaultBranch is set to a branch which does not exist
+        repo.set_config_key("init.defaultBranch", "nonexistent")
+            .unwrap();
+
+        // WHEN I call find_default_branch()
+        let branch = repo.find_default_branch("origin");
+
+        // THEN it returns the master branch
+        assert_eq!(branch, Ok("master".to_string()));
+    }
+
+    #[test]
+    fn find_default_branch_not_found() {
+        // GIVEN a repository without a remote, whose only branch is "trunk"
         let tmp_dir = assert_fs::TempDir::new().unwrap();
-        let repo = create_test_repository(&tmp_dir.path());
+        let repo = create_test_repository(tmp_dir.path());
+        repo.git("branch", &["-m", "master", "trunk"]).unwrap();
+
+        // AND init.defaultBranch is set to a branch which does not exist
+        repo.set_config_key("init.defaultBranch", "nonexistent")
+            .unwrap();
 
         // WHEN I call find_default_branch()
-        let branch = repo.find_default_branch();
+        let branch = repo.find_default_branch("origin");
 
         // THEN it fails
-        assert_eq!(branch, Err(GitError::CommandFailed { exit_code: 128 }));
+        assert_eq!(branch, Err(GitError::NoDefaultBranch));
+    }
+
+    #[test]
+    fn timeout() {
+        // GIVEN a repository with a slow git alias
+        let dir = assert_fs::TempDir::new().unwrap();
+        let mut repo = create_test_repository(dir.path());
+        repo.git("config", &["alias.slow", "!sleep 2"]).unwrap();
+
+        // AND a short timeout
+        repo.set_timeout(Some(Duration::from_millis(100)));
+
+        // WHEN I run the slow alias
+        let start = Instant::now();
+        let result = repo.git("slow", &[]);
+
+        // THEN it times out, without waiting for the command to finish
+        assert_eq!(
+            result,
+            Err(GitError::TimedOut {
+                command: "slow ".to_string()
+            })
+        );
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // AND fast commands still work
+        assert_eq!(repo.current_branch().unwrap().unwrap(), "master");
+    }
+
+    #[test]
+    fn command_failed_display() {
+        let error = GitError::CommandFailed {
+            command: "checkout foo".to_string(),
+            exit_code: 1,
+            stderr: "error: pathspec 'foo' did not match any file(s) known to git".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "'git checkout foo' exited with code 1: \
+            error: pathspec 'foo' did not match any file(s) known to git"
+        );
+    }
+
+    #[test]
+    fn command_failed_error_recognizes_locks() {
+        let index_lock = "fatal: Unable to create '/repo/.git/index.lock': File exists.";
+        assert_eq!(
+            command_failed_error("checkout foo".to_string(), 128, index_lock.to_string()),
+            GitError::Locked {
+                command: "checkout foo".to_string(),
+                stderr: index_lock.to_string(),
+            }
+        );
+
+        let ref_lock = "error: cannot lock ref 'refs/heads/foo': Unable to create \
+            '/repo/.git/refs/heads/foo.lock': File exists.";
+        assert_eq!(
+            command_failed_error("branch -D foo".to_string(), 1, ref_lock.to_string()),
+            GitError::Locked {
+                command: "branch -D foo".to_string(),
+                stderr: ref_lock.to_string(),
+            }
+        );
+
+        let other = "error: branch 'foo' not found.";
+        assert_eq!(
+            command_failed_error("branch -D foo".to_string(), 1, other.to_string()),
+            GitError::CommandFailed {
+                command: "branch -D foo".to_string(),
+                exit_code: 1,
+                stderr: other.to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn delete_branch_locked() {
+        // GIVEN a repository with a topic branch
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.create_branch("topic", None).unwrap();
+        repo.checkout("master").unwrap();
+
+        // AND another git process holding the lock on its ref
+        File::create(dir.path().join(".git/refs/heads/topic.lock")).unwrap();
+
+        // WHEN the branch is deleted
+        let result = repo.delete_branch("topic");
+
+        // THEN it fails with a lock error
+        assert!(matches!(result, Err(GitError::Locked { .. })));
     }
 }
@@ -0,0 +1,93 @@
+/*
+ * Copyright 2026 Aurélien Gâteau <mail@agateau.com>
+ *
+ * This file is part of git-bonsai.
+ *
+ * Git-bonsai is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 3 of the License, or (at your option)
+ * any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+/// A minimal glob matcher for branch names.
+///
+/// Patterns are matched against the full branch name. `*` matches any sequence of characters,
+/// including `/`, and `?` matches exactly one character. All other characters match themselves.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let mut p = 0;
+    let mut t = 0;
+    // Position of the last `*` in the pattern, and of the text character it was matched at,
+    // to backtrack when a mismatch happens
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the last `*` match one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&x| x == '*')
+}
+
+/// Returns true if `text` matches at least one of `patterns`
+pub fn matches_any(patterns: &[String], text: &str) -> bool {
+    patterns.iter().any(|x| matches(x, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_match() {
+        assert!(matches("master", "master"));
+        assert!(!matches("master", "main"));
+        assert!(!matches("master", "master2"));
+    }
+
+    #[test]
+    fn star() {
+        assert!(matches("feature/*", "feature/a"));
+        assert!(matches("feature/*", "feature/"));
+        assert!(matches("feature/*", "feature/a/b"));
+        assert!(!matches("feature/*", "bugfix/a"));
+        assert!(matches("*-wip", "topic-wip"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn question_mark() {
+        assert!(matches("release/?.0", "release/1.0"));
+        assert!(!matches("release/?.0", "release/10.0"));
+    }
+
+    #[test]
+    fn any() {
+        let patterns = vec!["feature/*".to_string(), "fix/*".to_string()];
+        assert!(matches_any(&patterns, "fix/a"));
+        assert!(!matches_any(&patterns, "topic"));
+        assert!(!matches_any(&[], "topic"));
+    }
+}
@@ -0,0 +1,137 @@
+/*
+ * Copyright 2026 Aurélien Gâteau <mail@agateau.com>
+ *
+ * This file is part of git-bonsai.
+ *
+ * Git-bonsai is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 3 of the License, or (at your option)
+ * any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use std::collections::HashSet;
+
+/// What to do with a group of branches pointing to the same commit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdenticalGroupPlan {
+    pub sha1: String,
+    /// Branches which can be deleted: neither protected nor excluded by --include, sorted
+    pub deletable: Vec<String>,
+    /// Protected branches of the group, sorted
+    pub protected: Vec<String>,
+    /// Unprotected branches excluded by --include, sorted
+    pub excluded: Vec<String>,
+    /// Set if no branch outside of the group keeps the commit alive: one of the deletable
+    /// branches must then be kept
+    pub keep_one: bool,
+}
+
+fn sorted(branches: Vec<&String>) -> Vec<String> {
+    let mut branches: Vec<String> = branches.into_iter().cloned().collect();
+    branches.sort();
+    branches
+}
+
+impl IdenticalGroupPlan {
+    /// Plans what to do with `branches`. `contained_elsewhere` must be true if a branch which is
+    /// not part of the group contains the commit.
+    pub fn new(
+        sha1: &str,
+        branches: &HashSet<String>,
+        protected: &HashSet<String>,
+        is_included: impl Fn(&str) -> bool,
+        contained_elsewhere: bool,
+    ) -> IdenticalGroupPlan {
+        let (deletable, excluded): (Vec<&String>, Vec<&String>) =
+            branches.difference(protected).partition(|x| is_included(x));
+        let protected = sorted(branches.intersection(protected).collect());
+        let excluded = sorted(excluded);
+        // A protected or excluded branch of the group keeps the commit alive too
+        let keep_one = !contained_elsewhere && protected.is_empty() && excluded.is_empty();
+        IdenticalGroupPlan {
+            sha1: sha1.to_string(),
+            deletable: sorted(deletable),
+            protected,
+            excluded,
+            keep_one,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branch_set(branches: &[&str]) -> HashSet<String> {
+        branches.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn plan(
+        branches: &[&str],
+        protected: &[&str],
+        include_prefix: &str,
+        contained_elsewhere: bool,
+    ) -> IdenticalGroupPlan {
+        IdenticalGroupPlan::new(
+            "1234abc",
+            &branch_set(branches),
+            &branch_set(protected),
+            |x| x.starts_with(include_prefix),
+            contained_elsewhere,
+        )
+    }
+
+    #[test]
+    fn unprotected_not_contained_elsewhere_keeps_one() {
+        let plan = plan(&["b", "a"], &[], "", false);
+        assert_eq!(
+            plan,
+            IdenticalGroupPlan {
+                sha1: "1234abc".to_string(),
+                deletable: vec!["a".to_string(), "b".to_string()],
+                protected: vec![],
+                excluded: vec![],
+                keep_one: true,
+            }
+        );
+    }
+
+    #[test]
+    fn contained_elsewhere_deletes_all() {
+        let plan = plan(&["b", "a"], &["master"], "", true);
+        assert_eq!(plan.deletable, &["a", "b"]);
+        assert!(plan.protected.is_empty());
+        assert!(!plan.keep_one);
+    }
+
+    #[test]
+    fn protected_branch_keeps_the_commit() {
+        let plan = plan(&["topic", "master", "dup"], &["master"], "", false);
+        assert_eq!(plan.deletable, &["dup", "topic"]);
+        assert_eq!(plan.protected, &["master"]);
+        assert!(!plan.keep_one);
+    }
+
+    #[test]
+    fn only_protected_branches() {
+        let plan = plan(&["main", "master"], &["main", "master"], "", false);
+        assert!(plan.deletable.is_empty());
+        assert_eq!(plan.protected, &["main", "master"]);
+        assert!(!plan.keep_one);
+    }
+
+    #[test]
+    fn excluded_branch_keeps_the_commit() {
+        let plan = plan(&["feature/a", "feature/b", "other"], &[], "feature/", false);
+        assert_eq!(plan.deletable, &["feature/a", "feature/b"]);
+        assert_eq!(plan.excluded, &["other"]);
+        assert!(!plan.keep_one);
+    }
+}
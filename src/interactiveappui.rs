@@ -16,24 +16,103 @@
  * You should have received a copy of the GNU General Public License along with
  * this program.  If not, see <http://www.gnu.org/licenses/>.
  */
-use crate::appui::{AppUi, BranchToDeleteInfo};
-use crate::tui;
+use std::cell::Cell;
 
-pub struct InteractiveAppUi;
+use crate::appui::{AppUi, BranchToDeleteInfo, Verbosity};
+use crate::batchappui;
+use crate::report::{ProtectedBranch, Report, RunReport};
+use crate::tui::{self, Confirmation};
+
+use console::style;
+
+pub struct InteractiveAppUi {
+    verbosity: Verbosity,
+    // If true, branches are selected by default
+    preselect: bool,
+    // Set when the user answers "all yes" or "all no" to confirm_delete(), to give the same
+    // answer to the following branches without asking
+    confirm_delete_answer: Cell<Option<bool>>,
+}
+
+impl InteractiveAppUi {
+    pub fn new(verbosity: Verbosity, preselect: bool) -> InteractiveAppUi {
+        InteractiveAppUi {
+            verbosity,
+            preselect,
+            confirm_delete_answer: Cell::new(None),
+        }
+    }
+}
+
+/// Colors the insertions and deletions of a `git diff --shortstat` summary
+fn format_diffstat(diffstat: &str) -> String {
+    if diffstat.is_empty() {
+        return "no changes".to_string();
+    }
+    diffstat
+        .split(", ")
+        .map(|x| {
+            if x.ends_with("(+)") {
+                style(x).green().to_string()
+            } else if x.ends_with("(-)") {
+                style(x).red().to_string()
+            } else {
+                x.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
 
 fn format_branch_info(branch_info: &BranchToDeleteInfo) -> String {
-    let container_str = branch_info
-        .contained_in
+    let mut contained_in: Vec<&String> = branch_info.contained_in.iter().collect();
+    contained_in.sort();
+    let container_str = contained_in
         .iter()
         .map(|x| format!("      - {}", x))
         .collect::<Vec<String>>()
         .join("\n");
 
-    format!("{}, contained in:\n{} \n", branch_info.name, container_str)
+    let name_str = match branch_info.unique_commits {
+        0 => branch_info.name.clone(),
+        1 => format!("{} (1 commit ahead of the base branch)", branch_info.name),
+        count => format!(
+            "{} ({} commits ahead of the base branch)",
+            branch_info.name, count
+        ),
+    };
+
+    let rewritten_merge_str = match branch_info.rewritten_merge {
+        Some(rewritten_merge) => format!(", {}", rewritten_merge),
+        None => "".to_string(),
+    };
+    let identical_str = if branch_info.identical_to.is_empty() {
+        "".to_string()
+    } else {
+        format!(", identical to {}", branch_info.identical_to.join(", "))
+    };
+    let diffstat_str = match &branch_info.diffstat {
+        Some(diffstat) => format!("\n      {}", format_diffstat(diffstat)),
+        None => "".to_string(),
+    };
+    if branch_info.contained_in.is_empty() {
+        return format!(
+            "{}{}{}{}\n",
+            name_str, rewritten_merge_str, identical_str, diffstat_str
+        );
+    }
+
+    format!(
+        "{}{}{}, contained in:\n{}{} \n",
+        name_str, rewritten_merge_str, identical_str, container_str, diffstat_str
+    )
 }
 
 impl AppUi for InteractiveAppUi {
     fn log_info(&self, msg: &str) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
         tui::log_info(msg);
     }
 
@@ -45,6 +124,10 @@ impl AppUi for InteractiveAppUi {
         tui::log_error(msg);
     }
 
+    fn report_progress(&self, current: usize, total: usize, label: &str) {
+        self.log_info(&format!("[{}/{}] {}", current, total, label));
+    }
+
     fn select_branches_to_delete(
         &self,
         branch_infos: &[BranchToDeleteInfo],
@@ -54,7 +137,7 @@ impl AppUi for InteractiveAppUi {
             .map(format_branch_info)
             .collect::<Vec<String>>();
 
-        let selections = tui::select("Select branches to delete", &select_items);
+        let selections = tui::select("Select branches to delete", &select_items, self.preselect);
 
         selections
             .iter()
@@ -71,6 +154,7 @@ impl AppUi for InteractiveAppUi {
             so it is safe to delete them all.\n\
             Select branches to delete",
             &items,
+            self.preselect,
         );
 
         selections
@@ -79,9 +163,18 @@ impl AppUi for InteractiveAppUi {
             .collect::<Vec<String>>()
     }
 
-    fn select_identical_branches_to_delete_keep_one(&self, branches: &[String]) -> Vec<String> {
+    fn select_identical_branches_to_delete_keep_one(
+        &self,
+        branches: &[String],
+        preferred: &str,
+    ) -> Vec<String> {
         let mut items = branches.to_vec();
         items.sort();
+        // Never preselect the preferred branch, so that accepting the defaults keeps it
+        let checked_items: Vec<(String, bool)> = items
+            .iter()
+            .map(|x| (x.clone(), self.preselect && x != preferred))
+            .collect();
 
         let mut selections: Vec<usize>;
         println!(
@@ -89,7 +182,7 @@ impl AppUi for InteractiveAppUi {
                 so you can delete all of them but one.\n"
         );
         loop {
-            selections = tui::select("Select branches to delete", &items);
+            selections = tui::select_checked("Select branches to delete", &checked_items);
             if selections.len() == items.len() {
                 self.log_error("You must leave at least one branch unchecked.");
             } else {
@@ -109,4 +202,73 @@ impl AppUi for InteractiveAppUi {
         tui::select_one("Select the branch to use as the default branch", &items)
             .map(|x| items[x].clone())
     }
+
+    fn confirm_delete(&self, branch: &str, log: &str) -> bool {
+        if let Some(answer) = self.confirm_delete_answer.get() {
+            return answer;
+        }
+        if log.is_empty() {
+            println!("{} has no commits which are not in the base branch", branch);
+        } else {
+            println!(
+                "Commits of {} which are not in the base branch:\n{}",
+                branch, log
+            );
+        }
+        match tui::confirm_with_all(&format!("Delete {}?", branch)) {
+            Confirmation::Yes => true,
+            Confirmation::No => false,
+            Confirmation::AllYes => {
+                self.confirm_delete_answer.set(Some(true));
+                true
+            }
+            Confirmation::AllNo => {
+                self.confirm_delete_answer.set(Some(false));
+                false
+            }
+        }
+    }
+
+    fn confirm_bulk_delete(&self, branches: &[String]) -> bool {
+        println!("Branches to delete:");
+        for branch in branches {
+            println!("- {}", branch);
+        }
+        let msg = match branches.len() {
+            1 => "Delete this branch?".to_string(),
+            count => format!("Delete these {} branches?", count),
+        };
+        tui::confirm(&msg)
+    }
+
+    fn confirm_delete_remote(&self, remote: &str, branch: &str) -> bool {
+        tui::confirm(&format!("Delete {} from remote {}?", branch, remote))
+    }
+
+    fn report_kept(&self, kept: &[(String, String)]) {
+        println!("Kept branches:");
+        for (branch, reason) in kept {
+            println!("- {} kept: {}", branch, reason);
+        }
+    }
+
+    fn show_report(&self, report: &Report) {
+        print!("{}", report);
+    }
+
+    fn show_list(&self, deletable: &[String], identical: &[Vec<String>]) {
+        batchappui::print_list(deletable, identical);
+    }
+
+    fn show_protected(&self, protected: &[ProtectedBranch]) {
+        batchappui::print_protected(protected);
+    }
+
+    fn report_plan(&self, before: &[String], after: &[String]) {
+        batchappui::print_plan(before, after);
+    }
+
+    fn show_summary(&self, _summary: &RunReport) {
+        // Actions have already been logged as they happened
+    }
 }
@@ -0,0 +1,134 @@
+/*
+ * Copyright 2026 Aurélien Gâteau <mail@agateau.com>
+ *
+ * This file is part of git-bonsai.
+ *
+ * Git-bonsai is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 3 of the License, or (at your option)
+ * any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::appui::{AppUi, BranchToDeleteInfo, Verbosity};
+use crate::batchappui;
+use crate::report::{ProtectedBranch, Report, RunReport};
+
+/// An AppUi implementation for machine-readable output: reports and run summaries are printed to
+/// stdout as JSON, logs go to stderr and all deletable branches are selected, like in batch mode
+pub struct JsonAppUi {
+    verbosity: Verbosity,
+}
+
+impl JsonAppUi {
+    pub fn new(verbosity: Verbosity) -> JsonAppUi {
+        JsonAppUi { verbosity }
+    }
+}
+
+impl AppUi for JsonAppUi {
+    fn log_info(&self, msg: &str) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        eprintln!("Info: {}", msg);
+    }
+
+    fn log_warning(&self, msg: &str) {
+        eprintln!("Warning: {}", msg);
+    }
+
+    fn log_error(&self, msg: &str) {
+        eprintln!("Error: {}", msg);
+    }
+
+    fn report_progress(&self, current: usize, total: usize, label: &str) {
+        self.log_info(&format!("[{}/{}] {}", current, total, label));
+    }
+
+    fn select_branches_to_delete(
+        &self,
+        branch_infos: &[BranchToDeleteInfo],
+    ) -> Vec<BranchToDeleteInfo> {
+        branch_infos.to_vec()
+    }
+
+    fn select_identical_branches_to_delete(&self, branches: &[String]) -> Vec<String> {
+        branches.to_vec()
+    }
+
+    fn select_identical_branches_to_delete_keep_one(
+        &self,
+        branches: &[String],
+        preferred: &str,
+    ) -> Vec<String> {
+        batchappui::select_all_but_one(branches, preferred)
+    }
+
+    fn select_default_branch(&self, branches: &[String]) -> Option<String> {
+        batchappui::guess_default_branch(branches)
+    }
+
+    fn confirm_delete(&self, _branch: &str, _log: &str) -> bool {
+        true
+    }
+
+    fn confirm_bulk_delete(&self, _branches: &[String]) -> bool {
+        true
+    }
+
+    fn confirm_delete_remote(&self, _remote: &str, _branch: &str) -> bool {
+        // app::run() refuses --delete-remote in json mode unless --yes is set
+        true
+    }
+
+    fn report_kept(&self, kept: &[(String, String)]) {
+        // Skipped branches are already part of the summary, so only log them
+        for (branch, reason) in kept {
+            eprintln!("{} kept: {}", branch, reason);
+        }
+    }
+
+    fn show_report(&self, report: &Report) {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(report).expect("Failed to serialize report")
+        );
+    }
+
+    fn show_list(&self, deletable: &[String], identical: &[Vec<String>]) {
+        let list = serde_json::json!({
+            "deletable": deletable,
+            "identical": identical,
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&list).expect("Failed to serialize list")
+        );
+    }
+
+    fn show_protected(&self, protected: &[ProtectedBranch]) {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(protected)
+                .expect("Failed to serialize protected branches")
+        );
+    }
+
+    fn report_plan(&self, _before: &[String], _after: &[String]) {
+        // Only the summary is printed, so that the output is a single JSON document
+    }
+
+    fn show_summary(&self, summary: &RunReport) {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(summary).expect("Failed to serialize summary")
+        );
+    }
+}
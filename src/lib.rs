@@ -20,6 +20,13 @@ pub mod app;
 pub mod appui;
 pub mod batchappui;
 pub mod cliargs;
+pub mod configfile;
 pub mod git;
+pub mod glob;
+pub mod identical;
 pub mod interactiveappui;
+pub mod jsonappui;
+pub mod logfile;
+pub mod ndjsonappui;
+pub mod report;
 pub mod tui;
@@ -0,0 +1,99 @@
+/*
+ * Copyright 2026 Aurélien Gâteau <mail@agateau.com>
+ *
+ * This file is part of git-bonsai.
+ *
+ * Git-bonsai is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 3 of the License, or (at your option)
+ * any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Appends timestamped records of the deleted and updated branches to a file, with --log-file
+pub struct LogFile {
+    file: File,
+}
+
+impl LogFile {
+    /// Opens `path` for appending, creating it if it does not exist
+    pub fn open(path: &Path) -> io::Result<LogFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(LogFile { file })
+    }
+
+    /// Appends a line like "2024-01-01T12:00:00Z deleted topic1", and flushes it so that the
+    /// record is not lost if git-bonsai gets interrupted
+    pub fn log(&self, action: &str, branch: &str) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+        let mut file = &self.file;
+        writeln!(
+            file,
+            "{} {} {}",
+            format_timestamp(timestamp),
+            action,
+            branch
+        )?;
+        file.flush()
+    }
+}
+
+/// Formats a Unix timestamp as an ISO 8601 UTC date, like "2024-01-01T12:00:00Z"
+fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Convert days since 1970-01-01 to a civil date, using Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_timestamp_epoch() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn format_timestamp_dates() {
+        assert_eq!(format_timestamp(1_000_000_000), "2001-09-09T01:46:40Z");
+        assert_eq!(format_timestamp(1_704_110_400), "2024-01-01T12:00:00Z");
+        // Leap day
+        assert_eq!(format_timestamp(1_709_208_000), "2024-02-29T12:00:00Z");
+        assert_eq!(format_timestamp(1_735_689_599), "2024-12-31T23:59:59Z");
+    }
+}
@@ -18,17 +18,11 @@
  */
 use structopt::StructOpt;
 
-mod app;
-mod appui;
-mod batchappui;
-mod cliargs;
-mod git;
-mod interactiveappui;
-mod tui;
-
-use cliargs::CliArgs;
+use git_bonsai::app;
+use git_bonsai::cliargs::CliArgs;
 
 fn main() {
     let args = CliArgs::from_args();
-    ::std::process::exit(app::run(args, "."));
+    let dir = args.repo.clone();
+    ::std::process::exit(app::run(args, &dir));
 }
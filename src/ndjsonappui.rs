@@ -0,0 +1,135 @@
+/*
+ * Copyright 2026 Aurélien Gâteau <mail@agateau.com>
+ *
+ * This file is part of git-bonsai.
+ *
+ * Git-bonsai is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 3 of the License, or (at your option)
+ * any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use serde_json::json;
+
+use crate::appui::{AppUi, BranchToDeleteInfo, Verbosity};
+use crate::batchappui;
+use crate::report::{ProtectedBranch, Report, RunReport};
+
+/// An AppUi implementation streaming events: each log, progress report and result is printed to
+/// stdout as soon as it happens, as a JSON object on its own line. Nothing is printed to stderr,
+/// so that a frontend only has to read stdout. Branches are selected like in batch mode.
+pub struct NdjsonAppUi {
+    verbosity: Verbosity,
+}
+
+impl NdjsonAppUi {
+    pub fn new(verbosity: Verbosity) -> NdjsonAppUi {
+        NdjsonAppUi { verbosity }
+    }
+
+    fn emit(&self, event: serde_json::Value) {
+        println!("{}", event);
+    }
+}
+
+impl AppUi for NdjsonAppUi {
+    fn log_info(&self, msg: &str) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        self.emit(json!({"event": "info", "message": msg}));
+    }
+
+    fn log_warning(&self, msg: &str) {
+        self.emit(json!({"event": "warning", "message": msg}));
+    }
+
+    fn log_error(&self, msg: &str) {
+        self.emit(json!({"event": "error", "message": msg}));
+    }
+
+    fn report_progress(&self, current: usize, total: usize, label: &str) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        self.emit(json!({
+            "event": "progress",
+            "current": current,
+            "total": total,
+            "label": label,
+        }));
+    }
+
+    fn select_branches_to_delete(
+        &self,
+        branch_infos: &[BranchToDeleteInfo],
+    ) -> Vec<BranchToDeleteInfo> {
+        branch_infos.to_vec()
+    }
+
+    fn select_identical_branches_to_delete(&self, branches: &[String]) -> Vec<String> {
+        branches.to_vec()
+    }
+
+    fn select_identical_branches_to_delete_keep_one(
+        &self,
+        branches: &[String],
+        preferred: &str,
+    ) -> Vec<String> {
+        batchappui::select_all_but_one(branches, preferred)
+    }
+
+    fn select_default_branch(&self, branches: &[String]) -> Option<String> {
+        batchappui::guess_default_branch(branches)
+    }
+
+    fn confirm_delete(&self, _branch: &str, _log: &str) -> bool {
+        true
+    }
+
+    fn confirm_bulk_delete(&self, _branches: &[String]) -> bool {
+        true
+    }
+
+    fn confirm_delete_remote(&self, _remote: &str, _branch: &str) -> bool {
+        // app::run() refuses --delete-remote with --events unless --yes is set
+        true
+    }
+
+    fn report_kept(&self, kept: &[(String, String)]) {
+        for (branch, reason) in kept {
+            self.emit(json!({"event": "kept", "branch": branch, "reason": reason}));
+        }
+    }
+
+    fn show_report(&self, report: &Report) {
+        self.emit(json!({"event": "report", "report": report}));
+    }
+
+    fn show_list(&self, deletable: &[String], identical: &[Vec<String>]) {
+        self.emit(json!({
+            "event": "list",
+            "deletable": deletable,
+            "identical": identical,
+        }));
+    }
+
+    fn show_protected(&self, protected: &[ProtectedBranch]) {
+        self.emit(json!({"event": "protected", "protected": protected}));
+    }
+
+    fn report_plan(&self, before: &[String], after: &[String]) {
+        self.emit(json!({"event": "plan", "before": before, "after": after}));
+    }
+
+    fn show_summary(&self, summary: &RunReport) {
+        self.emit(json!({"event": "summary", "summary": summary}));
+    }
+}
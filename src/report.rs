@@ -0,0 +1,151 @@
+/*
+ * Copyright 2026 Aurélien Gâteau <mail@agateau.com>
+ *
+ * This file is part of git-bonsai.
+ *
+ * Git-bonsai is free software: you can redistribute it and/or modify it under
+ * the terms of the GNU General Public License as published by the Free
+ * Software Foundation, either version 3 of the License, or (at your option)
+ * any later version.
+ *
+ * This program is distributed in the hope that it will be useful, but WITHOUT
+ * ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+ * FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General Public License for
+ * more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+/**
+ * This module contains the types describing what git-bonsai found in a repository
+ */
+use std::fmt;
+
+use serde::Serialize;
+
+/// Why a branch is protected
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProtectionSource {
+    DefaultBranch,
+    GitConfig,
+    GlobalGitConfig,
+    ConfigFile,
+    CommandLine,
+    MergedInto,
+    KeepMergedInto,
+    Tagged,
+    Tracked,
+    Worktree,
+    CurrentBranch,
+}
+
+impl fmt::Display for ProtectionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            ProtectionSource::DefaultBranch => "default branch",
+            ProtectionSource::GitConfig => "git config",
+            ProtectionSource::GlobalGitConfig => "global git config",
+            ProtectionSource::ConfigFile => ".git-bonsai.toml",
+            ProtectionSource::CommandLine => "command line",
+            ProtectionSource::MergedInto => "--merged-into target",
+            ProtectionSource::KeepMergedInto => "merged into the --keep-merged-into branch",
+            ProtectionSource::Tagged => "last commit is tagged",
+            ProtectionSource::Tracked => "upstream still exists",
+            ProtectionSource::Worktree => "checked out in a worktree",
+            ProtectionSource::CurrentBranch => "current branch",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// How a branch whose commits are not in the base branch has been detected as merged, with
+/// --detect-squashed or --detect-rebased
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RewrittenMerge {
+    SquashMerged,
+    RebaseMerged,
+}
+
+impl fmt::Display for RewrittenMerge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            RewrittenMerge::SquashMerged => "squash-merged",
+            RewrittenMerge::RebaseMerged => "rebase-merged",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProtectedBranch {
+    pub name: String,
+    pub reason: ProtectionSource,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeletableBranch {
+    pub name: String,
+    pub contained_in: Vec<String>,
+    pub rewritten_merge: Option<RewrittenMerge>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SkippedBranch {
+    pub name: String,
+    pub reason: String,
+}
+
+/// A read-only snapshot of the branches of a repository, as produced by --report-only
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub default_branch: Option<String>,
+    pub protected: Vec<ProtectedBranch>,
+    pub deletable: Vec<DeletableBranch>,
+    pub skipped: Vec<SkippedBranch>,
+}
+
+/// What a run did, as returned by App::run() and printed by --format json
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RunReport {
+    pub deleted: Vec<String>,
+    /// The new names of the branches archived with --archive-prefix
+    pub archived: Vec<String>,
+    pub updated: Vec<String>,
+    pub skipped: Vec<SkippedBranch>,
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Default branch: {}",
+            self.default_branch.as_deref().unwrap_or("unknown")
+        )?;
+
+        writeln!(f, "Protected branches:")?;
+        for branch in &self.protected {
+            writeln!(f, "- {} ({})", branch.name, branch.reason)?;
+        }
+
+        writeln!(f, "Deletable branches:")?;
+        for branch in &self.deletable {
+            write!(f, "- {}", branch.name)?;
+            if let Some(rewritten_merge) = branch.rewritten_merge {
+                write!(f, ", {}", rewritten_merge)?;
+            }
+            if !branch.contained_in.is_empty() {
+                write!(f, ", contained in: {}", branch.contained_in.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f, "Skipped branches:")?;
+        for branch in &self.skipped {
+            writeln!(f, "- {}: {}", branch.name, branch.reason)?;
+        }
+        Ok(())
+    }
+}
@@ -20,9 +20,20 @@
 /**
  * This module contains "low-level" primitives to implement a text-based UI
  */
+use std::env;
+
 use console::style;
 
-use dialoguer::{MultiSelect, Select};
+use dialoguer::{Confirm, Input, MultiSelect, Select};
+
+/// Disables colors if `no_color` is true or if the NO_COLOR environment variable is set.
+/// Otherwise the console crate enables colors only if stdout is a terminal.
+pub fn init_colors(no_color: bool) {
+    let no_color_env = env::var_os("NO_COLOR").is_some_and(|x| !x.is_empty());
+    if no_color || no_color_env {
+        console::set_colors_enabled(false);
+    }
+}
 
 pub fn log_warning(msg: &str) {
     println!("{}", style(format!("Warning: {}", msg)).yellow());
@@ -36,16 +47,63 @@ pub fn log_info(msg: &str) {
     println!("{}", style(format!("Info: {}", msg)).blue());
 }
 
-pub fn select(msg: &str, items: &[String]) -> Vec<usize> {
-    let checked_items: Vec<(String, bool)> = items.iter().map(|x| (x.clone(), true)).collect();
+/// Returns the items with their initial checked state
+fn checked_items(items: &[String], preselect: bool) -> Vec<(String, bool)> {
+    items.iter().map(|x| (x.clone(), preselect)).collect()
+}
+
+/// Lets the user select items. If `preselect` is true, all items are checked at start.
+pub fn select(msg: &str, items: &[String], preselect: bool) -> Vec<usize> {
+    select_checked(msg, &checked_items(items, preselect))
+}
 
+/// Lets the user select items, each item being paired with its initial checked state
+pub fn select_checked(msg: &str, checked_items: &[(String, bool)]) -> Vec<usize> {
     MultiSelect::new()
         .with_prompt(msg)
-        .items_checked(&checked_items[..])
+        .items_checked(checked_items)
         .interact()
         .unwrap()
 }
 
+pub fn confirm(msg: &str) -> bool {
+    Confirm::new().with_prompt(msg).interact().unwrap()
+}
+
+/// An answer to confirm_with_all()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Confirmation {
+    Yes,
+    No,
+    /// Yes to this question and to all the following ones
+    AllYes,
+    /// No to this question and to all the following ones
+    AllNo,
+}
+
+/// Parses an answer to confirm_with_all(). Returns None if the answer is not valid.
+fn parse_confirmation(answer: &str) -> Option<Confirmation> {
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Some(Confirmation::Yes),
+        "n" | "no" => Some(Confirmation::No),
+        "a" | "all" => Some(Confirmation::AllYes),
+        "d" | "done" => Some(Confirmation::AllNo),
+        _ => None,
+    }
+}
+
+/// Like confirm(), but also lets the user answer the same way to all the following questions
+pub fn confirm_with_all(msg: &str) -> Confirmation {
+    let prompt = format!("{} [y]es, [n]o, [a]ll yes, [d]one (all no)", msg);
+    loop {
+        let answer: String = Input::new().with_prompt(&prompt).interact_text().unwrap();
+        if let Some(confirmation) = parse_confirmation(&answer) {
+            return confirmation;
+        }
+        log_error("Please answer y, n, a or d");
+    }
+}
+
 pub fn select_one(msg: &str, items: &[String]) -> Option<usize> {
     Select::new()
         .with_prompt(msg)
@@ -54,3 +112,44 @@ pub fn select_one(msg: &str, items: &[String]) -> Option<usize> {
         .interact_opt()
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_items_preselect() {
+        let items = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(
+            checked_items(&items, true),
+            vec![("a".to_string(), true), ("b".to_string(), true)]
+        );
+    }
+
+    #[test]
+    fn parse_confirmation_valid() {
+        assert_eq!(parse_confirmation("y"), Some(Confirmation::Yes));
+        assert_eq!(parse_confirmation("Yes"), Some(Confirmation::Yes));
+        assert_eq!(parse_confirmation("n"), Some(Confirmation::No));
+        assert_eq!(parse_confirmation(" no "), Some(Confirmation::No));
+        assert_eq!(parse_confirmation("a"), Some(Confirmation::AllYes));
+        assert_eq!(parse_confirmation("all"), Some(Confirmation::AllYes));
+        assert_eq!(parse_confirmation("D"), Some(Confirmation::AllNo));
+        assert_eq!(parse_confirmation("done"), Some(Confirmation::AllNo));
+    }
+
+    #[test]
+    fn parse_confirmation_invalid() {
+        assert_eq!(parse_confirmation(""), None);
+        assert_eq!(parse_confirmation("maybe"), None);
+    }
+
+    #[test]
+    fn checked_items_no_preselect() {
+        let items = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(
+            checked_items(&items, false),
+            vec![("a".to_string(), false), ("b".to_string(), false)]
+        );
+    }
+}
@@ -22,19 +22,32 @@ mod integ {
     extern crate claim;
     extern crate git_bonsai;
 
-    use std::collections::HashSet;
-    use std::fs::File;
+    use std::cell::RefCell;
+    use std::collections::{HashMap, HashSet};
+    use std::fs::{self, File};
+    use std::io::{BufRead, BufReader};
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Output, Stdio};
+    use std::rc::Rc;
+    use std::sync::Mutex;
+    use std::time::SystemTime;
     use structopt::StructOpt;
 
     use assert_fs::prelude::*;
     use claim::*;
     use predicates::prelude::*;
 
-    use git_bonsai::app::{self, App, AppError, DEFAULT_BRANCH_CONFIG_KEY};
+    use git_bonsai::app::{
+        self, restore_interrupted_run, App, AppError, RunError, RunRestorePoint,
+        DEFAULT_BRANCH_CONFIG_KEY,
+    };
+    use git_bonsai::appui::{AppUi, BranchToDeleteInfo, Verbosity};
     use git_bonsai::batchappui::BatchAppUi;
     use git_bonsai::cliargs::CliArgs;
+    use git_bonsai::configfile::{ConfigFile, CONFIG_FILE_NAME};
     use git_bonsai::git::create_test_repository;
-    use git_bonsai::git::Repository;
+    use git_bonsai::git::{BranchRestorer, GitError, GitRepository, MergeFilter, Repository};
+    use git_bonsai::report::{ProtectedBranch, Report, RewrittenMerge, RunReport, SkippedBranch};
 
     fn create_repository() -> (assert_fs::TempDir, Repository) {
         let dir = assert_fs::TempDir::new().unwrap();
@@ -46,13 +59,13 @@ mod integ {
 
     fn clone_repository(url: &str) -> (assert_fs::TempDir, Repository) {
         let dir = assert_fs::TempDir::new().unwrap();
-        let repo = Repository::clone(dir.path(), &url).unwrap();
+        let repo = Repository::clone(dir.path(), url).unwrap();
         (dir, repo)
     }
 
     fn create_branch(repo: &Repository, name: &str) {
         repo.git("checkout", &["-b", name]).unwrap();
-        create_and_commit_file(&repo, name);
+        create_and_commit_file(repo, &name.replace('/', "-"));
     }
 
     fn create_and_commit_file(repo: &Repository, name: &str) {
@@ -71,15 +84,450 @@ mod integ {
         let mut full_argv = vec!["git-bonsai"];
         full_argv.extend(argv);
         let args = CliArgs::from_iter(full_argv);
-        app::run(args, &cwd)
+        app::run(args, cwd)
+    }
+
+    /// Runs the git-bonsai executable, so that its output can be checked
+    fn run_git_bonsai_process(cwd: &str, argv: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_git-bonsai"))
+            .current_dir(cwd)
+            .env_remove("GB_DEBUG")
+            .args(argv)
+            .output()
+            .unwrap()
     }
 
     fn create_app(cwd: &str, argv: &[&str]) -> App {
+        create_app_with_ui(cwd, argv, Box::new(BatchAppUi::new(Verbosity::Normal)))
+    }
+
+    fn create_app_with_ui(cwd: &str, argv: &[&str], ui: Box<dyn AppUi>) -> App {
         let mut full_argv = vec!["git-bonsai"];
         full_argv.extend(argv);
-        let ui = Box::new(BatchAppUi {});
         let args = CliArgs::from_iter(full_argv);
-        App::new(&args, ui, &cwd)
+        App::new(&args, &ConfigFile::default(), ui, cwd).unwrap()
+    }
+
+    /// An interactive-like UI which never selects anything, as if the user always declined
+    struct DecliningAppUi;
+
+    impl AppUi for DecliningAppUi {
+        fn log_info(&self, _msg: &str) {}
+        fn log_warning(&self, _msg: &str) {}
+        fn log_error(&self, _msg: &str) {}
+        fn report_progress(&self, _current: usize, _total: usize, _label: &str) {}
+
+        fn select_branches_to_delete(
+            &self,
+            _branch_infos: &[BranchToDeleteInfo],
+        ) -> Vec<BranchToDeleteInfo> {
+            Vec::new()
+        }
+
+        fn select_identical_branches_to_delete(&self, _branches: &[String]) -> Vec<String> {
+            Vec::new()
+        }
+
+        fn select_identical_branches_to_delete_keep_one(
+            &self,
+            _branches: &[String],
+            _preferred: &str,
+        ) -> Vec<String> {
+            Vec::new()
+        }
+
+        fn select_default_branch(&self, _branches: &[String]) -> Option<String> {
+            None
+        }
+
+        fn confirm_delete(&self, _branch: &str, _log: &str) -> bool {
+            false
+        }
+
+        fn confirm_bulk_delete(&self, _branches: &[String]) -> bool {
+            false
+        }
+
+        fn confirm_delete_remote(&self, _remote: &str, _branch: &str) -> bool {
+            false
+        }
+
+        fn report_kept(&self, _kept: &[(String, String)]) {}
+
+        fn show_report(&self, _report: &Report) {}
+
+        fn show_list(&self, _deletable: &[String], _identical: &[Vec<String>]) {}
+
+        fn show_protected(&self, _protected: &[ProtectedBranch]) {}
+
+        fn report_plan(&self, _before: &[String], _after: &[String]) {}
+
+        fn show_summary(&self, _summary: &RunReport) {}
+    }
+
+    /// Behaves like BatchAppUi, except for the hooks set by the test
+    struct TestAppUi {
+        batch_ui: BatchAppUi,
+        /// If set, this branch is selected first by select_branches_to_delete(), even if it is not
+        /// deletable
+        unsafe_branch: Option<String>,
+        /// If set, the final confirmation before deleting branches is declined
+        decline_bulk_delete: bool,
+        /// The branches offered by each selection prompt
+        prompts: Rc<RefCell<Vec<Vec<String>>>>,
+        /// The branches of each final confirmation before deleting branches
+        bulk_confirmations: Rc<RefCell<Vec<Vec<String>>>>,
+    }
+
+    impl TestAppUi {
+        fn new() -> TestAppUi {
+            TestAppUi {
+                batch_ui: BatchAppUi::new(Verbosity::Normal),
+                unsafe_branch: None,
+                decline_bulk_delete: false,
+                prompts: Rc::new(RefCell::new(Vec::new())),
+                bulk_confirmations: Rc::new(RefCell::new(Vec::new())),
+            }
+        }
+
+        fn with_unsafe_branch(branch: &str) -> TestAppUi {
+            TestAppUi {
+                unsafe_branch: Some(branch.to_string()),
+                ..TestAppUi::new()
+            }
+        }
+    }
+
+    impl AppUi for TestAppUi {
+        fn log_info(&self, msg: &str) {
+            self.batch_ui.log_info(msg);
+        }
+        fn log_warning(&self, msg: &str) {
+            self.batch_ui.log_warning(msg);
+        }
+        fn log_error(&self, msg: &str) {
+            self.batch_ui.log_error(msg);
+        }
+        fn report_progress(&self, current: usize, total: usize, label: &str) {
+            self.batch_ui.report_progress(current, total, label);
+        }
+        fn select_branches_to_delete(
+            &self,
+            branch_infos: &[BranchToDeleteInfo],
+        ) -> Vec<BranchToDeleteInfo> {
+            self.prompts
+                .borrow_mut()
+                .push(branch_infos.iter().map(|x| x.name.clone()).collect());
+            let mut selection = Vec::new();
+            if let Some(branch) = &self.unsafe_branch {
+                selection.push(BranchToDeleteInfo {
+                    name: branch.clone(),
+                    contained_in: HashSet::new(),
+                    rewritten_merge: None,
+                    unique_commits: 0,
+                    diffstat: None,
+                    identical_to: Vec::new(),
+                });
+            }
+            selection.extend(self.batch_ui.select_branches_to_delete(branch_infos));
+            selection
+        }
+        fn select_identical_branches_to_delete(&self, branches: &[String]) -> Vec<String> {
+            self.prompts.borrow_mut().push(branches.to_vec());
+            self.batch_ui.select_identical_branches_to_delete(branches)
+        }
+        fn select_identical_branches_to_delete_keep_one(
+            &self,
+            branches: &[String],
+            preferred: &str,
+        ) -> Vec<String> {
+            self.prompts.borrow_mut().push(branches.to_vec());
+            self.batch_ui
+                .select_identical_branches_to_delete_keep_one(branches, preferred)
+        }
+        fn select_default_branch(&self, branches: &[String]) -> Option<String> {
+            self.batch_ui.select_default_branch(branches)
+        }
+        fn confirm_delete(&self, branch: &str, log: &str) -> bool {
+            self.batch_ui.confirm_delete(branch, log)
+        }
+        fn confirm_bulk_delete(&self, branches: &[String]) -> bool {
+            self.bulk_confirmations.borrow_mut().push(branches.to_vec());
+            !self.decline_bulk_delete && self.batch_ui.confirm_bulk_delete(branches)
+        }
+        fn confirm_delete_remote(&self, remote: &str, branch: &str) -> bool {
+            self.batch_ui.confirm_delete_remote(remote, branch)
+        }
+        fn report_kept(&self, kept: &[(String, String)]) {
+            self.batch_ui.report_kept(kept);
+        }
+        fn show_report(&self, report: &Report) {
+            self.batch_ui.show_report(report);
+        }
+        fn show_list(&self, deletable: &[String], identical: &[Vec<String>]) {
+            self.batch_ui.show_list(deletable, identical);
+        }
+        fn show_protected(&self, protected: &[ProtectedBranch]) {
+            self.batch_ui.show_protected(protected);
+        }
+        fn report_plan(&self, before: &[String], after: &[String]) {
+            self.batch_ui.report_plan(before, after);
+        }
+        fn show_summary(&self, summary: &RunReport) {
+            self.batch_ui.show_summary(summary);
+        }
+    }
+
+    /// A GitRepository which does not run git: the branches, and the branches containing them,
+    /// are defined by the test. Only the operations needed to analyze branches are implemented:
+    /// the others return empty values, or fail with the error returned by unsupported().
+    struct MockRepository {
+        path: PathBuf,
+        default_branch: String,
+        /// branch => branches containing it, including itself
+        containment_map: HashMap<String, HashSet<String>>,
+        /// If set, current_branch() fails, as if git had timed out
+        current_branch_fails: bool,
+    }
+
+    impl MockRepository {
+        fn new(default_branch: &str, containment: &[(&str, &[&str])]) -> MockRepository {
+            let containment_map = containment
+                .iter()
+                .map(|(branch, containers)| {
+                    let mut set: HashSet<String> =
+                        containers.iter().map(|x| x.to_string()).collect();
+                    set.insert(branch.to_string());
+                    (branch.to_string(), set)
+                })
+                .collect();
+            MockRepository {
+                path: PathBuf::from("/does-not-exist"),
+                default_branch: default_branch.to_string(),
+                containment_map,
+                current_branch_fails: false,
+            }
+        }
+    }
+
+    /// The error returned by the operations MockRepository does not implement
+    fn unsupported(operation: &str) -> GitError {
+        GitError::UnexpectedOutput(format!("MockRepository does not support {}()", operation))
+    }
+
+    impl GitRepository for MockRepository {
+        fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn list_remotes(&self) -> Result<Vec<String>, GitError> {
+            Ok(Vec::new())
+        }
+
+        fn fetch_with_args(&self, _args: &[&str]) -> Result<(), GitError> {
+            Err(unsupported("fetch_with_args"))
+        }
+
+        fn fetch_dry_run(&self, _remote: &str) -> Result<String, GitError> {
+            Err(unsupported("fetch_dry_run"))
+        }
+
+        fn get_config_keys(&self, _key: &str) -> Result<Vec<String>, GitError> {
+            Ok(Vec::new())
+        }
+
+        fn get_global_config_keys(&self, _key: &str) -> Result<Vec<String>, GitError> {
+            Ok(Vec::new())
+        }
+
+        fn get_config_value(&self, key: &str) -> Result<Option<String>, GitError> {
+            Ok((key == DEFAULT_BRANCH_CONFIG_KEY).then(|| self.default_branch.clone()))
+        }
+
+        fn set_config_key(&self, _key: &str, _value: &str) -> Result<(), GitError> {
+            Err(unsupported("set_config_key"))
+        }
+
+        fn add_config_key(&self, _key: &str, _value: &str) -> Result<(), GitError> {
+            Err(unsupported("add_config_key"))
+        }
+
+        fn find_default_branch(&self, _remote: &str) -> Result<String, GitError> {
+            Err(unsupported("find_default_branch"))
+        }
+
+        fn list_branches(&self) -> Result<Vec<String>, GitError> {
+            let mut branches: Vec<String> = self.containment_map.keys().cloned().collect();
+            branches.sort();
+            Ok(branches)
+        }
+
+        fn list_branches_filtered(&self, _filter: MergeFilter) -> Result<Vec<String>, GitError> {
+            Err(unsupported("list_branches_filtered"))
+        }
+
+        fn list_merged_branches(&self, _base: &str) -> Result<Vec<String>, GitError> {
+            Err(unsupported("list_merged_branches"))
+        }
+
+        fn list_branches_with_sha1s(&self) -> Result<Vec<(String, String)>, GitError> {
+            Err(unsupported("list_branches_with_sha1s"))
+        }
+
+        fn list_branches_containing(&self, _commit: &str) -> Result<Vec<String>, GitError> {
+            Err(unsupported("list_branches_containing"))
+        }
+
+        fn build_containment_map(&self) -> Result<HashMap<String, HashSet<String>>, GitError> {
+            Ok(self.containment_map.clone())
+        }
+
+        fn list_tracking_branches(&self, _remote: &str) -> Result<Vec<String>, GitError> {
+            Ok(Vec::new())
+        }
+
+        fn list_branches_with_upstream(
+            &self,
+        ) -> Result<Vec<(String, Option<String>, bool)>, GitError> {
+            Ok(Vec::new())
+        }
+
+        fn branches_in_use_by_worktrees(&self) -> Result<HashSet<String>, GitError> {
+            Ok(HashSet::new())
+        }
+
+        fn is_checked_out_in_worktree(&self, _branch: &str) -> Result<bool, GitError> {
+            Ok(false)
+        }
+
+        fn checkout(&self, _branch: &str) -> Result<(), GitError> {
+            Err(unsupported("checkout"))
+        }
+
+        fn delete_branch(&self, _branch: &str) -> Result<(), GitError> {
+            Err(unsupported("delete_branch"))
+        }
+
+        fn delete_remote_branch(&self, _remote: &str, _branch: &str) -> Result<(), GitError> {
+            Err(unsupported("delete_remote_branch"))
+        }
+
+        fn rename_branch(&self, _old: &str, _new: &str) -> Result<(), GitError> {
+            Err(unsupported("rename_branch"))
+        }
+
+        fn create_branch(&self, _name: &str, _start_point: Option<&str>) -> Result<(), GitError> {
+            Err(unsupported("create_branch"))
+        }
+
+        fn branch_exists(&self, name: &str) -> Result<bool, GitError> {
+            Ok(self.containment_map.contains_key(name))
+        }
+
+        fn rev_parse(&self, _rev: &str) -> Result<String, GitError> {
+            Err(unsupported("rev_parse"))
+        }
+
+        fn abbreviated_sha(&self, _sha1: &str) -> Result<String, GitError> {
+            Err(unsupported("abbreviated_sha"))
+        }
+
+        fn cherry_missing_commits(
+            &self,
+            _base: &str,
+            _branch: &str,
+        ) -> Result<Vec<String>, GitError> {
+            Err(unsupported("cherry_missing_commits"))
+        }
+
+        fn is_squash_merged(&self, _branch: &str, _base: &str) -> Result<bool, GitError> {
+            Err(unsupported("is_squash_merged"))
+        }
+
+        fn count_unique_commits(&self, _branch: &str, _base: &str) -> Result<usize, GitError> {
+            Ok(0)
+        }
+
+        fn ahead_behind(&self, _branch: &str) -> Result<(usize, usize), GitError> {
+            Err(unsupported("ahead_behind"))
+        }
+
+        fn log_range(&self, _from: &str, _to: &str) -> Result<String, GitError> {
+            Err(unsupported("log_range"))
+        }
+
+        fn diff_stat(&self, _from: &str, _to: &str) -> Result<String, GitError> {
+            Err(unsupported("diff_stat"))
+        }
+
+        fn get_branch_commit_date(&self, _branch: &str) -> Result<SystemTime, GitError> {
+            Err(unsupported("get_branch_commit_date"))
+        }
+
+        fn merge_commit_date(
+            &self,
+            _branch: &str,
+            _base: &str,
+        ) -> Result<Option<SystemTime>, GitError> {
+            Err(unsupported("merge_commit_date"))
+        }
+
+        fn is_ancestor(&self, _maybe_ancestor: &str, _descendant: &str) -> Result<bool, GitError> {
+            Err(unsupported("is_ancestor"))
+        }
+
+        fn has_commit(&self, _sha1: &str) -> bool {
+            false
+        }
+
+        fn create_backup_ref(&self, _branch: &str) -> Result<(), GitError> {
+            Err(unsupported("create_backup_ref"))
+        }
+
+        fn list_backup_refs(&self) -> Result<Vec<(String, String)>, GitError> {
+            Ok(Vec::new())
+        }
+
+        fn list_tag_commits(&self) -> Result<HashSet<String>, GitError> {
+            Ok(HashSet::new())
+        }
+
+        fn delete_backup_refs(&self) -> Result<(), GitError> {
+            Err(unsupported("delete_backup_refs"))
+        }
+
+        fn current_branch(&self) -> Result<Option<String>, GitError> {
+            if self.current_branch_fails {
+                return Err(GitError::TimedOut {
+                    command: "symbolic-ref --short HEAD".to_string(),
+                });
+            }
+            Ok(Some(self.default_branch.clone()))
+        }
+
+        fn is_detached_head(&self) -> Result<bool, GitError> {
+            Ok(false)
+        }
+
+        fn update_branch(&self) -> Result<(), GitError> {
+            Err(unsupported("update_branch"))
+        }
+
+        fn rebase_onto_upstream(&self) -> Result<(), GitError> {
+            Err(unsupported("rebase_onto_upstream"))
+        }
+
+        fn has_staged_changes(&self) -> Result<bool, GitError> {
+            Ok(false)
+        }
+
+        fn has_unstaged_changes(&self) -> Result<bool, GitError> {
+            Ok(false)
+        }
+
+        fn has_untracked_files(&self) -> Result<bool, GitError> {
+            Ok(false)
+        }
     }
 
     macro_rules! assert_branches_eq {
@@ -89,6 +537,25 @@ mod integ {
         };
     }
 
+    #[test]
+    fn single_branch() {
+        // GIVEN a repository with a single branch
+        let (dir, repo) = create_repository();
+
+        // WHEN git-bonsai runs
+        let output = run_git_bonsai_process(dir.path().to_str().unwrap(), &["-y", "--no-fetch"]);
+
+        // THEN it explains there is nothing to do, and succeeds
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.contains("Only one branch present, nothing to do"),
+            "{}",
+            stdout
+        );
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
     #[test]
     fn no_op() {
         // GIVEN a repository with a single branch
@@ -96,7 +563,7 @@ mod integ {
         let path_str = dir.path().to_str().unwrap();
 
         // WHEN git-bonsai runs
-        let result = run_git_bonsai(&path_str, &["-y"]);
+        let result = run_git_bonsai(path_str, &["-y"]);
 
         // THEN it succeeds
         assert_eq!(result, 0);
@@ -117,7 +584,7 @@ mod integ {
 
         // WHEN git-bonsai runs
         {
-            let app = create_app(&path_str, &[]);
+            let app = create_app(path_str, &[]);
             assert_ok!(app.remove_merged_branches());
         }
 
@@ -126,126 +593,2467 @@ mod integ {
     }
 
     #[test]
-    fn skip_protected_branch() {
-        // GIVEN a repository with a protected, merged branch: "protected"
+    fn restore_deleted_branches() {
+        // GIVEN a repository with two merged topic branches
         let (dir, repo) = create_repository();
         let path_str = dir.path().to_str().unwrap();
-        create_branch(&repo, "protected");
+        create_branch(&repo, "topic1");
+        create_branch(&repo, "topic2");
         repo.checkout("master").unwrap();
-        merge_branch(&repo, "protected");
+        merge_branch(&repo, "topic2");
+        let topic1_sha1 = repo.rev_parse("topic1").unwrap();
+        let topic2_sha1 = repo.rev_parse("topic2").unwrap();
 
-        assert_branches_eq!(&repo, &["master", "protected"]);
+        // AND git-bonsai deleted them
+        assert_eq!(run_git_bonsai(path_str, &["-y", "--no-fetch"]), 0);
+        assert_branches_eq!(&repo, &["master"]);
 
-        // WHEN git-bonsai runs with "-x protected"
+        // WHEN git-bonsai runs with --restore
+        let result = run_git_bonsai(path_str, &["--restore"]);
+
+        // THEN the branches are back, pointing to the same commits
+        assert_eq!(result, 0);
+        assert_branches_eq!(&repo, &["master", "topic1", "topic2"]);
+        assert_eq!(repo.rev_parse("topic1").unwrap(), topic1_sha1);
+        assert_eq!(repo.rev_parse("topic2").unwrap(), topic2_sha1);
+
+        // WHEN git-bonsai runs with --restore again
+        let result = run_git_bonsai(path_str, &["--restore"]);
+
+        // THEN it succeeds, leaving the existing branches alone
+        assert_eq!(result, 0);
+        assert_branches_eq!(&repo, &["master", "topic1", "topic2"]);
+    }
+
+    #[test]
+    fn include_patterns() {
+        // GIVEN a repository with two branches merged in master: feature/a and bugfix/b
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "feature/a");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "feature/a");
+        create_branch(&repo, "bugfix/b");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "bugfix/b");
+
+        // WHEN git-bonsai runs with --include 'feature/*'
         {
-            let app = create_app(&path_str, &["-x", "protected"]);
+            let app = create_app(path_str, &["--include", "feature/*"]);
             assert_ok!(app.remove_merged_branches());
         }
 
-        // THEN the protected branch is still there
-        assert_branches_eq!(&repo, &["master", "protected"]);
+        // THEN only feature/a has been removed
+        assert_branches_eq!(&repo, &["bugfix/b", "master"]);
+    }
 
-        // WHEN git-bonsai runs without "-x protected"
+    #[test]
+    fn exclude_pattern() {
+        // GIVEN a repository with three branches merged in master: release/1.0, release/2.0 and
+        // topic
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        for branch in ["release/1.0", "release/2.0", "topic"] {
+            create_branch(&repo, branch);
+            repo.checkout("master").unwrap();
+            merge_branch(&repo, branch);
+        }
+
+        // WHEN git-bonsai runs with --exclude-pattern 'release/*'
         {
-            let app = create_app(&path_str, &[]);
+            let app = create_app(path_str, &["--exclude-pattern", "release/*"]);
             assert_ok!(app.remove_merged_branches());
         }
 
-        // THEN the protected branch is gone
-        assert_branches_eq!(&repo, &["master"]);
+        // THEN both release branches are still there
+        assert_branches_eq!(&repo, &["master", "release/1.0", "release/2.0"]);
     }
 
     #[test]
-    fn update_branch() {
-        // GIVEN a source repository
-        let (source_dir, source_repo) = create_repository();
-
-        // AND a clone of it
-        let (clone_dir, _clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
-        let clone_dir_str = clone_dir.path().to_str().unwrap();
+    fn protected_pattern_config() {
+        // GIVEN a repository with two branches merged in master: wip/foo and topic
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        for branch in ["wip/foo", "topic"] {
+            create_branch(&repo, branch);
+            repo.checkout("master").unwrap();
+            merge_branch(&repo, branch);
+        }
 
-        // AND a new commit in the source repository
-        create_and_commit_file(&source_repo, "new");
+        // AND a protected pattern in git config
+        repo.git(
+            "config",
+            &["--add", "git-bonsai.protected-pattern", "wip/*"],
+        )
+        .unwrap();
 
-        // WHEN git-bonsai runs in the clone
-        let result = run_git_bonsai(&clone_dir_str, &["-y"]);
-        assert_eq!(result, 0);
+        // WHEN git-bonsai runs
+        {
+            let app = create_app(path_str, &[]);
+            assert_ok!(app.remove_merged_branches());
+        }
 
-        // THEN the clone repository now contains the new commit
-        clone_dir.child("new").assert(predicate::path::exists());
+        // THEN wip/foo is still there
+        assert_branches_eq!(&repo, &["master", "wip/foo"]);
     }
 
     #[test]
-    fn identical_sha1_no_other_branch() {
-        // GIVEN a repository with three branches pointing to the same sha1, contained in no other
-        // branch
+    fn exclude_pattern_identical_branches() {
+        // GIVEN a repository with release/1.0, release/2.0 and topic pointing to the same sha1,
+        // contained in no other branch
         let (dir, repo) = create_repository();
         let path_str = dir.path().to_str().unwrap();
-        create_branch(&repo, "topic1");
-        repo.git("branch", &["topic2", "topic1"]).unwrap();
-        repo.git("branch", &["topic3", "topic1"]).unwrap();
+        create_branch(&repo, "topic");
+        repo.create_branch("release/1.0", Some("topic")).unwrap();
+        repo.create_branch("release/2.0", Some("topic")).unwrap();
 
-        // WHEN git-bonsai runs
-        let app = create_app(&path_str, &[]);
+        // WHEN git-bonsai runs with --exclude-pattern 'release/*'
+        let app = create_app(path_str, &["--exclude-pattern", "release/*"]);
         assert_ok!(app.delete_identical_branches());
 
-        // THEN only the first topic branch remains
-        assert_branches_eq!(&repo, &["master", "topic1"]);
+        // THEN only topic has been removed
+        assert_branches_eq!(&repo, &["master", "release/1.0", "release/2.0"]);
     }
 
     #[test]
-    fn identical_sha1_contained_in_master() {
-        // GIVEN a repository with two branches pointing to the same sha1, contained in the master
-        // branch
+    fn detect_squashed() {
+        // GIVEN a repository with a topic branch squash-merged in master
         let (dir, repo) = create_repository();
         let path_str = dir.path().to_str().unwrap();
-        repo.git("branch", &["topic1"]).unwrap();
-        repo.git("branch", &["topic2"]).unwrap();
+        create_branch(&repo, "topic");
+        create_and_commit_file(&repo, "topic2");
+        repo.checkout("master").unwrap();
+        repo.git("merge", &["--squash", "topic"]).unwrap();
+        repo.git("commit", &["-m", "Squash-merge topic"]).unwrap();
 
-        // WHEN git-bonsai runs
-        let app = create_app(&path_str, &[]);
-        assert_ok!(app.delete_identical_branches());
+        // WHEN git-bonsai runs without --detect-squashed
+        {
+            let app = create_app(path_str, &[]);
+            assert_ok!(app.remove_merged_branches());
+        }
 
-        // THEN only the master branch remains
+        // THEN the topic branch is still there
+        assert_branches_eq!(&repo, &["master", "topic"]);
+
+        // WHEN git-bonsai runs with --detect-squashed
+        {
+            let app = create_app(path_str, &["--detect-squashed"]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the topic branch has been removed
         assert_branches_eq!(&repo, &["master"]);
     }
 
     #[test]
-    fn skip_worktree_branches() {
-        // GIVEN a source repository with two branches
-        let (source_dir, source_repo) = create_repository();
-        create_branch(&source_repo, "topic1");
-        source_repo.checkout("master").unwrap();
-
-        // AND a clone of this repository
+    fn detect_rewritten_merges_with_orphan_branch() {
+        // GIVEN a repository with a topic branch squash-merged in master
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic");
+        repo.checkout("master").unwrap();
+        repo.git("merge", &["--squash", "topic"]).unwrap();
+        repo.git("commit", &["-m", "Squash-merge topic"]).unwrap();
+
+        // AND an orphan branch, with no common history with master
+        repo.git("checkout", &["--orphan", "gh-pages"]).unwrap();
+        repo.git("commit", &["-m", "gh-pages"]).unwrap();
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai runs with --detect-squashed and --detect-rebased
+        {
+            let app = create_app(path_str, &["--detect-squashed", "--detect-rebased"]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the topic branch has been removed, and the orphan branch has been kept
+        assert_branches_eq!(&repo, &["gh-pages", "master"]);
+    }
+
+    #[test]
+    fn max_age() {
+        // GIVEN a repository with two branches merged in master: old, whose last commit is from
+        // 2001, and recent
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.git("checkout", &["-b", "old"]).unwrap();
+        let status = Command::new("git")
+            .current_dir(dir.path())
+            .env("GIT_COMMITTER_DATE", "1000000000 +0000")
+            .args(["commit", "--allow-empty", "-m", "Old commit"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "old");
+        create_branch(&repo, "recent");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "recent");
+
+        // WHEN git-bonsai runs with --max-age 30
+        {
+            let app = create_app(path_str, &["--max-age", "30"]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN only the old branch has been removed
+        assert_branches_eq!(&repo, &["master", "recent"]);
+    }
+
+    #[test]
+    fn max_age_overflow() {
+        // GIVEN a repository with a branch merged in master
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic");
+
+        // WHEN git-bonsai runs with a --max-age too large to be represented as a date
+        {
+            let max_age = u64::MAX.to_string();
+            let app = create_app(path_str, &["--max-age", &max_age]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the branch has been kept
+        assert_branches_eq!(&repo, &["master", "topic"]);
+    }
+
+    #[test]
+    fn merged_before() {
+        // GIVEN a repository with two branches merged in master: old, merged in 2001, and recent,
+        // merged now. The last commit of both branches is from 2001.
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        for branch in &["old", "recent"] {
+            repo.git("checkout", &["-b", branch]).unwrap();
+            let status = Command::new("git")
+                .current_dir(dir.path())
+                .env("GIT_COMMITTER_DATE", "1000000000 +0000")
+                .args(["commit", "--allow-empty", "-m", branch])
+                .status()
+                .unwrap();
+            assert!(status.success());
+            repo.checkout("master").unwrap();
+        }
+        let status = Command::new("git")
+            .current_dir(dir.path())
+            .env("GIT_COMMITTER_DATE", "1000000000 +0000")
+            .args(["merge", "--no-ff", "old", "-m", "Merging old"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        merge_branch(&repo, "recent");
+
+        // WHEN git-bonsai runs with --merged-before 30
+        {
+            let app = create_app(path_str, &["--merged-before", "30"]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN only the branch merged a long time ago has been removed
+        assert_branches_eq!(&repo, &["master", "recent"]);
+    }
+
+    #[test]
+    fn merged_before_overflow() {
+        // GIVEN a repository with a branch merged in master
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic");
+
+        // WHEN git-bonsai runs with a --merged-before too large to be represented as a date
+        {
+            let merged_before = u64::MAX.to_string();
+            let app = create_app(path_str, &["--merged-before", &merged_before]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the branch has been kept
+        assert_branches_eq!(&repo, &["master", "topic"]);
+    }
+
+    #[test]
+    fn sort_by_date() {
+        // GIVEN a repository with three branches merged in master, whose last commits are from
+        // 2011 for "a-middle", 2001 for "b-old" and now for "c-new"
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        for (branch, date) in &[
+            ("a-middle", "1300000000 +0000"),
+            ("b-old", "1000000000 +0000"),
+        ] {
+            repo.git("checkout", &["-b", branch]).unwrap();
+            let status = Command::new("git")
+                .current_dir(dir.path())
+                .env("GIT_COMMITTER_DATE", date)
+                .args(["commit", "--allow-empty", "-m", branch])
+                .status()
+                .unwrap();
+            assert!(status.success());
+            repo.checkout("master").unwrap();
+            merge_branch(&repo, branch);
+        }
+        create_branch(&repo, "c-new");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "c-new");
+
+        // WHEN I list the deletable branches with --sort date
+        let app = create_app(path_str, &["--sort", "date"]);
+        let branches = app.deletable_branches().unwrap();
+
+        // THEN the branches with the oldest last commit come first
+        let names: Vec<&str> = branches.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, &["b-old", "a-middle", "c-new"]);
+
+        // AND without --sort, they are sorted by name
+        let app = create_app(path_str, &[]);
+        let branches = app.deletable_branches().unwrap();
+        let names: Vec<&str> = branches.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, &["a-middle", "b-old", "c-new"]);
+    }
+
+    /// Creates a repository with a merged branch, "merged", and an unmerged one, "unmerged",
+    /// which is the current branch
+    fn create_repository_with_unmerged_current_branch() -> (assert_fs::TempDir, Repository) {
+        let (dir, repo) = create_repository();
+        create_branch(&repo, "merged");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "merged");
+        create_branch(&repo, "unmerged");
+        (dir, repo)
+    }
+
+    #[test]
+    fn delete_errors_keep_going() {
+        // GIVEN a repository with a merged branch and an unmerged one
+        let (dir, repo) = create_repository_with_unmerged_current_branch();
+        let path_str = dir.path().to_str().unwrap();
+
+        // WHEN git-bonsai tries to delete both
+        let app = create_app_with_ui(
+            path_str,
+            &[],
+            Box::new(TestAppUi::with_unsafe_branch("unmerged")),
+        );
+        let result = app.remove_merged_branches();
+
+        // THEN it succeeds
+        assert_ok!(result);
+
+        // AND only the merged branch has been deleted
+        assert_branches_eq!(&repo, &["master", "unmerged"]);
+
+        // AND the current branch has been restored
+        assert_eq!(repo.current_branch().unwrap().unwrap(), "unmerged");
+    }
+
+    #[test]
+    fn delete_errors_fail_fast() {
+        // GIVEN a repository with a merged branch and an unmerged one
+        let (dir, repo) = create_repository_with_unmerged_current_branch();
+        let path_str = dir.path().to_str().unwrap();
+
+        // WHEN git-bonsai tries to delete both, unmerged first, with --fail-fast
+        let app = create_app_with_ui(
+            path_str,
+            &["--fail-fast"],
+            Box::new(TestAppUi::with_unsafe_branch("unmerged")),
+        );
+        let result = app.remove_merged_branches();
+
+        // THEN it fails
+        assert_eq!(result, Err(AppError::UnsafeDelete));
+
+        // AND no branch has been deleted
+        assert_branches_eq!(&repo, &["master", "merged", "unmerged"]);
+
+        // AND the current branch has been restored
+        assert_eq!(repo.current_branch().unwrap().unwrap(), "unmerged");
+    }
+
+    #[test]
+    fn deletable_branches_are_sorted() {
+        // GIVEN a repository with branches merged in master in non-alphabetical order
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        for branch in ["topic-c", "Topic-b", "topic-a"] {
+            create_branch(&repo, branch);
+            repo.checkout("master").unwrap();
+            merge_branch(&repo, branch);
+        }
+
+        // WHEN git-bonsai builds a report
+        let app = create_app(path_str, &[]);
+        let report = app.build_report().unwrap();
+
+        // THEN the deletable branches are sorted by name
+        let names: Vec<&str> = report.deletable.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, &["Topic-b", "topic-a", "topic-c"]);
+    }
+
+    #[test]
+    fn deletable_branches() {
+        // GIVEN a repository with two merged branches, an unmerged one and an excluded one
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        for branch in ["topic2", "topic1", "excluded"] {
+            create_branch(&repo, branch);
+            repo.checkout("master").unwrap();
+            merge_branch(&repo, branch);
+        }
+        create_branch(&repo, "unmerged");
+
+        // WHEN deletable branches are listed
+        let mut app = create_app(path_str, &["-x", "excluded"]);
+        app.add_default_branch_to_protected_branches().unwrap();
+        let branches = app.deletable_branches().unwrap();
+
+        // THEN only the merged, unprotected branches are listed, sorted by name
+        let names: Vec<&str> = branches.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, &["topic1", "topic2"]);
+
+        // AND nothing has been deleted
+        assert_branches_eq!(
+            &repo,
+            &["excluded", "master", "topic1", "topic2", "unmerged"]
+        );
+    }
+
+    #[test]
+    fn merged_into_protected_branches_matches_report() {
+        // GIVEN a repository with branches merged in master, an unmerged branch and a branch
+        // merged in a protected branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        for branch in ["topic1", "topic2"] {
+            create_branch(&repo, branch);
+            repo.checkout("master").unwrap();
+            merge_branch(&repo, branch);
+        }
+        create_branch(&repo, "release");
+        repo.create_branch("fix", Some("release")).unwrap();
+        create_and_commit_file(&repo, "release2");
+        repo.checkout("master").unwrap();
+        create_branch(&repo, "unmerged");
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai lists deletable branches both ways
+        let mut app = create_app(path_str, &["-x", "release"]);
+        app.add_default_branch_to_protected_branches().unwrap();
+        let report = app.build_report().unwrap();
+        let merged = app.list_branches_merged_into_protected_branches().unwrap();
+
+        // THEN they agree
+        let deletable: HashSet<String> = report.deletable.into_iter().map(|x| x.name).collect();
+        assert_eq!(merged, deletable);
+        assert_eq!(
+            merged,
+            HashSet::from(["fix", "topic1", "topic2"].map(String::from))
+        );
+    }
+
+    #[test]
+    fn archive_prefix() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic");
+
+        // WHEN git-bonsai runs with --archive-prefix archive/
+        {
+            let app = create_app(path_str, &["--archive-prefix", "archive/"]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the topic branch has been renamed
+        assert_branches_eq!(&repo, &["archive/topic", "master"]);
+
+        // AND the archive prefix is protected in git config
+        assert_eq!(
+            repo.get_config_keys("git-bonsai.protected-pattern")
+                .unwrap(),
+            &["archive/*"]
+        );
+
+        // WHEN git-bonsai runs again, without --archive-prefix
+        {
+            let app = create_app(path_str, &[]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the archived branch is still there
+        assert_branches_eq!(&repo, &["archive/topic", "master"]);
+    }
+
+    #[test]
+    fn detect_rebased() {
+        // GIVEN a repository with a topic branch rebase-merged in master, after master got a new
+        // commit
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic");
+        create_and_commit_file(&repo, "topic2");
+        repo.checkout("master").unwrap();
+        create_and_commit_file(&repo, "other");
+        repo.git("cherry-pick", &["master..topic"]).unwrap();
+
+        // WHEN git-bonsai runs without --detect-rebased
+        {
+            let app = create_app(path_str, &[]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the topic branch is still there
+        assert_branches_eq!(&repo, &["master", "topic"]);
+
+        // WHEN git-bonsai builds a report with --detect-rebased
+        let app = create_app(path_str, &["--detect-rebased"]);
+        let report = app.build_report().unwrap();
+
+        // THEN the topic branch is labeled as rebase-merged
+        assert_eq!(report.deletable[0].name, "topic");
+        assert_eq!(
+            report.deletable[0].rewritten_merge,
+            Some(RewrittenMerge::RebaseMerged)
+        );
+
+        // WHEN git-bonsai runs with --detect-rebased
+        assert_ok!(app.remove_merged_branches());
+
+        // THEN the topic branch has been removed
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn skip_protected_branch() {
+        // GIVEN a repository with a protected, merged branch: "protected"
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "protected");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "protected");
+
+        assert_branches_eq!(&repo, &["master", "protected"]);
+
+        // WHEN git-bonsai runs with "-x protected"
+        {
+            let app = create_app(path_str, &["-x", "protected"]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the protected branch is still there
+        assert_branches_eq!(&repo, &["master", "protected"]);
+
+        // WHEN git-bonsai runs without "-x protected"
+        {
+            let app = create_app(path_str, &[]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the protected branch is gone
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn update_branch() {
+        // GIVEN a source repository
+        let (source_dir, source_repo) = create_repository();
+
+        // AND a clone of it
+        let (clone_dir, _clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        let clone_dir_str = clone_dir.path().to_str().unwrap();
+
+        // AND a new commit in the source repository
+        create_and_commit_file(&source_repo, "new");
+
+        // WHEN git-bonsai runs in the clone
+        let result = run_git_bonsai(clone_dir_str, &["-y"]);
+        assert_eq!(result, 0);
+
+        // THEN the clone repository now contains the new commit
+        clone_dir.child("new").assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn update_branch_custom_remote() {
+        // GIVEN a source repository
+        let (source_dir, source_repo) = create_repository();
+
+        // AND a clone of it, whose remote is called "upstream"
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        let clone_dir_str = clone_dir.path().to_str().unwrap();
+        clone_repo
+            .git("remote", &["rename", "origin", "upstream"])
+            .unwrap();
+
+        // AND a new commit in the source repository
+        create_and_commit_file(&source_repo, "new");
+
+        // WHEN git-bonsai runs in the clone with --remote upstream
+        let result = run_git_bonsai(clone_dir_str, &["-y", "--remote", "upstream"]);
+        assert_eq!(result, 0);
+
+        // THEN the clone repository now contains the new commit
+        clone_dir.child("new").assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn update_tracking_branches_detached_head() {
+        // GIVEN a source repository
+        let (source_dir, source_repo) = create_repository();
+
+        // AND a clone of it, with a detached HEAD
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        let clone_dir_str = clone_dir.path().to_str().unwrap();
+        clone_repo.git("checkout", &["--detach"]).unwrap();
+        let sha1 = clone_repo.rev_parse("HEAD").unwrap();
+
+        // AND a new commit in the source repository
+        create_and_commit_file(&source_repo, "new");
+
+        // WHEN git-bonsai updates the tracking branches
+        {
+            let app = create_app(clone_dir_str, &[]);
+            assert_ok!(app.fetch_changes());
+            assert_ok!(app.update_tracking_branches());
+        }
+
+        // THEN master has been updated
+        assert_eq!(
+            clone_repo.rev_parse("master").unwrap(),
+            source_repo.rev_parse("master").unwrap()
+        );
+
+        // AND HEAD is still detached at the same commit
+        assert_eq!(clone_repo.current_branch(), Ok(None));
+        assert_eq!(clone_repo.rev_parse("HEAD").unwrap(), sha1);
+    }
+
+    #[test]
+    fn delete_remote() {
+        // GIVEN a source repository with two topic branches merged in master
+        let (source_dir, source_repo) = create_repository();
+        for branch in ["topic1", "topic2"] {
+            create_branch(&source_repo, branch);
+            source_repo.checkout("master").unwrap();
+            merge_branch(&source_repo, branch);
+        }
+
+        // AND a clone of it, with local branches for the topic branches
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        let clone_dir_str = clone_dir.path().to_str().unwrap();
+        for branch in ["topic1", "topic2"] {
+            clone_repo
+                .create_branch(branch, Some(&format!("origin/{}", branch)))
+                .unwrap();
+        }
+
+        // WHEN git-bonsai runs in the clone without --delete-remote
+        let result = run_git_bonsai(clone_dir_str, &["-y", "-x", "topic2"]);
+        assert_eq!(result, 0);
+
+        // THEN topic1 has only been deleted locally
+        assert_branches_eq!(&clone_repo, &["master", "topic2"]);
+        assert_branches_eq!(&source_repo, &["master", "topic1", "topic2"]);
+
+        // WHEN git-bonsai runs in the clone with --delete-remote
+        let result = run_git_bonsai(clone_dir_str, &["-y", "--delete-remote"]);
+        assert_eq!(result, 0);
+
+        // THEN topic2 has been deleted locally and from the source repository
+        assert_branches_eq!(&clone_repo, &["master"]);
+        assert_branches_eq!(&source_repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn allow_untracked() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+
+        // AND an untracked file
+        dir.child("untracked").touch().unwrap();
+
+        // WHEN git-bonsai runs without --allow-untracked
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch"]);
+
+        // THEN it refuses to run
+        assert_eq!(result, 1);
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+
+        // WHEN git-bonsai runs with --allow-untracked
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--allow-untracked"]);
+
+        // THEN the topic branch has been deleted
+        assert_eq!(result, 0);
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn allow_untracked_still_refuses_unstaged_changes() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+
+        // AND an unstaged change
+        dir.child("f").write_str("changed").unwrap();
+
+        // WHEN git-bonsai runs with --allow-untracked
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--allow-untracked"]);
+
+        // THEN it refuses to run
+        assert_eq!(result, 1);
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn on_delete() {
+        // GIVEN a repository with two merged topic branches
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+        repo.create_branch("topic2", None).unwrap();
+
+        // AND a file outside the repository for the hook to write to
+        let log_dir = assert_fs::TempDir::new().unwrap();
+        let log_file = log_dir.child("deleted");
+        let command = format!(
+            "echo \"$1 $GB_BRANCH\" >> {}",
+            log_file.path().to_str().unwrap()
+        );
+
+        // WHEN git-bonsai runs with --on-delete
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--on-delete", &command]);
+        assert_eq!(result, 0);
+
+        // THEN the hook has been called for each deleted branch
+        assert_branches_eq!(&repo, &["master"]);
+        let content = fs::read_to_string(log_file.path()).unwrap();
+        let mut lines: Vec<&str> = content.lines().collect();
+        lines.sort_unstable();
+        assert_eq!(lines, &["topic1 topic1", "topic2 topic2"]);
+    }
+
+    #[test]
+    fn fetch_args() {
+        // GIVEN a clone of a source repository
+        let (source_dir, _source_repo) = create_repository();
+        let (clone_dir, _clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+
+        // WHEN git-bonsai runs with two --fetch-arg options
+        let output = run_git_bonsai_process(
+            clone_dir.path().to_str().unwrap(),
+            &[
+                "-y",
+                "--verbose",
+                "--fetch-arg",
+                "--tags",
+                "--fetch-arg=--no-recurse-submodules",
+            ],
+        );
+        assert!(output.status.success());
+
+        // THEN the arguments have been passed to git fetch, after --prune
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("git fetch --prune --tags --no-recurse-submodules origin\n"),
+            "{}",
+            stderr
+        );
+    }
+
+    #[test]
+    fn fetch_without_args() {
+        // GIVEN a clone of a source repository
+        let (source_dir, _source_repo) = create_repository();
+        let (clone_dir, _clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+
+        // WHEN git-bonsai runs without --fetch-arg
+        let output =
+            run_git_bonsai_process(clone_dir.path().to_str().unwrap(), &["-y", "--verbose"]);
+        assert!(output.status.success());
+
+        // THEN git fetch only prunes
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("git fetch --prune origin\n"), "{}", stderr);
+    }
+
+    #[test]
+    fn log_file() {
+        // GIVEN a source repository with a branch merged into master
+        let (source_dir, source_repo) = create_repository();
+        create_branch(&source_repo, "topic1");
+        source_repo.checkout("master").unwrap();
+        merge_branch(&source_repo, "topic1");
+
+        // AND a clone of it, with a local copy of the merged branch
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        clone_repo
+            .create_branch("topic1", Some("origin/topic1"))
+            .unwrap();
+
+        // AND a new commit in the source repository
+        create_and_commit_file(&source_repo, "new");
+
+        // AND an existing log file
+        let log_dir = assert_fs::TempDir::new().unwrap();
+        let log_file = log_dir.child("bonsai.log");
+        log_file.write_str("previous run\n").unwrap();
+
+        // WHEN git-bonsai runs with --log-file
+        let result = run_git_bonsai(
+            clone_dir.path().to_str().unwrap(),
+            &["-y", "--log-file", log_file.path().to_str().unwrap()],
+        );
+        assert_eq!(result, 0);
+
+        // THEN the updated and deleted branches have been appended to the log file, with a
+        // timestamp
+        let content = fs::read_to_string(log_file.path()).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines[0], "previous run");
+        let records: Vec<&str> = lines[1..]
+            .iter()
+            .map(|x| {
+                let (timestamp, record) = x.split_once(' ').unwrap();
+                assert!(timestamp.ends_with('Z'), "{}", timestamp);
+                record
+            })
+            .collect();
+        assert_eq!(records, &["updated master", "deleted topic1"]);
+    }
+
+    #[test]
+    fn log_file_cannot_be_opened() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+
+        // WHEN git-bonsai runs with a --log-file in a directory which does not exist
+        let log_dir = assert_fs::TempDir::new().unwrap();
+        let log_path = log_dir.path().join("missing").join("bonsai.log");
+        let output = run_git_bonsai_process(
+            path_str,
+            &["-y", "--no-fetch", "--log-file", log_path.to_str().unwrap()],
+        );
+
+        // THEN it warns about it
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("Cannot open log file"), "{}", stdout);
+
+        // AND the branch has been deleted anyway
+        assert!(output.status.success());
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn on_delete_failure_does_not_stop_the_run() {
+        // GIVEN a repository with two merged topic branches
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+        repo.create_branch("topic2", None).unwrap();
+
+        // WHEN git-bonsai runs with a failing --on-delete command
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--on-delete", "exit 1"]);
+
+        // THEN all branches have been deleted
+        assert_eq!(result, 0);
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn config_file_protected_branches() {
+        // GIVEN a repository with a config file protecting topic1
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE_NAME),
+            "protected = [\"topic1\"]\nno_fetch = true\n",
+        )
+        .unwrap();
+        repo.git("add", &[CONFIG_FILE_NAME]).unwrap();
+        repo.git("commit", &["-m", "Add config file"]).unwrap();
+
+        // AND two merged topic branches
+        repo.create_branch("topic1", None).unwrap();
+        repo.create_branch("topic2", None).unwrap();
+
+        // WHEN git-bonsai runs
+        let result = run_git_bonsai(path_str, &["-y"]);
+        assert_eq!(result, 0);
+
+        // THEN topic1 has been kept
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn invalid_config_file() {
+        // GIVEN a repository with an invalid config file
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        fs::write(dir.path().join(CONFIG_FILE_NAME), "protected = 12\n").unwrap();
+        repo.git("add", &[CONFIG_FILE_NAME]).unwrap();
+        repo.git("commit", &["-m", "Add config file"]).unwrap();
+
+        // WHEN git-bonsai runs
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch"]);
+
+        // THEN it fails
+        assert_eq!(result, 1);
+    }
+
+    #[test]
+    fn protect_current() {
+        // GIVEN a repository with a merged topic branch, which is the current branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+        repo.checkout("topic1").unwrap();
+
+        // WHEN git-bonsai runs with --protect-current
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--protect-current"]);
+        assert_eq!(result, 0);
+
+        // THEN the current branch has been kept
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+
+        // WHEN git-bonsai runs without --protect-current
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch"]);
+        assert_eq!(result, 0);
+
+        // THEN the current branch has been deleted
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn exit_code_dirty_tree() {
+        // GIVEN a repository with an unstaged change
+        let (dir, _repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        dir.child("f").write_str("changed").unwrap();
+
+        // WHEN git-bonsai runs
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch"]);
+
+        // THEN it exits with the dirty tree exit code
+        assert_eq!(result, app::EXIT_DIRTY_TREE);
+    }
+
+    #[test]
+    fn exit_code_mapping() {
+        assert_eq!(
+            AppError::InterruptedByUser.exit_code(),
+            app::EXIT_INTERRUPTED
+        );
+        assert_eq!(AppError::UnsafeDelete.exit_code(), app::EXIT_UNSAFE_DELETE);
+        assert_eq!(
+            AppError::Git(GitError::FailedToRunGit).exit_code(),
+            app::EXIT_GIT_FAILED
+        );
+    }
+
+    #[test]
+    fn empty_repository() {
+        // GIVEN a repository without any commit
+        let dir = assert_fs::TempDir::new().unwrap();
+        let repo = Repository::new(dir.path());
+        repo.git("init", &[]).unwrap();
+
+        // WHEN git-bonsai runs
+        let output = run_git_bonsai_process(dir.path().to_str().unwrap(), &["-y"]);
+
+        // THEN it exits successfully, explaining there is nothing to do
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("Repository has no commits, nothing to do"));
+    }
+
+    #[test]
+    fn reset_default_branch() {
+        // GIVEN a repository whose stored default branch is wrong
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic", None).unwrap();
+        repo.set_config_key(DEFAULT_BRANCH_CONFIG_KEY, "topic")
+            .unwrap();
+
+        // WHEN git-bonsai runs with --reset-default-branch
+        let mut app = create_app(path_str, &["--reset-default-branch"]);
+        app.add_default_branch_to_protected_branches().unwrap();
+
+        // THEN the default branch has been determined again and stored
+        assert_eq!(
+            repo.get_config_keys(DEFAULT_BRANCH_CONFIG_KEY).unwrap(),
+            &["master"]
+        );
+        assert!(app.get_protected_branches().contains("master"));
+        assert!(!app.get_protected_branches().contains("topic"));
+    }
+
+    #[test]
+    fn fetch_all() {
+        // GIVEN two source repositories
+        let (source_dir, source_repo) = create_repository();
+        let (other_dir, other_repo) = create_repository();
+        create_branch(&other_repo, "feature");
+
+        // AND a clone of the first one, with the second one as an additional remote
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        let clone_dir_str = clone_dir.path().to_str().unwrap();
+        clone_repo
+            .git(
+                "remote",
+                &["add", "other", other_dir.path().to_str().unwrap()],
+            )
+            .unwrap();
+        clone_repo.git("fetch", &["other"]).unwrap();
+        clone_repo
+            .git("branch", &["--track", "feature", "other/feature"])
+            .unwrap();
+
+        // AND new commits in both source repositories
+        create_and_commit_file(&source_repo, "new");
+        create_and_commit_file(&other_repo, "other-new");
+
+        // WHEN git-bonsai runs in the clone with --fetch-all
+        let result = run_git_bonsai(clone_dir_str, &["-y", "--fetch-all"]);
+        assert_eq!(result, 0);
+
+        // THEN both remotes have been fetched
+        assert_eq!(
+            clone_repo.rev_parse("origin/master").unwrap(),
+            source_repo.rev_parse("master").unwrap()
+        );
+        assert_eq!(
+            clone_repo.rev_parse("other/feature").unwrap(),
+            other_repo.rev_parse("feature").unwrap()
+        );
+
+        // AND the branches tracking both remotes have been updated
+        assert_eq!(
+            clone_repo.rev_parse("master").unwrap(),
+            source_repo.rev_parse("master").unwrap()
+        );
+        assert_eq!(
+            clone_repo.rev_parse("feature").unwrap(),
+            other_repo.rev_parse("feature").unwrap()
+        );
+    }
+
+    #[test]
+    fn autostash() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+
+        // AND an unstaged change and an untracked file
+        dir.child("f").write_str("changed").unwrap();
+        dir.child("untracked").touch().unwrap();
+
+        // WHEN git-bonsai runs with --autostash
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--autostash"]);
+        assert_eq!(result, 0);
+
+        // THEN the topic branch has been deleted
+        assert_branches_eq!(&repo, &["master"]);
+
+        // AND the changes have been restored
+        dir.child("f").assert("changed");
+        dir.child("untracked").assert(predicate::path::exists());
+        assert_eq!(repo.git("stash", &["list"]).unwrap(), "");
+    }
+
+    #[test]
+    fn autostash_dry_run() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+
+        // AND an unstaged change
+        dir.child("f").write_str("changed").unwrap();
+
+        // WHEN git-bonsai runs with --autostash and --dry-run
+        let output =
+            run_git_bonsai_process(path_str, &["-y", "--no-fetch", "--autostash", "--dry-run"]);
+        assert!(output.status.success());
+
+        // THEN the changes have not been stashed
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(!stdout.contains("Stashing changes"), "{}", stdout);
+        assert_eq!(repo.git("stash", &["list"]).unwrap(), "");
+        dir.child("f").assert("changed");
+
+        // AND the topic branch is still there
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn update_diverged_branch() {
+        // GIVEN a source repository with a topic branch
+        let (source_dir, source_repo) = create_repository();
+        source_repo.create_branch("topic", None).unwrap();
+
+        // AND a clone of it, with a local branch tracking topic
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        clone_repo.git("config", &["user.name", "test"]).unwrap();
+        clone_repo
+            .git("config", &["user.email", "test@example.com"])
+            .unwrap();
+        clone_repo
+            .git("branch", &["--track", "topic", "origin/topic"])
+            .unwrap();
+
+        // AND new commits in master and topic in the source repository
+        create_and_commit_file(&source_repo, "new");
+        source_repo.checkout("topic").unwrap();
+        create_and_commit_file(&source_repo, "remote-topic");
+
+        // AND a local commit in topic, so that it diverges from its upstream
+        clone_repo.checkout("topic").unwrap();
+        create_and_commit_file(&clone_repo, "local-topic");
+        clone_repo.checkout("master").unwrap();
+        let topic_sha1 = clone_repo.rev_parse("topic").unwrap();
+
+        // WHEN git-bonsai runs in batch mode
+        let output = run_git_bonsai_process(clone_dir.path().to_str().unwrap(), &["-y"]);
+        assert!(output.status.success());
+
+        // THEN master has been fast-forwarded
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.contains("master is 1 behind, fast-forwarding"),
+            "{}",
+            stdout
+        );
+        clone_dir.child("new").assert(predicate::path::exists());
+
+        // AND topic has been skipped
+        assert!(
+            stdout.contains("topic has diverged, skipping"),
+            "{}",
+            stdout
+        );
+        assert_eq!(clone_repo.rev_parse("topic").unwrap(), topic_sha1);
+    }
+
+    #[test]
+    fn update_diverged_branch_rebase() {
+        // GIVEN a source repository
+        let (source_dir, source_repo) = create_repository();
+
+        // AND a clone of it
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        clone_repo.git("config", &["user.name", "test"]).unwrap();
+        clone_repo
+            .git("config", &["user.email", "test@example.com"])
+            .unwrap();
+
+        // AND a new commit in the source repository and another in the clone, so that master
+        // diverges from its upstream
+        create_and_commit_file(&source_repo, "remote");
+        create_and_commit_file(&clone_repo, "local");
+
+        // WHEN git-bonsai runs with --update-strategy rebase
+        let output = run_git_bonsai_process(
+            clone_dir.path().to_str().unwrap(),
+            &["-y", "--update-strategy", "rebase"],
+        );
+        assert!(output.status.success());
+
+        // THEN master has been rebased on top of its upstream
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.contains("master has diverged, rebasing"),
+            "{}",
+            stdout
+        );
+        assert_eq!(clone_repo.ahead_behind("master"), Ok((1, 0)));
+        clone_dir.child("remote").assert(predicate::path::exists());
+        clone_dir.child("local").assert(predicate::path::exists());
+    }
+
+    #[test]
+    fn update_progress() {
+        // GIVEN a source repository with two branches
+        let (source_dir, source_repo) = create_repository();
+        source_repo.create_branch("topic1", None).unwrap();
+
+        // AND a clone of it, with local branches tracking them
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        clone_repo
+            .git("branch", &["--track", "topic1", "origin/topic1"])
+            .unwrap();
+
+        // WHEN git-bonsai runs in batch mode
+        let output = run_git_bonsai_process(clone_dir.path().to_str().unwrap(), &["-y"]);
+        assert!(output.status.success());
+
+        // THEN the update of each branch is reported with a counter
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("[1/2] Updating"), "{}", stdout);
+        assert!(stdout.contains("[2/2] Updating"), "{}", stdout);
+    }
+
+    #[test]
+    fn restore_interrupted_run_checks_out_original_branch() {
+        // GIVEN a repository whose original branch has been recorded
+        let (dir, repo) = create_repository();
+        let restore_point = Mutex::new(Some(RunRestorePoint {
+            path: dir.path().to_path_buf(),
+            rev: "master".to_string(),
+            stashed: false,
+        }));
+
+        // AND in which another branch has been checked out
+        create_branch(&repo, "topic");
+
+        // WHEN git-bonsai is interrupted
+        restore_interrupted_run(&restore_point).unwrap();
+
+        // THEN the original branch has been checked out
+        assert_eq!(repo.current_branch().unwrap().unwrap(), "master");
+
+        // AND the restore point has been cleared, so that the branch is not restored twice
+        assert!(restore_point.lock().unwrap().is_none());
+        restore_interrupted_run(&restore_point).unwrap();
+    }
+
+    #[test]
+    fn interrupted_autostash() {
+        // GIVEN a repository whose remote never answers
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.git("config", &["protocol.ext.allow", "always"])
+            .unwrap();
+        repo.git("remote", &["add", "origin", "ext::sh -c sleep% 30"])
+            .unwrap();
+
+        // AND an unstaged change and an untracked file
+        dir.child("f").write_str("changed").unwrap();
+        dir.child("untracked").touch().unwrap();
+
+        // WHEN git-bonsai runs with --autostash
+        let mut child = Command::new(env!("CARGO_BIN_EXE_git-bonsai"))
+            .current_dir(path_str)
+            .env_remove("GB_DEBUG")
+            .args(["-y", "--autostash", "--verbose"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // AND it gets interrupted while fetching
+        let stderr = BufReader::new(child.stderr.take().unwrap());
+        for line in stderr.lines() {
+            if line.unwrap().contains(": git fetch ") {
+                break;
+            }
+        }
+        let status = Command::new("kill")
+            .args(["-INT", &child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // THEN it exits with the SIGINT exit code
+        assert_eq!(child.wait().unwrap().code(), Some(app::EXIT_SIGINT));
+
+        // AND the changes have been restored
+        dir.child("f").assert("changed");
+        dir.child("untracked").assert(predicate::path::exists());
+        assert_eq!(repo.git("stash", &["list"]).unwrap(), "");
+    }
+
+    #[test]
+    fn delete_from_default_branch_does_not_checkout() {
+        // GIVEN a repository with a merged topic branch, whose current branch is the default one
+        let (dir, repo) = create_repository();
+        create_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic1");
+
+        // WHEN git-bonsai runs, printing the git commands
+        let output = run_git_bonsai_process(
+            dir.path().to_str().unwrap(),
+            &["-y", "--no-fetch", "--verbose"],
+        );
+        assert!(output.status.success());
+
+        // THEN the topic branch has been deleted
+        assert_branches_eq!(&repo, &["master"]);
+
+        // AND no branch has been checked out
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(!stderr.contains(": git checkout "), "{}", stderr);
+    }
+
+    #[test]
+    fn identical_protected_branches_are_explained() {
+        // GIVEN a repository with a release branch identical to master
+        let (dir, repo) = create_repository();
+        repo.create_branch("release", None).unwrap();
+
+        // WHEN git-bonsai runs with release protected
+        let output = run_git_bonsai_process(
+            dir.path().to_str().unwrap(),
+            &["-y", "--no-fetch", "-x", "release"],
+        );
+        assert!(output.status.success());
+
+        // THEN it explains why the identical branches are kept
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.contains(
+                "Keeping master, release: these protected branches point to the same commit"
+            ),
+            "{}",
+            stdout
+        );
+        assert_branches_eq!(&repo, &["master", "release"]);
+    }
+
+    #[test]
+    fn current_only() {
+        // GIVEN a repository with a feature branch, and a sub-branch merged into it
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "feature");
+        create_branch(&repo, "feature-part1");
+        repo.checkout("feature").unwrap();
+        merge_branch(&repo, "feature-part1");
+
+        // AND a topic branch merged into master
+        repo.checkout("master").unwrap();
+        create_branch(&repo, "topic");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic");
+
+        // WHEN git-bonsai runs with --current-only from the feature branch
+        repo.checkout("feature").unwrap();
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--current-only"]);
+        assert_eq!(result, 0);
+
+        // THEN the sub-branch has been deleted, but not the feature branch nor the topic branch
+        assert_branches_eq!(&repo, &["feature", "master", "topic"]);
+        assert_eq!(repo.current_branch(), Ok(Some("feature".to_string())));
+    }
+
+    #[test]
+    fn detached_head() {
+        // GIVEN a repository with a merged branch and a detached HEAD
+        let (dir, repo) = create_repository();
+        repo.create_branch("topic", None).unwrap();
+        repo.git("checkout", &["--detach"]).unwrap();
+
+        // WHEN git-bonsai runs
+        let output = run_git_bonsai_process(dir.path().to_str().unwrap(), &["-y", "--no-fetch"]);
+
+        // THEN it fails, explaining HEAD is detached
+        assert_eq!(output.status.code(), Some(app::EXIT_DIRTY_TREE));
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.contains("Error: HEAD is detached, check out a branch first"),
+            "{}",
+            stdout
+        );
+
+        // AND no branch has been deleted
+        assert_branches_eq!(&repo, &["master", "topic"]);
+    }
+
+    #[test]
+    fn current_only_detached_head() {
+        // GIVEN a repository with a detached HEAD
+        let (dir, repo) = create_repository();
+        repo.git("checkout", &["--detach"]).unwrap();
+
+        // WHEN an app is created with --current-only
+        let mut app = create_app(
+            dir.path().to_str().unwrap(),
+            &["--no-fetch", "--current-only"],
+        );
+
+        // THEN it fails to run
+        assert_eq!(app.run().unwrap_err().error, AppError::NoCurrentBranch);
+    }
+
+    #[test]
+    fn keep_merged_into() {
+        // GIVEN a repository with a staging branch, and a topic1 branch merged into staging and
+        // master
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+        create_branch(&repo, "staging");
+        merge_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic1");
+
+        // AND a topic2 branch only merged into master
+        create_branch(&repo, "topic2");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic2");
+
+        // WHEN git-bonsai runs with --keep-merged-into staging
+        let result = run_git_bonsai(
+            path_str,
+            &["-y", "--no-fetch", "--keep-merged-into", "staging"],
+        );
+        assert_eq!(result, 0);
+
+        // THEN only the branch which is not merged into staging has been deleted
+        assert_branches_eq!(&repo, &["master", "staging", "topic1"]);
+    }
+
+    #[test]
+    fn protect_tagged() {
+        // GIVEN a repository with two merged branches, the tip of topic1 being tagged
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic1");
+        repo.git("tag", &["v1.0"]).unwrap();
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic1");
+        create_branch(&repo, "topic2");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic2");
+
+        // WHEN git-bonsai runs with --protect-tagged
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--protect-tagged"]);
+        assert_eq!(result, 0);
+
+        // THEN only the branch whose tip is not tagged has been deleted
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn keep_tracked() {
+        // GIVEN a source repository with two branches merged into master
+        let (source_dir, source_repo) = create_repository();
+        for branch in &["topic1", "topic2"] {
+            create_branch(&source_repo, branch);
+            source_repo.checkout("master").unwrap();
+            merge_branch(&source_repo, branch);
+        }
+
+        // AND a clone of it, where topic1 tracks its remote branch and topic2 is local-only
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        clone_repo
+            .create_branch("topic1", Some("origin/topic1"))
+            .unwrap();
+        clone_repo
+            .git("branch", &["--no-track", "topic2", "origin/topic2"])
+            .unwrap();
+
+        // WHEN git-bonsai runs with --keep-tracked
+        let result = run_git_bonsai(
+            clone_dir.path().to_str().unwrap(),
+            &["-y", "--no-fetch", "--keep-tracked"],
+        );
+        assert_eq!(result, 0);
+
+        // THEN only the local-only branch has been deleted
+        assert_branches_eq!(&clone_repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn prune_empty() {
+        // GIVEN a repository with a branch pointing to the tip of master, and an unmerged branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("empty", None).unwrap();
+        create_branch(&repo, "topic");
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai runs with --prune-empty
+        let output = run_git_bonsai_process(path_str, &["-y", "--no-fetch", "--prune-empty"]);
+        assert!(output.status.success());
+
+        // THEN the empty branch has been offered for deletion and deleted
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.contains("Info: empty has no commits beyond master"),
+            "{}",
+            stdout
+        );
+        assert_branches_eq!(&repo, &["master", "topic"]);
+    }
+
+    #[test]
+    fn prune_empty_declined() {
+        // GIVEN a repository with a branch pointing to the tip of master
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("empty", None).unwrap();
+
+        // WHEN git-bonsai runs with --prune-empty, and the user declines the deletion
+        let mut app = create_app_with_ui(
+            path_str,
+            &["--no-fetch", "--prune-empty"],
+            Box::new(DecliningAppUi),
+        );
+        assert_ok!(app.run());
+
+        // THEN the empty branch has not been deleted
+        assert_branches_eq!(&repo, &["empty", "master"]);
+    }
+
+    #[test]
+    fn single_prompt() {
+        // GIVEN a repository with a merged topic branch, and two identical branches not
+        // contained in any other branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic1");
+        create_branch(&repo, "dup1");
+        repo.create_branch("dup2", None).unwrap();
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai runs with --single-prompt
+        let ui = TestAppUi::new();
+        let prompts = ui.prompts.clone();
+        let mut app =
+            create_app_with_ui(path_str, &["--no-fetch", "--single-prompt"], Box::new(ui));
+        assert_ok!(app.run());
+
+        // THEN a single prompt offered both the merged and the identical branches
+        assert_eq!(
+            *prompts.borrow(),
+            vec![vec![
+                "dup1".to_string(),
+                "dup2".to_string(),
+                "topic1".to_string()
+            ]]
+        );
+
+        // AND one of the identical branches has been kept
+        assert_branches_eq!(&repo, &["dup1", "master"]);
+    }
+
+    #[test]
+    fn park_branch() {
+        // GIVEN a repository with a merged topic branch, a parking branch, and an unmerged current
+        // branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic1");
+        repo.create_branch("parking", None).unwrap();
+        create_branch(&repo, "unmerged");
+
+        // WHEN git-bonsai runs with --park-branch
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--park-branch", "parking"]);
+        assert_eq!(result, 0);
+
+        // THEN the topic branch has been deleted, but not the parking branch
+        assert_branches_eq!(&repo, &["master", "parking", "unmerged"]);
+
+        // AND the current branch has been restored
+        assert_eq!(repo.current_branch().unwrap().unwrap(), "unmerged");
+    }
+
+    #[test]
+    fn current_branch_not_switched() {
+        // GIVEN a repository with a merged topic branch, and an unmerged current branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic1");
+        create_branch(&repo, "unmerged");
+        let reflog = repo.git("reflog", &["HEAD"]).unwrap();
+
+        // WHEN git-bonsai runs with --keep-if-open-worktree
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--keep-if-open-worktree"]);
+        assert_eq!(result, 0);
+
+        // THEN the topic branch has been deleted
+        assert_branches_eq!(&repo, &["master", "unmerged"]);
+
+        // AND HEAD has not moved, since the current branch could not be deleted
+        assert_eq!(repo.git("reflog", &["HEAD"]).unwrap(), reflog);
+    }
+
+    #[test]
+    fn park_branch_does_not_exist() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+
+        // WHEN git-bonsai runs with a --park-branch which does not exist
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--park-branch", "nope"]);
+
+        // THEN it fails, and nothing has been deleted
+        assert_eq!(result, 1);
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn before_ref() {
+        // GIVEN a repository with a branch merged before the v1 tag
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "shipped");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "shipped");
+        repo.git("tag", &["v1"]).unwrap();
+
+        // AND a branch merged after it
+        create_branch(&repo, "unreleased");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "unreleased");
+
+        // WHEN git-bonsai runs with --before-ref v1
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch", "--before-ref", "v1"]);
+        assert_eq!(result, 0);
+
+        // THEN only the branch merged before the tag has been deleted
+        assert_branches_eq!(&repo, &["master", "unreleased"]);
+    }
+
+    #[test]
+    fn declined_bulk_delete() {
+        // GIVEN a repository with a merged branch
+        let (dir, repo) = create_repository_with_unmerged_current_branch();
+        let path_str = dir.path().to_str().unwrap();
+
+        // WHEN git-bonsai runs with a UI which selects a branch to delete, but declines the
+        // final confirmation
+        let ui = TestAppUi {
+            decline_bulk_delete: true,
+            ..TestAppUi::new()
+        };
+        let mut app = create_app_with_ui(path_str, &["--no-fetch"], Box::new(ui));
+        assert_ok!(app.run());
+
+        // THEN no branch has been deleted
+        assert_branches_eq!(&repo, &["master", "merged", "unmerged"]);
+
+        // WHEN it runs again with --no-final-confirm
+        let ui = TestAppUi {
+            decline_bulk_delete: true,
+            ..TestAppUi::new()
+        };
+        let mut app = create_app_with_ui(
+            path_str,
+            &["--no-fetch", "--no-final-confirm"],
+            Box::new(ui),
+        );
+        assert_ok!(app.run());
+
+        // THEN the merged branch has been deleted
+        assert_branches_eq!(&repo, &["master", "unmerged"]);
+    }
+
+    #[test]
+    fn bulk_delete_confirmed_once() {
+        // GIVEN a repository with a merged topic branch, and two branches identical to master
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic1");
+        repo.create_branch("dup1", None).unwrap();
+        repo.create_branch("dup2", None).unwrap();
+
+        // WHEN git-bonsai runs
+        let ui = TestAppUi::new();
+        let bulk_confirmations = ui.bulk_confirmations.clone();
+        let mut app = create_app_with_ui(path_str, &["--no-fetch"], Box::new(ui));
+        assert_ok!(app.run());
+
+        // THEN a single final confirmation listed the identical and the merged branches
+        assert_eq!(
+            *bulk_confirmations.borrow(),
+            vec![vec![
+                "dup1".to_string(),
+                "dup2".to_string(),
+                "topic1".to_string()
+            ]]
+        );
+
+        // AND all of them have been deleted
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn batch_ui_confirms_bulk_delete() {
+        let ui = BatchAppUi::new(Verbosity::Normal);
+        assert!(ui.confirm_bulk_delete(&["topic1".to_string(), "topic2".to_string()]));
+    }
+
+    #[test]
+    fn no_color() {
+        // GIVEN a repository
+        let (dir, _repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+
+        // WHEN git-bonsai runs with colors forced
+        let output = Command::new(env!("CARGO_BIN_EXE_git-bonsai"))
+            .current_dir(path_str)
+            .env("CLICOLOR_FORCE", "1")
+            .env_remove("NO_COLOR")
+            .args(["-y", "--no-fetch"])
+            .output()
+            .unwrap();
+
+        // THEN the output contains ANSI codes
+        assert!(String::from_utf8(output.stdout).unwrap().contains('\x1b'));
+
+        // WHEN it runs with --no-color, or with NO_COLOR set
+        let no_color_flag_output = Command::new(env!("CARGO_BIN_EXE_git-bonsai"))
+            .current_dir(path_str)
+            .env("CLICOLOR_FORCE", "1")
+            .env_remove("NO_COLOR")
+            .args(["-y", "--no-fetch", "--no-color"])
+            .output()
+            .unwrap();
+        let no_color_env_output = Command::new(env!("CARGO_BIN_EXE_git-bonsai"))
+            .current_dir(path_str)
+            .env("CLICOLOR_FORCE", "1")
+            .env("NO_COLOR", "1")
+            .args(["-y", "--no-fetch"])
+            .output()
+            .unwrap();
+
+        // THEN the output does not contain any ANSI code
+        for output in [no_color_flag_output, no_color_env_output] {
+            assert!(output.status.success());
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            assert!(!stdout.is_empty());
+            assert!(!stdout.contains('\x1b'), "{}", stdout);
+        }
+    }
+
+    #[test]
+    fn no_update() {
+        // GIVEN a source repository
+        let (source_dir, source_repo) = create_repository();
+
+        // AND a clone of it
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        let clone_dir_str = clone_dir.path().to_str().unwrap();
+        let old_sha1 = clone_repo.rev_parse("master").unwrap();
+
+        // AND a new commit in the source repository
+        create_and_commit_file(&source_repo, "new");
+
+        // WHEN git-bonsai runs in the clone with --no-update
+        let result = run_git_bonsai(clone_dir_str, &["-y", "--no-update"]);
+        assert_eq!(result, 0);
+
+        // THEN the changes have been fetched
+        assert_eq!(
+            clone_repo.rev_parse("origin/master").unwrap(),
+            source_repo.rev_parse("master").unwrap()
+        );
+
+        // AND the master branch has not been fast-forwarded
+        assert_eq!(clone_repo.rev_parse("master").unwrap(), old_sha1);
+        clone_dir.child("new").assert(predicate::path::missing());
+    }
+
+    #[test]
+    fn dry_run() {
+        // GIVEN a source repository
+        let (source_dir, source_repo) = create_repository();
+
+        // AND a clone of it, with a merged topic branch
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        let clone_dir_str = clone_dir.path().to_str().unwrap();
+        clone_repo.create_branch("topic1", None).unwrap();
+        let old_sha1 = clone_repo.git("rev-parse", &["origin/master"]).unwrap();
+
+        // AND a new commit in the source repository
+        create_and_commit_file(&source_repo, "new");
+
+        // WHEN git-bonsai runs in the clone with --dry-run
+        let result = run_git_bonsai(clone_dir_str, &["-y", "--dry-run"]);
+        assert_eq!(result, 0);
+
+        // THEN the remote refs of the clone have not changed
+        let sha1 = clone_repo.git("rev-parse", &["origin/master"]).unwrap();
+        assert_eq!(sha1, old_sha1);
+
+        // AND the clone does not contain the new commit
+        clone_dir.child("new").assert(predicate::path::missing());
+
+        // AND the topic branch has not been deleted
+        assert_branches_eq!(&clone_repo, &["master", "topic1"]);
+
+        // AND the default branch has not been stored in the config
+        assert_eq!(
+            clone_repo.get_config_value(DEFAULT_BRANCH_CONFIG_KEY),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn dry_run_reports_plan() {
+        // GIVEN a repository with a merged branch and an unmerged one
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "merged");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "merged");
+        create_branch(&repo, "unmerged");
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai runs with --dry-run
+        let output = run_git_bonsai_process(path_str, &["-y", "--no-fetch", "--dry-run"]);
+        assert!(output.status.success());
+
+        // THEN it prints the branches before the run, and the ones which would remain after it
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.ends_with("Before: [master, merged, unmerged]\nAfter: [master, unmerged]\n"),
+            "{}",
+            stdout
+        );
+
+        // AND no branch has been deleted
+        assert_branches_eq!(&repo, &["master", "merged", "unmerged"]);
+    }
+
+    #[test]
+    fn maintain() {
+        // GIVEN a source repository
+        let (source_dir, source_repo) = create_repository();
+
+        // AND a clone of it, with a merged topic branch
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        let clone_dir_str = clone_dir.path().to_str().unwrap();
+        clone_repo.create_branch("topic1", None).unwrap();
+
+        // AND a new commit in the source repository
+        create_and_commit_file(&source_repo, "new");
+
+        // WHEN git-bonsai runs in the clone with --maintain
+        let result = run_git_bonsai(clone_dir_str, &["-y", "--maintain"]);
+        assert_eq!(result, 0);
+
+        // THEN the clone repository now contains the new commit
+        clone_dir.child("new").assert(predicate::path::exists());
+
+        // AND the topic branch has not been deleted
+        assert_branches_eq!(&clone_repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn identical_sha1_no_other_branch() {
+        // GIVEN a repository with three branches pointing to the same sha1, contained in no other
+        // branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic1");
+        repo.create_branch("topic2", Some("topic1")).unwrap();
+        repo.create_branch("topic3", Some("topic1")).unwrap();
+
+        // WHEN git-bonsai runs
+        let app = create_app(path_str, &[]);
+        assert_ok!(app.delete_identical_branches());
+
+        // THEN only the first topic branch remains
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn identical_sha1_auto_keep_one() {
+        // GIVEN a repository with three branches pointing to the same sha1, contained in no other
+        // branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic1");
+        repo.create_branch("topic2", Some("topic1")).unwrap();
+        repo.create_branch("topic3", Some("topic1")).unwrap();
+
+        // WHEN git-bonsai runs with --auto-keep-one
+        let ui = TestAppUi::new();
+        let prompts = ui.prompts.clone();
+        let bulk_confirmations = ui.bulk_confirmations.clone();
+        let app = create_app_with_ui(path_str, &["--auto-keep-one"], Box::new(ui));
+        assert_ok!(app.delete_identical_branches());
+
+        // THEN the UI has not been asked to select branches
+        assert!(prompts.borrow().is_empty());
+
+        // AND the deletion of the other branches has been confirmed
+        assert_eq!(
+            *bulk_confirmations.borrow(),
+            vec![vec!["topic2".to_string(), "topic3".to_string()]]
+        );
+
+        // AND only the first topic branch remains
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+    }
+
+    #[test]
+    fn identical_sha1_keep_current_branch() {
+        // GIVEN a repository with two branches pointing to the same sha1, contained in no other
+        // branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "main-ish");
+        repo.create_branch("zzz", Some("main-ish")).unwrap();
+
+        // AND the last one in alphabetical order is the current branch
+        repo.checkout("zzz").unwrap();
+
+        // WHEN git-bonsai runs
+        let app = create_app(path_str, &[]);
+        assert_ok!(app.delete_identical_branches());
+
+        // THEN the current branch has been kept
+        assert_branches_eq!(&repo, &["master", "zzz"]);
+        assert_eq!(repo.current_branch().unwrap().unwrap(), "zzz");
+    }
+
+    #[test]
+    fn identical_sha1_keep_default_branch() {
+        // GIVEN a repository whose default branch is main-ish, and another branch pointing to the
+        // same sha1, contained in no other branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "main-ish");
+        repo.create_branch("aaa", Some("main-ish")).unwrap();
+        repo.set_config_key(DEFAULT_BRANCH_CONFIG_KEY, "main-ish")
+            .unwrap();
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai runs without protecting the default branch
+        let app = create_app(
+            path_str,
+            &["--exclude-default-branch-check", "--merged-into", "master"],
+        );
+        assert_ok!(app.delete_identical_branches());
+
+        // THEN the default branch has been kept
+        assert_branches_eq!(&repo, &["main-ish", "master"]);
+    }
+
+    #[test]
+    fn identical_sha1_contained_in_master() {
+        // GIVEN a repository with two branches pointing to the same sha1, contained in the master
+        // branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic1", None).unwrap();
+        repo.create_branch("topic2", None).unwrap();
+
+        // WHEN git-bonsai runs
+        let app = create_app(path_str, &[]);
+        assert_ok!(app.delete_identical_branches());
+
+        // THEN only the master branch remains
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn skip_worktree_branches() {
+        // GIVEN a source repository with two branches
+        let (source_dir, source_repo) = create_repository();
+        create_branch(&source_repo, "topic1");
+        source_repo.checkout("master").unwrap();
+
+        // AND a clone of this repository
         let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
         let clone_path_str = clone_dir.path().to_str().unwrap();
 
-        // with the topic1 branch checked-out in a separate worktree
-        let worktree_dir = assert_fs::TempDir::new().unwrap();
-        let worktree_path_str = worktree_dir.path().to_str().unwrap();
-        clone_repo
-            .git("worktree", &["add", worktree_path_str, "topic1"])
+        // with the topic1 branch checked-out in a separate worktree
+        let worktree_dir = assert_fs::TempDir::new().unwrap();
+        let worktree_path_str = worktree_dir.path().to_str().unwrap();
+        clone_repo
+            .git("worktree", &["add", worktree_path_str, "topic1"])
+            .unwrap();
+
+        let worktree_repo = Repository::new(worktree_dir.path());
+        worktree_repo.checkout("topic1").unwrap();
+
+        // WHEN git-bonsai updates the branches of the clone
+        // THEN it does not fail
+        let app = create_app(clone_path_str, &[]);
+        assert_ok!(app.update_tracking_branches());
+    }
+
+    #[test]
+    fn deletable_branches_in_bare_repository() {
+        // GIVEN a bare clone of a repository with a merged topic branch
+        let (source_dir, source_repo) = create_repository();
+        create_branch(&source_repo, "topic");
+        source_repo.checkout("master").unwrap();
+        merge_branch(&source_repo, "topic");
+        let bare_dir = assert_fs::TempDir::new().unwrap();
+        let status = Command::new("git")
+            .args(["clone", "--bare", "--quiet"])
+            .arg(source_dir.path())
+            .arg(bare_dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // WHEN git-bonsai lists the deletable branches of the bare clone
+        let app = create_app(
+            bare_dir.path().to_str().unwrap(),
+            &["--assume-default", "master"],
+        );
+        let branches = app.deletable_branches().unwrap();
+
+        // THEN it lists the topic branch
+        let names: Vec<&str> = branches.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, &["topic"]);
+    }
+
+    #[test]
+    fn keep_if_open_worktree() {
+        // GIVEN a repository with two merged topic branches, topic1 and topic2
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic1");
+        create_branch(&repo, "topic2");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic2");
+
+        // AND topic1 is checked out in the main worktree
+        repo.checkout("topic1").unwrap();
+
+        // AND topic2 is checked out in a linked worktree
+        let worktree_dir = assert_fs::TempDir::new().unwrap();
+        let worktree_path_str = worktree_dir.path().to_str().unwrap();
+        repo.git("worktree", &["add", worktree_path_str, "topic2"])
+            .unwrap();
+
+        // WHEN git-bonsai runs with --keep-if-open-worktree
+        {
+            let app = create_app(path_str, &["--keep-if-open-worktree"]);
+            assert_ok!(app.delete_identical_branches());
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN both topic branches are still there
+        assert_ok!(repo.git("rev-parse", &["--verify", "refs/heads/topic1"]));
+        assert_ok!(repo.git("rev-parse", &["--verify", "refs/heads/topic2"]));
+
+        // WHEN git-bonsai runs without --keep-if-open-worktree
+        {
+            let app = create_app(path_str, &[]);
+            assert_ok!(app.remove_merged_branches());
+        }
+
+        // THEN the branch of the main worktree is gone, but not the one of the linked worktree
+        assert_branches_eq!(&repo, &["master", "topic2"]);
+    }
+
+    #[test]
+    fn do_not_delete_branches_checked_out_in_worktrees() {
+        // GIVEN a repository with two merged topic branches
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        for branch in ["topic1", "topic2"] {
+            create_branch(&repo, branch);
+            repo.checkout("master").unwrap();
+            merge_branch(&repo, branch);
+        }
+
+        // AND topic1 is checked out in a linked worktree
+        let worktree_dir = assert_fs::TempDir::new().unwrap();
+        let worktree_path_str = worktree_dir.path().to_str().unwrap();
+        repo.git("worktree", &["add", worktree_path_str, "topic1"])
+            .unwrap();
+
+        // WHEN git-bonsai runs
+        let ui = TestAppUi::new();
+        let prompts = ui.prompts.clone();
+        let mut app = create_app_with_ui(path_str, &[], Box::new(ui));
+        app.add_default_branch_to_protected_branches().unwrap();
+        assert_ok!(app.remove_merged_branches());
+
+        // THEN the branch checked out in the worktree has not been offered
+        assert_eq!(*prompts.borrow(), vec![vec!["topic2".to_string()]]);
+
+        // AND it has been kept
+        assert_branches_eq!(&repo, &["master", "topic1"]);
+
+        // AND it is reported as skipped
+        assert_eq!(
+            app.build_report().unwrap().skipped,
+            vec![SkippedBranch {
+                name: "topic1".to_string(),
+                reason: "checked out in another worktree".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn assume_default() {
+        // GIVEN a clone of a repository, with a develop branch
+        let (source_dir, _source_repo) = create_repository();
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        clone_repo.create_branch("develop", None).unwrap();
+
+        // WHEN git-bonsai runs with --assume-default develop
+        let output = run_git_bonsai_process(
+            clone_dir.path().to_str().unwrap(),
+            &["--report-only", "--verbose", "--assume-default", "develop"],
+        );
+        assert!(output.status.success());
+
+        // THEN develop is the protected default branch
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.starts_with("Default branch: develop\n"),
+            "{}",
+            stdout
+        );
+        assert!(
+            stdout.contains("- develop (default branch)\n"),
+            "{}",
+            stdout
+        );
+
+        // AND the default branch has not been looked for
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(!stderr.contains("refs/remotes/origin/HEAD"), "{}", stderr);
+
+        // AND it has not been stored
+        assert_eq!(
+            clone_repo.get_config_value(DEFAULT_BRANCH_CONFIG_KEY),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn assume_default_missing_branch() {
+        // GIVEN a repository
+        let (dir, repo) = create_repository();
+        repo.create_branch("topic", None).unwrap();
+
+        // WHEN git-bonsai runs with --assume-default and a branch which does not exist
+        let mut app = create_app(
+            dir.path().to_str().unwrap(),
+            &["-y", "--no-fetch", "--assume-default", "develop"],
+        );
+
+        // THEN it fails
+        let error = app.run().unwrap_err();
+        assert_eq!(error.error, AppError::NoSuchBranch("develop".to_string()));
+
+        // AND the returned report lists the error
+        assert_eq!(error.report.errors, &[error.error.to_string()]);
+
+        // AND no branch has been deleted
+        assert_branches_eq!(&repo, &["master", "topic"]);
+    }
+
+    #[test]
+    fn stored_default_branch_deleted() {
+        // GIVEN a repository whose stored default branch does not exist anymore
+        let (dir, repo) = create_repository();
+        repo.set_config_key(DEFAULT_BRANCH_CONFIG_KEY, "trunk")
+            .unwrap();
+
+        // AND a topic branch merged into master
+        create_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic1");
+
+        // WHEN git-bonsai runs
+        let output = run_git_bonsai_process(dir.path().to_str().unwrap(), &["-y", "--no-fetch"]);
+        assert!(output.status.success());
+
+        // THEN it warns about the missing default branch
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.contains("Default branch trunk does not exist anymore"),
+            "{}",
+            stdout
+        );
+
+        // AND the default branch has been found again and stored
+        assert_eq!(
+            repo.get_config_keys(DEFAULT_BRANCH_CONFIG_KEY).unwrap(),
+            vec!["master".to_string()]
+        );
+
+        // AND the topic branch has been deleted
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn batch_guesses_default_branch() {
+        // GIVEN a repository without a default branch configured nor a remote
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path_str = dir.path().to_str().unwrap();
+        let repo = create_test_repository(dir.path());
+
+        // AND a topic branch merged into master
+        create_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic1");
+
+        // WHEN git-bonsai runs in batch mode
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch"]);
+        assert_eq!(result, 0);
+
+        // THEN master has been picked as the default branch
+        assert_eq!(
+            repo.get_config_keys(DEFAULT_BRANCH_CONFIG_KEY).unwrap(),
+            vec!["master".to_string()]
+        );
+
+        // AND the topic branch has been deleted
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn batch_without_guessable_default_branch() {
+        // GIVEN a repository without a default branch configured nor a remote, whose only branch
+        // is neither main nor master
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path_str = dir.path().to_str().unwrap();
+        let repo = create_test_repository(dir.path());
+        repo.git("branch", &["-m", "develop"]).unwrap();
+
+        // WHEN git-bonsai runs in batch mode
+        let result = run_git_bonsai(path_str, &["-y", "--no-fetch"]);
+
+        // THEN it gives up instead of asking
+        assert_eq!(result, app::EXIT_INTERRUPTED);
+        assert!(repo
+            .get_config_keys(DEFAULT_BRANCH_CONFIG_KEY)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn exclude_default_branch_check() {
+        // GIVEN a repository without a default branch configured
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path_str = dir.path().to_str().unwrap();
+        let repo = create_test_repository(dir.path());
+
+        // AND a develop branch, with a topic1 branch merged into it
+        create_branch(&repo, "develop");
+        create_branch(&repo, "topic1");
+        repo.checkout("develop").unwrap();
+        merge_branch(&repo, "topic1");
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai runs with --exclude-default-branch-check, but no --merged-into
+        let result = run_git_bonsai(
+            path_str,
+            &["-y", "--no-fetch", "--exclude-default-branch-check"],
+        );
+
+        // THEN it fails
+        assert_eq!(result, 1);
+
+        // AND no branches have been deleted
+        assert_branches_eq!(&repo, &["develop", "master", "topic1"]);
+
+        // WHEN git-bonsai runs with --exclude-default-branch-check and --merged-into
+        let result = run_git_bonsai(
+            path_str,
+            &[
+                "-y",
+                "--no-fetch",
+                "--exclude-default-branch-check",
+                "--merged-into",
+                "develop",
+                "-x",
+                "master",
+            ],
+        );
+
+        // THEN it succeeds
+        assert_eq!(result, 0);
+
+        // AND the branch merged into develop has been deleted
+        assert_branches_eq!(&repo, &["develop", "master"]);
+
+        // AND the default branch has not been stored
+        assert_eq!(
+            repo.get_config_keys(DEFAULT_BRANCH_CONFIG_KEY).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn global_config_unreadable() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        repo.create_branch("topic", None).unwrap();
+
+        // WHEN git-bonsai runs without $HOME, so that git cannot read the global config
+        let status = Command::new(env!("CARGO_BIN_EXE_git-bonsai"))
+            .current_dir(dir.path())
+            .env_remove("GB_DEBUG")
+            .env_remove("HOME")
+            .env_remove("XDG_CONFIG_HOME")
+            .args(["-y", "--no-fetch"])
+            .status()
             .unwrap();
 
-        let worktree_repo = Repository::new(worktree_dir.path());
-        worktree_repo.checkout("topic1").unwrap();
+        // THEN it succeeds
+        assert!(status.success(), "{:?}", status);
 
-        // WHEN git-bonsai updates the branches of the clone
-        // THEN it does not fail
-        let app = create_app(&clone_path_str, &[]);
-        assert_ok!(app.update_tracking_branches());
+        // AND the topic branch has been deleted
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn app_with_repository() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        repo.create_branch("topic", None).unwrap();
+
+        // AND an app working on a Repository instance
+        let args = CliArgs::from_iter(&["git-bonsai", "-y", "--no-fetch"]);
+        let mut app = App::with_repository(
+            &args,
+            &ConfigFile::default(),
+            Box::new(BatchAppUi::new(Verbosity::Normal)),
+            Box::new(Repository::new(dir.path())),
+        )
+        .unwrap();
+
+        // WHEN the app runs
+        let report = app.run().unwrap();
+
+        // THEN the topic branch has been deleted
+        assert_eq!(report.deleted, &["topic"]);
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn get_deletable_branches_with_mock_repository() {
+        // GIVEN a repository where topic1 is merged into master, and topic2 is not contained in
+        // any other branch
+        let repo = MockRepository::new(
+            "master",
+            &[("master", &[]), ("topic1", &["master"]), ("topic2", &[])],
+        );
+        let args = CliArgs::from_iter(&["git-bonsai"]);
+        let app = App::with_repository(
+            &args,
+            &ConfigFile::default(),
+            Box::new(BatchAppUi::new(Verbosity::Normal)),
+            Box::new(repo),
+        )
+        .unwrap();
+
+        // WHEN I list the deletable branches
+        let branches = app.deletable_branches().unwrap();
+
+        // THEN only topic1 is deletable, because master contains it
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].name, "topic1");
+        assert_eq!(
+            branches[0].contained_in,
+            HashSet::from(["master".to_string()])
+        );
+    }
+
+    #[test]
+    fn protect_current_git_failure() {
+        // GIVEN a repository where getting the current branch fails
+        let mut repo = MockRepository::new("master", &[("master", &[])]);
+        repo.current_branch_fails = true;
+
+        // WHEN an app runs on it with --protect-current
+        let args = CliArgs::from_iter(&["git-bonsai", "--no-fetch", "--protect-current"]);
+        let mut app = App::with_repository(
+            &args,
+            &ConfigFile::default(),
+            Box::new(BatchAppUi::new(Verbosity::Normal)),
+            Box::new(repo),
+        )
+        .unwrap();
+        let result = app.run();
+
+        // THEN the run fails with the git error, instead of panicking
+        assert!(
+            matches!(
+                result,
+                Err(RunError {
+                    error: AppError::Git(GitError::TimedOut { .. }),
+                    ..
+                })
+            ),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn current_only_git_failure() {
+        // GIVEN a repository where getting the current branch fails
+        let mut repo = MockRepository::new("master", &[("master", &[])]);
+        repo.current_branch_fails = true;
+
+        // WHEN an app runs on it with --current-only
+        let args = CliArgs::from_iter(&["git-bonsai", "--no-fetch", "--current-only"]);
+        let mut app = App::with_repository(
+            &args,
+            &ConfigFile::default(),
+            Box::new(BatchAppUi::new(Verbosity::Normal)),
+            Box::new(repo),
+        )
+        .unwrap();
+        let result = app.run();
+
+        // THEN the run fails with the git error, instead of panicking
+        assert!(
+            matches!(
+                result,
+                Err(RunError {
+                    error: AppError::Git(GitError::TimedOut { .. }),
+                    ..
+                })
+            ),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn branch_restorer_git_failure() {
+        // GIVEN a repository where getting the current branch fails
+        let mut repo = MockRepository::new("master", &[("master", &[])]);
+        repo.current_branch_fails = true;
+
+        // WHEN a branch restorer is created for it
+        let result = BranchRestorer::new(&repo);
+
+        // THEN it fails with the git error, instead of panicking
+        assert!(matches!(result, Err(GitError::TimedOut { .. })));
     }
 
     #[test]
     fn safe_delete_branch() {
         // GIVEN a repository with a test branch equals to master
         let (dir, repo) = create_repository();
-        repo.git("branch", &["test"]).unwrap();
+        repo.create_branch("test", None).unwrap();
         repo.checkout("master").unwrap();
 
         // WHEN I call safe_delete_branch
-        let app = create_app(&dir.path().to_str().unwrap(), &[]);
+        let app = create_app(dir.path().to_str().unwrap(), &[]);
         let result = app.safe_delete_branch("test");
 
         // THEN it succeeds
@@ -255,6 +3063,23 @@ mod integ {
         assert_eq!(repo.list_branches().unwrap(), &["master"]);
     }
 
+    #[test]
+    fn safe_delete_branch_contained_only_in_current_branch() {
+        // GIVEN a repository with a test branch only merged into master, the current branch
+        let (dir, repo) = create_repository();
+        create_branch(&repo, "test");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "test");
+
+        // WHEN I call safe_delete_branch
+        let app = create_app(dir.path().to_str().unwrap(), &[]);
+        let result = app.safe_delete_branch("test");
+
+        // THEN it succeeds, since master holds the commits of the test branch
+        assert_eq!(result, Ok(()));
+        assert_eq!(repo.list_branches().unwrap(), &["master"]);
+    }
+
     #[test]
     fn cant_delete_unique_branch() {
         // GIVEN a repository with a test branch containing unique content
@@ -263,7 +3088,7 @@ mod integ {
         repo.checkout("master").unwrap();
 
         // WHEN I call safe_delete_branch
-        let app = create_app(&dir.path().to_str().unwrap(), &[]);
+        let app = create_app(dir.path().to_str().unwrap(), &[]);
         let result = app.safe_delete_branch("test");
 
         // THEN it fails
@@ -273,6 +3098,440 @@ mod integ {
         assert_eq!(repo.list_branches().unwrap(), &["master", "test"]);
     }
 
+    #[test]
+    fn report_only_json() {
+        // GIVEN a repository with a merged topic branch and an unmerged one
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "merged");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "merged");
+        create_branch(&repo, "unmerged");
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai runs with --report-only --format json
+        let output = run_git_bonsai_process(path_str, &["--report-only", "--format", "json"]);
+        assert!(output.status.success());
+
+        // THEN it prints a valid JSON snapshot
+        let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(report["default_branch"], "master");
+
+        // AND the protected set includes the default branch
+        assert_eq!(
+            report["protected"],
+            serde_json::json!([{"name": "master", "reason": "default-branch"}])
+        );
+
+        // AND it lists the deletable and skipped branches
+        assert_eq!(report["deletable"][0]["name"], "merged");
+        assert_eq!(report["skipped"][0]["name"], "unmerged");
+
+        // AND no branches have been deleted
+        assert_branches_eq!(&repo, &["master", "merged", "unmerged"]);
+    }
+
+    #[test]
+    fn show_protected() {
+        // GIVEN a repository with a config file protecting topic1
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        fs::write(
+            dir.path().join(CONFIG_FILE_NAME),
+            "protected = [\"topic1\"]\n",
+        )
+        .unwrap();
+        repo.git("add", &[CONFIG_FILE_NAME]).unwrap();
+        repo.git("commit", &["-m", "Add config file"]).unwrap();
+
+        // AND three merged topic branches
+        repo.create_branch("topic1", None).unwrap();
+        repo.create_branch("topic2", None).unwrap();
+        repo.create_branch("topic3", None).unwrap();
+
+        // WHEN git-bonsai runs with --show-protected, protecting topic2 from the command line
+        let output = run_git_bonsai_process(
+            path_str,
+            &["--show-protected", "--format", "json", "-x", "topic2"],
+        );
+        assert!(output.status.success());
+
+        // THEN it prints the protected branches with their source
+        let protected: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(
+            protected,
+            serde_json::json!([
+                {"name": "master", "reason": "default-branch"},
+                {"name": "topic1", "reason": "config-file"},
+                {"name": "topic2", "reason": "command-line"},
+            ])
+        );
+
+        // AND the text output lists the same branches
+        let output = run_git_bonsai_process(path_str, &["--show-protected", "-x", "topic2"]);
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("- topic1 (.git-bonsai.toml)\n"));
+        assert!(stdout.contains("- topic2 (command line)\n"));
+
+        // AND no branches have been deleted
+        assert_branches_eq!(&repo, &["master", "topic1", "topic2", "topic3"]);
+    }
+
+    #[test]
+    fn read_only_modes_do_not_store_default_branch() {
+        for arg in &["--report-only", "--list", "--show-protected"] {
+            // GIVEN a clone of a repository, whose default branch has not been stored yet
+            let (source_dir, _source_repo) = create_repository();
+            let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+            let clone_dir_str = clone_dir.path().to_str().unwrap();
+
+            // WHEN git-bonsai runs in a read-only mode
+            let result = run_git_bonsai(clone_dir_str, &[arg]);
+            assert_eq!(result, 0);
+
+            // THEN the default branch has not been stored in the config
+            assert_eq!(
+                clone_repo.get_config_value(DEFAULT_BRANCH_CONFIG_KEY),
+                Ok(None),
+                "{}",
+                arg
+            );
+        }
+    }
+
+    #[test]
+    fn list() {
+        // GIVEN a source repository
+        let (source_dir, source_repo) = create_repository();
+
+        // AND a clone of it, with a merged topic branch and two identical unmerged branches
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        let clone_dir_str = clone_dir.path().to_str().unwrap();
+        clone_repo.git("config", &["user.name", "test"]).unwrap();
+        clone_repo
+            .git("config", &["user.email", "test@example.com"])
+            .unwrap();
+        create_branch(&clone_repo, "topic");
+        clone_repo.checkout("master").unwrap();
+        merge_branch(&clone_repo, "topic");
+        create_branch(&clone_repo, "dup1");
+        clone_repo.create_branch("dup2", None).unwrap();
+        clone_repo.checkout("master").unwrap();
+        let master_sha1 = clone_repo.rev_parse("master").unwrap();
+        let origin_master_sha1 = clone_repo.rev_parse("origin/master").unwrap();
+
+        // AND a new commit in the source repository
+        create_and_commit_file(&source_repo, "new");
+
+        // WHEN git-bonsai runs in the clone with --list
+        let output = run_git_bonsai_process(clone_dir_str, &["--list"]);
+        assert!(output.status.success());
+
+        // THEN it lists the deletable and the identical branches. Identical branches contain each
+        // other, so they are deletable too
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.ends_with(
+                "Deletable branches:\n- dup1\n- dup2\n- topic\n\
+                Identical branches:\n- dup1, dup2\n"
+            ),
+            "{}",
+            stdout
+        );
+
+        // AND no branches have been deleted
+        assert_branches_eq!(&clone_repo, &["dup1", "dup2", "master", "topic"]);
+
+        // AND nothing has been fetched nor updated
+        assert_eq!(clone_repo.rev_parse("master").unwrap(), master_sha1);
+        assert_eq!(
+            clone_repo.rev_parse("origin/master").unwrap(),
+            origin_master_sha1
+        );
+    }
+
+    #[test]
+    fn run_report() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic");
+
+        // WHEN git-bonsai runs
+        let mut app = create_app(path_str, &[]);
+        let report = app.run().unwrap();
+
+        // THEN the report lists the deleted topic branch
+        assert_eq!(report.deleted, &["topic"]);
+        assert!(report.errors.is_empty());
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn json_summary() {
+        // GIVEN a repository with a merged topic branch and an unmerged one
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "merged");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "merged");
+        create_branch(&repo, "unmerged");
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai runs with --format json
+        let output = run_git_bonsai_process(path_str, &["--format", "json"]);
+        assert!(output.status.success());
+
+        // THEN the merged branch has been deleted without asking
+        assert_branches_eq!(&repo, &["master", "unmerged"]);
+
+        // AND it prints a JSON summary of what has been done
+        let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(summary["deleted"], serde_json::json!(["merged"]));
+        assert_eq!(summary["skipped"][0]["name"], "unmerged");
+        assert_eq!(summary["errors"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn ndjson_events() {
+        // GIVEN a source repository with a branch merged into master
+        let (source_dir, source_repo) = create_repository();
+        create_branch(&source_repo, "topic1");
+        source_repo.checkout("master").unwrap();
+        merge_branch(&source_repo, "topic1");
+
+        // AND a clone of it, with a local copy of the merged branch
+        let (clone_dir, clone_repo) = clone_repository(source_dir.path().to_str().unwrap());
+        clone_repo
+            .create_branch("topic1", Some("origin/topic1"))
+            .unwrap();
+
+        // AND a new commit in the source repository
+        create_and_commit_file(&source_repo, "new");
+
+        // WHEN git-bonsai runs with --events ndjson
+        let output =
+            run_git_bonsai_process(clone_dir.path().to_str().unwrap(), &["--events", "ndjson"]);
+        assert!(output.status.success());
+
+        // THEN nothing is printed to stderr
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+
+        // AND each line of stdout is a JSON event
+        let events: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+
+        // AND the update, the deletion and the summary have been reported as they happened
+        let progress = serde_json::json!({
+            "event": "progress",
+            "current": 1,
+            "total": 2,
+            "label": "Updating master",
+        });
+        let deleting = serde_json::json!({"event": "info", "message": "Deleting topic1"});
+        assert!(events.contains(&progress), "{:?}", events);
+        assert!(events.contains(&deleting), "{:?}", events);
+        let summary = events.last().unwrap();
+        assert_eq!(summary["event"], "summary");
+        assert_eq!(summary["summary"]["deleted"], serde_json::json!(["topic1"]));
+    }
+
+    #[test]
+    fn json_summary_on_error() {
+        // GIVEN a repository with a topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic", None).unwrap();
+
+        // WHEN git-bonsai runs with --format json and an invalid --merged-into branch
+        let output = run_git_bonsai_process(
+            path_str,
+            &["--format", "json", "--merged-into", "nonexistent"],
+        );
+
+        // THEN it fails
+        assert!(!output.status.success());
+
+        // AND the JSON summary is still printed, with the error
+        let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(summary["errors"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn quiet() {
+        // GIVEN a repository with a topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        repo.create_branch("topic", None).unwrap();
+
+        // WHEN git-bonsai runs with an invalid --merged-into branch
+        let output = run_git_bonsai_process(path_str, &["-y", "--merged-into", "nonexistent"]);
+
+        // THEN it prints info and error messages
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("Info:"));
+        assert!(stdout.contains("Error:"));
+
+        // WHEN git-bonsai runs again with --quiet
+        let output =
+            run_git_bonsai_process(path_str, &["-y", "--quiet", "--merged-into", "nonexistent"]);
+
+        // THEN it only prints the error messages
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(!stdout.contains("Info:"));
+        assert!(stdout.contains("Error:"));
+    }
+
+    #[test]
+    fn explain() {
+        // GIVEN a repository with a branch containing unique commits
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "unique");
+        repo.checkout("master").unwrap();
+
+        // WHEN git-bonsai runs with --explain
+        let output = run_git_bonsai_process(path_str, &["-y", "--explain"]);
+        assert!(output.status.success());
+
+        // THEN it explains why each branch is kept
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("master kept: protected"));
+        assert!(stdout.contains("unique kept: not contained in any other branch"));
+    }
+
+    #[test]
+    fn repo_option() {
+        // GIVEN a repository with a merged topic branch
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        create_branch(&repo, "topic");
+        repo.checkout("master").unwrap();
+        merge_branch(&repo, "topic");
+
+        // AND an unrelated directory
+        let other_dir = assert_fs::TempDir::new().unwrap();
+        let other_dir_str = other_dir.path().to_str().unwrap();
+
+        // WHEN git-bonsai runs from the unrelated directory, with -C pointing to the repository
+        let output = run_git_bonsai_process(other_dir_str, &["-y", "-C", path_str]);
+        assert!(output.status.success());
+
+        // THEN the topic branch has been removed
+        assert_branches_eq!(&repo, &["master"]);
+    }
+
+    #[test]
+    fn repo_option_invalid_directory() {
+        // GIVEN a path which does not exist
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path_str = dir.path().to_str().unwrap();
+        let missing_path = dir.path().join("missing");
+
+        // WHEN git-bonsai runs with -C pointing to it
+        let output =
+            run_git_bonsai_process(path_str, &["-y", "-C", missing_path.to_str().unwrap()]);
+
+        // THEN it fails with a clear error message
+        assert_eq!(output.status.code(), Some(1));
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("is not a directory"));
+    }
+
+    #[test]
+    fn verbose() {
+        // GIVEN a repository
+        let (dir, _repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+
+        // WHEN git-bonsai runs without --verbose
+        let output = run_git_bonsai_process(path_str, &["-y"]);
+
+        // THEN it does not print git commands
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(!stderr.contains(": git branch"));
+
+        // WHEN git-bonsai runs with --verbose
+        let output = run_git_bonsai_process(path_str, &["-y", "--verbose"]);
+
+        // THEN it prints git commands on stderr
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(": git branch"));
+    }
+
+    #[test]
+    fn not_a_git_repository() {
+        // GIVEN a directory which is not a git repository
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path_str = dir.path().to_str().unwrap();
+
+        // WHEN git-bonsai runs in it
+        let output = run_git_bonsai_process(path_str, &["-y"]);
+
+        // THEN it fails with a clear error message
+        assert_eq!(output.status.code(), Some(1));
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("Not inside a git repository"));
+    }
+
+    #[test]
+    fn protected_branches_from_global_git_config() {
+        // GIVEN a repository with a merged branch, "global", and a protected one, "local"
+        let (dir, repo) = create_repository();
+        let path_str = dir.path().to_str().unwrap();
+        for branch in ["global", "local"] {
+            create_branch(&repo, branch);
+            repo.checkout("master").unwrap();
+            merge_branch(&repo, branch);
+        }
+        repo.git(
+            "config",
+            &["--add", "git-bonsai.protected-branches", "local"],
+        )
+        .unwrap();
+
+        // AND a home directory whose global git config protects "global"
+        let home_dir = assert_fs::TempDir::new().unwrap();
+        let status = Command::new("git")
+            .env("HOME", home_dir.path())
+            .args([
+                "config",
+                "--global",
+                "--add",
+                "git-bonsai.protected-branches",
+                "global",
+            ])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // WHEN git-bonsai runs with this home directory
+        let output = Command::new(env!("CARGO_BIN_EXE_git-bonsai"))
+            .current_dir(path_str)
+            .env("HOME", home_dir.path())
+            .args(["--report-only", "--format", "json"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        // THEN both branches are protected
+        let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(
+            report["protected"],
+            serde_json::json!([
+                {"name": "global", "reason": "global-git-config"},
+                {"name": "local", "reason": "git-config"},
+                {"name": "master", "reason": "default-branch"},
+            ])
+        );
+    }
+
     #[test]
     fn test_protected_branches_from_git_config() {
         // GIVEN a repository with protected branches declared in git-config
@@ -291,7 +3550,7 @@ mod integ {
         .unwrap();
 
         // WHEN app is instantiated
-        let mut app = create_app(&dir.path().to_str().unwrap(), &[]);
+        let mut app = create_app(dir.path().to_str().unwrap(), &[]);
         app.add_default_branch_to_protected_branches().unwrap();
 
         // THEN app.protected_branches contains all protected branches